@@ -0,0 +1,52 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+use chrono::{SecondsFormat, TimeZone, Utc};
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        .filter(|s| !s.is_empty())
+}
+
+/* Build information is given to the program through environment variables read by `env!`,
+ * `SOURCE_DATE_EPOCH` fixes the build date for reproducible builds.
+ */
+fn main() {
+    let git_commit = command_output("git", &["rev-parse", "HEAD"]);
+    let build_date = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .parse()
+            .ok()
+            .and_then(|epoch| Utc.timestamp_opt(epoch, 0).single()),
+        Err(_) => Some(Utc::now()),
+    };
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let rustc = command_output(&rustc, &["--version"]);
+
+    println!(
+        "cargo:rustc-env=VENT_TXT_GIT_COMMIT={}",
+        git_commit.as_deref().unwrap_or("unknown")
+    );
+    println!(
+        "cargo:rustc-env=VENT_TXT_BUILD_DATE={}",
+        build_date.map_or_else(
+            || String::from("unknown"),
+            |date| { date.to_rfc3339_opts(SecondsFormat::Secs, false) }
+        )
+    );
+    println!(
+        "cargo:rustc-env=VENT_TXT_RUSTC={}",
+        rustc.as_deref().unwrap_or("unknown")
+    );
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // Packaged sources have no repository, watching missing paths would rerun this every build
+    if Path::new(".git").exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        println!("cargo:rerun-if-changed=.git/refs");
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+use std::io::{Error as IoError, ErrorKind};
+
+use crate::{
+    display_message_id, empty_reply_error, escape_message, format_local_time, get_allow_empty,
+    get_max_chain, parse_date, parse_message_id, split_reply_marker, threads, validate_message,
+    Entry,
+};
+
+/* New messages are gathered by a builder and validated all at once by `build`, so that every way
+ * of adding or editing a message follows the same rules. The message may start with a reply marker
+ * typed by the user, `reply` takes a 0-based ID instead.
+ */
+#[derive(Default)]
+pub struct EntryBuilder {
+    message: String,
+    reply: Option<usize>,
+    date: Option<String>,
+    prompt: Option<String>,
+    tags: Vec<String>,
+    multiline: bool,
+    id: Option<usize>,
+}
+
+impl EntryBuilder {
+    pub fn new(message: &str) -> Self {
+        EntryBuilder {
+            message: message.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn reply(mut self, reply: Option<usize>) -> Self {
+        self.reply = reply;
+        self
+    }
+
+    pub fn date(mut self, date: Option<&str>) -> Self {
+        self.date = date.map(str::to_owned);
+        self
+    }
+
+    pub fn prompt(mut self, prompt: Option<&str>) -> Self {
+        self.prompt = prompt.map(str::to_owned);
+        self
+    }
+
+    /* Tags are appended to the message, which is where they are stored. */
+    pub fn tags(mut self, tags: &[&str]) -> Self {
+        self.tags.extend(tags.iter().map(|tag| tag.to_string()));
+        self
+    }
+
+    pub fn multiline(mut self, multiline: bool) -> Self {
+        self.multiline = multiline;
+        self
+    }
+
+    /* Edited messages keep their ID instead of taking the next one. */
+    pub fn id(mut self, id: usize) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /* `entries` are the messages of the database, the new message takes the next ID unless `id`
+     * is given. Replies have to point to a message before it and respect `VENT_TXT_MAX_CHAIN`.
+     */
+    pub fn build(self, entries: &[Entry]) -> Result<Entry, IoError> {
+        let mut message = self.message.trim().to_owned();
+        if self.multiline {
+            // Each line stays a line of the message but nothing else may break it
+            validate_message(escape_message(&message))?;
+        } else {
+            message = validate_message(message)?;
+        }
+
+        let mut reply = self.reply;
+        if let Some((reply_text, rest)) = split_reply_marker(&message) {
+            if reply.is_some() {
+                return Err(IoError::new(
+                    ErrorKind::InvalidInput,
+                    "Message has two replies",
+                ));
+            }
+            reply = Some(
+                parse_message_id(reply_text)
+                    .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Invalid reply ID"))?,
+            );
+            message = rest.trim_start().to_owned();
+            if message.is_empty() && !get_allow_empty() {
+                return Err(empty_reply_error());
+            }
+        }
+        let id = self.id.unwrap_or(entries.len());
+        if let Some(reply) = reply {
+            check_reply(entries, id, reply)?;
+        }
+
+        for tag in self.tags.iter() {
+            let tag = tag.strip_prefix('#').unwrap_or(tag);
+            if tag.is_empty()
+                || !tag
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            {
+                return Err(IoError::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid tag {tag}"),
+                ));
+            }
+            message.push_str(&format!(" #{}", tag.to_lowercase()));
+        }
+
+        let date = match self.date {
+            Some(date) if parse_date(&date).is_some() => date,
+            Some(_) => return Err(IoError::new(ErrorKind::InvalidInput, "Invalid date")),
+            None => format_local_time(),
+        };
+        let prompt = self
+            .prompt
+            .map(|prompt| validate_message(prompt.trim().to_owned()))
+            .transpose()?;
+
+        Ok(Entry {
+            id,
+            date,
+            reply,
+            tags: Entry::extract_tags(&message),
+            reading_time_seconds: Entry::reading_time_seconds(&message),
+            message,
+            prompt,
+            spoiler: false,
+            starred: false,
+            attachments: Vec::new(),
+            metadata: BTreeMap::new(),
+        })
+    }
+}
+
+/* Replies only point to earlier messages, which keeps threads free of cycles. The depth of a
+ * message is the number of replies leading to it from its thread's root, replies to a message
+ * already at the depth allowed by `VENT_TXT_MAX_CHAIN` are refused.
+ */
+fn check_reply(entries: &[Entry], id: usize, reply: usize) -> Result<(), IoError> {
+    if reply >= entries.len() {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "Out-of-bound reply ID",
+        ));
+    }
+    if reply == id {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "Message can't reply to itself",
+        ));
+    }
+    if reply > id {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Message {} can only reply to an earlier message",
+                display_message_id(id)
+            ),
+        ));
+    }
+    match get_max_chain() {
+        Some(max_chain) if threads::reply_ancestors(entries, reply).len() >= max_chain => {
+            Err(IoError::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Message {} is at the maximum reply depth of {max_chain} allowed by VENT_TXT_MAX_CHAIN",
+                    display_message_id(reply)
+                ),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{parse_lines, with_env};
+
+    const DATABASE: [&str; 3] = [
+        "2024-01-01 10:00:00 +0000,first",
+        "2024-01-02 10:00:00 +0000,>>0 second",
+        "2024-01-03 10:00:00 +0000,>>1 third",
+    ];
+
+    fn error(builder: EntryBuilder) -> String {
+        with_env(&[], || builder.build(&parse_lines(&DATABASE)))
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn build_takes_the_next_id() {
+        let entry = with_env(&[], || {
+            EntryBuilder::new("  >>#1 hello #World ")
+                .tags(&["#rust"])
+                .date(Some("2024-02-01 10:00:00 +0000"))
+                .build(&parse_lines(&DATABASE))
+                .unwrap()
+        });
+        assert_eq!(entry.id, 3);
+        assert_eq!(entry.reply, Some(1));
+        assert_eq!(entry.message, "hello #World #rust");
+        assert_eq!(entry.tags, ["world", "rust"]);
+        assert_eq!(entry.date, "2024-02-01 10:00:00 +0000");
+    }
+
+    #[test]
+    fn build_refuses_invalid_messages() {
+        assert_eq!(error(EntryBuilder::new(" ")), "Empty message");
+        assert_eq!(error(EntryBuilder::new(">>0")), "Empty reply");
+        assert_eq!(
+            error(EntryBuilder::new("a\nb")),
+            "Message contains new line"
+        );
+        assert_eq!(
+            error(EntryBuilder::new("a").tags(&["a b"])),
+            "Invalid tag a b"
+        );
+        assert_eq!(
+            error(EntryBuilder::new("a").date(Some("soon"))),
+            "Invalid date"
+        );
+        assert_eq!(
+            error(EntryBuilder::new(">>0 a").reply(Some(1))),
+            "Message has two replies"
+        );
+    }
+
+    #[test]
+    fn build_keeps_lines_of_multiline_messages() {
+        let entry = with_env(&[], || {
+            EntryBuilder::new("a\nb")
+                .multiline(true)
+                .build(&parse_lines(&DATABASE))
+                .unwrap()
+        });
+        assert_eq!(entry.message, "a\nb");
+    }
+
+    #[test]
+    fn build_refuses_replies_outside_of_the_database() {
+        assert_eq!(error(EntryBuilder::new(">>3 a")), "Out-of-bound reply ID");
+        assert_eq!(
+            error(EntryBuilder::new("a").reply(Some(99))),
+            "Out-of-bound reply ID"
+        );
+    }
+
+    #[test]
+    fn build_refuses_replies_to_the_message_itself_or_later_ones() {
+        assert_eq!(
+            error(EntryBuilder::new(">>1 a").id(1)),
+            "Message can't reply to itself"
+        );
+        assert_eq!(
+            error(EntryBuilder::new(">>2 a").id(0)),
+            "Message 0 can only reply to an earlier message"
+        );
+        let entry = with_env(&[], || {
+            EntryBuilder::new(">>0 a")
+                .id(2)
+                .build(&parse_lines(&DATABASE))
+                .unwrap()
+        });
+        assert_eq!((entry.id, entry.reply), (2, Some(0)));
+    }
+
+    #[test]
+    fn build_respects_the_maximum_chain() {
+        with_env(&[("VENT_TXT_MAX_CHAIN", "2")], || {
+            let entries = parse_lines(&DATABASE);
+            assert!(EntryBuilder::new(">>0 a").build(&entries).is_ok());
+            assert!(EntryBuilder::new(">>1 a").build(&entries).is_ok());
+            let error = EntryBuilder::new(">>2 a").build(&entries).unwrap_err();
+            assert!(error.to_string().contains("VENT_TXT_MAX_CHAIN"), "{error}");
+        });
+    }
+
+    #[test]
+    fn build_refuses_lone_reply_markers() {
+        for message in [">>0", ">>[0]   ", " >>#1 \t "] {
+            assert_eq!(
+                error(EntryBuilder::new(message)),
+                "Empty reply",
+                "{message:?}"
+            );
+        }
+        // Without a number, the marker is the message itself
+        let entry = with_env(&[], || {
+            EntryBuilder::new(">>").build(&parse_lines(&DATABASE))
+        });
+        assert_eq!(entry.unwrap().message, ">>");
+    }
+
+    #[test]
+    fn build_accepts_empty_messages_when_allowed() {
+        with_env(&[("VENT_TXT_ALLOW_EMPTY", "1")], || {
+            let entries = parse_lines(&DATABASE);
+            let empty = EntryBuilder::new("  ").build(&entries).unwrap();
+            assert_eq!(empty.message, "");
+            assert!(empty.to_raw_entry().unwrap().ends_with(','));
+            let reply = EntryBuilder::new(">>0").build(&entries).unwrap();
+            assert_eq!((reply.reply, reply.message.as_str()), (Some(0), ""));
+        });
+        let empty = with_env(&[("VENT_TXT_ALLOW_EMPTY", "1")], || {
+            EntryBuilder::new("").build(&[]).unwrap()
+        });
+        let error = with_env(&[], || empty.to_raw_entry()).unwrap_err();
+        assert_eq!(error.to_string(), "Empty message");
+    }
+}
@@ -0,0 +1,171 @@
+use std::io::prelude::*;
+use std::io::{Error as IoError, ErrorKind};
+
+use crate::builder::EntryBuilder;
+use crate::render;
+use crate::{
+    get_drafts_path, open_database_for_append, open_for_append, print_entry, read_entries_for_add,
+    sync_database, write_raw_entries_to, Entry,
+};
+
+/* Drafts are stored like messages in their own file. Their IDs are positions in that file which
+ * change when a draft before them is published or removed. Drafts are built like the messages
+ * they become, their replies are checked against the database when they are written and again
+ * when they are published.
+ */
+fn read_raw_drafts() -> Result<Vec<String>, IoError> {
+    match Entry::read_raw_entries_from(&get_drafts_path()) {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        drafts => drafts,
+    }
+}
+
+fn out_of_bound_error() -> IoError {
+    IoError::new(ErrorKind::InvalidInput, "Out-of-bound draft ID")
+}
+
+pub fn add(message: &str, prompt: Option<&str>) -> Result<(), IoError> {
+    let draft = EntryBuilder::new(message)
+        .prompt(prompt)
+        .build(&read_entries_for_add()?)?;
+    let mut file = open_for_append(&get_drafts_path())?;
+    writeln!(file, "{}", draft.to_new_raw_entry())?;
+    sync_database(&file)
+}
+
+pub fn list() -> Result<(), IoError> {
+    for draft in Entry::parse_raw_entries(&read_raw_drafts()?)?.iter() {
+        print_entry(draft, "");
+    }
+    Ok(())
+}
+
+/* The date of a draft is the time it was last written, its prompt is kept. */
+pub fn edit(draft_id: usize, message: &str) -> Result<(), IoError> {
+    let mut drafts = read_raw_drafts()?;
+    let draft = drafts.get_mut(draft_id).ok_or_else(out_of_bound_error)?;
+    let prompt = Entry::parse_entry(draft_id, draft)?.prompt;
+    *draft = EntryBuilder::new(message)
+        .prompt(prompt.as_deref())
+        .build(&read_entries_for_add()?)?
+        .to_new_raw_entry();
+    write_raw_entries_to(&get_drafts_path(), &drafts)
+}
+
+pub fn remove(draft_id: usize) -> Result<(), IoError> {
+    let mut drafts = read_raw_drafts()?;
+    if draft_id >= drafts.len() {
+        return Err(out_of_bound_error());
+    }
+    drafts.remove(draft_id);
+    write_raw_entries_to(&get_drafts_path(), &drafts)
+}
+
+/* The message is added before the draft is removed, an interrupted publish leaves the draft
+ * behind rather than losing it.
+ */
+pub fn publish(draft_id: usize) -> Result<(), IoError> {
+    let mut drafts = read_raw_drafts()?;
+    if draft_id >= drafts.len() {
+        return Err(out_of_bound_error());
+    }
+    let draft = Entry::parse_entry(draft_id, &drafts.remove(draft_id))?;
+    let entries = read_entries_for_add()?;
+    let mut entry = EntryBuilder::new(&draft.message)
+        .reply(draft.reply)
+        .prompt(draft.prompt.as_deref())
+        .multiline(draft.message.contains('\n'))
+        .build(&entries)?;
+    render::store_anchor(&mut entry, &entries);
+    let mut file = open_database_for_append()?;
+    writeln!(file, "{}", entry.to_new_raw_entry())?;
+    sync_database(&file)?;
+    write_raw_entries_to(&get_drafts_path(), &drafts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{temp_dir, with_env};
+    use std::fs;
+
+    fn with_database<T>(lines: &str, f: impl FnOnce() -> T) -> T {
+        let directory = temp_dir("drafts");
+        let csv = directory.join("vent.csv");
+        fs::write(&csv, lines).unwrap();
+        let drafts = directory.join("drafts.csv");
+        with_env(
+            &[
+                ("VENT_TXT_CSV", csv.to_str().unwrap()),
+                ("VENT_TXT_DRAFTS", drafts.to_str().unwrap()),
+            ],
+            f,
+        )
+    }
+
+    const DATABASE: &str = "2024-01-01 10:00:00 +0000,first\n";
+
+    #[test]
+    fn add_refuses_replies_outside_of_the_database() {
+        with_database(DATABASE, || {
+            let error = add(">>42 x", None).unwrap_err();
+            assert_eq!(error.to_string(), "Out-of-bound reply ID");
+            assert!(read_raw_drafts().unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn edit_keeps_the_prompt() {
+        with_database(DATABASE, || {
+            add("draft", Some("why")).unwrap();
+            assert!(edit(0, ">>1 x").is_err());
+            edit(0, ">>0 edited").unwrap();
+            let draft = Entry::parse_entry(0, &read_raw_drafts().unwrap()[0]).unwrap();
+            assert_eq!(draft.reply, Some(0));
+            assert_eq!(draft.message, "edited");
+            assert_eq!(draft.prompt.as_deref(), Some("why"));
+        });
+    }
+
+    #[test]
+    fn publish_moves_the_draft_to_the_database() {
+        with_database(DATABASE, || {
+            add("a", None).unwrap();
+            add(">>0 b", None).unwrap();
+            publish(1).unwrap();
+            let entries = Entry::read_entries().unwrap();
+            assert_eq!(entries.len(), 2);
+            assert_eq!(
+                (entries[1].reply, entries[1].message.as_str()),
+                (Some(0), "b")
+            );
+            assert_eq!(read_raw_drafts().unwrap().len(), 1);
+            assert!(publish(1).is_err());
+        });
+    }
+
+    #[test]
+    fn publish_checks_replies_again() {
+        with_database(DATABASE, || {
+            add(">>0 reply", None).unwrap();
+            fs::write(crate::get_csv_path(), "").unwrap();
+            let error = publish(0).unwrap_err();
+            assert_eq!(error.to_string(), "Out-of-bound reply ID");
+            assert_eq!(read_raw_drafts().unwrap().len(), 1);
+        });
+    }
+
+    #[test]
+    fn remove_shifts_the_following_drafts() {
+        with_database(DATABASE, || {
+            assert_eq!(remove(0).unwrap_err().to_string(), "Out-of-bound draft ID");
+            add("a", None).unwrap();
+            add("b", None).unwrap();
+            remove(0).unwrap();
+            let drafts = Entry::parse_raw_entries(&read_raw_drafts().unwrap()).unwrap();
+            assert_eq!(drafts.len(), 1);
+            assert_eq!((drafts[0].id, drafts[0].message.as_str()), (0, "b"));
+            assert_eq!(Entry::read_entries().unwrap().len(), 1);
+        });
+    }
+}
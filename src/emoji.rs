@@ -0,0 +1,155 @@
+/* Shortcodes are the names used by GitHub and Slack, sorted so that they can be binary searched. */
+const SHORTCODES: [(&str, &str); 96] = [
+    ("+1", "\u{1F44D}"),
+    ("-1", "\u{1F44E}"),
+    ("angry", "\u{1F620}"),
+    ("anguished", "\u{1F627}"),
+    ("astonished", "\u{1F632}"),
+    ("blush", "\u{1F60A}"),
+    ("broken_heart", "\u{1F494}"),
+    ("bug", "\u{1F41B}"),
+    ("cake", "\u{1F370}"),
+    ("cat", "\u{1F431}"),
+    ("check", "\u{2714}\u{FE0F}"),
+    ("clap", "\u{1F44F}"),
+    ("cloud", "\u{2601}\u{FE0F}"),
+    ("coffee", "\u{2615}"),
+    ("cold_sweat", "\u{1F630}"),
+    ("confused", "\u{1F615}"),
+    ("cry", "\u{1F622}"),
+    ("disappointed", "\u{1F61E}"),
+    ("dizzy_face", "\u{1F635}"),
+    ("dog", "\u{1F436}"),
+    ("expressionless", "\u{1F611}"),
+    ("eyes", "\u{1F440}"),
+    ("fearful", "\u{1F628}"),
+    ("fire", "\u{1F525}"),
+    ("flushed", "\u{1F633}"),
+    ("frowning", "\u{1F626}"),
+    ("ghost", "\u{1F47B}"),
+    ("grimacing", "\u{1F62C}"),
+    ("grin", "\u{1F601}"),
+    ("grinning", "\u{1F600}"),
+    ("heart", "\u{2764}\u{FE0F}"),
+    ("heart_eyes", "\u{1F60D}"),
+    ("hugs", "\u{1F917}"),
+    ("hushed", "\u{1F62F}"),
+    ("innocent", "\u{1F607}"),
+    ("joy", "\u{1F602}"),
+    ("kissing", "\u{1F617}"),
+    ("kissing_heart", "\u{1F618}"),
+    ("laughing", "\u{1F606}"),
+    ("mask", "\u{1F637}"),
+    ("moon", "\u{1F319}"),
+    ("muscle", "\u{1F4AA}"),
+    ("neutral_face", "\u{1F610}"),
+    ("no_mouth", "\u{1F636}"),
+    ("ok_hand", "\u{1F44C}"),
+    ("open_mouth", "\u{1F62E}"),
+    ("pensive", "\u{1F614}"),
+    ("persevere", "\u{1F623}"),
+    ("pill", "\u{1F48A}"),
+    ("pizza", "\u{1F355}"),
+    ("point_down", "\u{1F447}"),
+    ("point_left", "\u{1F448}"),
+    ("point_right", "\u{1F449}"),
+    ("point_up", "\u{261D}\u{FE0F}"),
+    ("pray", "\u{1F64F}"),
+    ("rage", "\u{1F621}"),
+    ("rain_cloud", "\u{1F327}\u{FE0F}"),
+    ("raised_hands", "\u{1F64C}"),
+    ("relaxed", "\u{263A}\u{FE0F}"),
+    ("relieved", "\u{1F60C}"),
+    ("rocket", "\u{1F680}"),
+    ("rofl", "\u{1F923}"),
+    ("scream", "\u{1F631}"),
+    ("see_no_evil", "\u{1F648}"),
+    ("shrug", "\u{1F937}"),
+    ("skull", "\u{1F480}"),
+    ("sleeping", "\u{1F634}"),
+    ("sleepy", "\u{1F62A}"),
+    ("slightly_frowning_face", "\u{1F641}"),
+    ("slightly_smiling_face", "\u{1F642}"),
+    ("smile", "\u{1F604}"),
+    ("smiley", "\u{1F603}"),
+    ("smirk", "\u{1F60F}"),
+    ("sob", "\u{1F62D}"),
+    ("sparkles", "\u{2728}"),
+    ("star", "\u{2B50}"),
+    ("stuck_out_tongue", "\u{1F61B}"),
+    ("sunglasses", "\u{1F60E}"),
+    ("sunny", "\u{2600}\u{FE0F}"),
+    ("sweat", "\u{1F613}"),
+    ("sweat_smile", "\u{1F605}"),
+    ("tada", "\u{1F389}"),
+    ("thinking", "\u{1F914}"),
+    ("thumbsdown", "\u{1F44E}"),
+    ("thumbsup", "\u{1F44D}"),
+    ("tired_face", "\u{1F62B}"),
+    ("triumph", "\u{1F624}"),
+    ("unamused", "\u{1F612}"),
+    ("upside_down_face", "\u{1F643}"),
+    ("warning", "\u{26A0}\u{FE0F}"),
+    ("wave", "\u{1F44B}"),
+    ("weary", "\u{1F629}"),
+    ("wink", "\u{1F609}"),
+    ("worried", "\u{1F61F}"),
+    ("yum", "\u{1F60B}"),
+    ("zzz", "\u{1F4A4}"),
+];
+
+fn is_shortcode_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '+' | '-')
+}
+
+/* Shortcodes are replaced by their emoji, unknown ones are kept as written. The colon closing an
+ * unknown shortcode may open the next one, e.g. in `:not:smile:`.
+ */
+pub fn expand_shortcodes(text: &str) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(':') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let name_end = after.find(|c| !is_shortcode_char(c)).unwrap_or(after.len());
+        let emoji = after[name_end..]
+            .starts_with(':')
+            .then(|| SHORTCODES.binary_search_by_key(&&after[..name_end], |&(name, _)| name))
+            .and_then(Result::ok);
+        match emoji {
+            Some(index) => {
+                expanded.push_str(SHORTCODES[index].1);
+                rest = &after[name_end + 1..];
+            }
+            None => {
+                expanded.push(':');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortcodes_are_sorted() {
+        assert!(SHORTCODES.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(SHORTCODES
+            .iter()
+            .all(|(name, _)| name.chars().all(is_shortcode_char)));
+    }
+
+    #[test]
+    fn known_shortcodes_are_expanded() {
+        assert_eq!(expand_shortcodes("hi :wave:!"), "hi \u{1F44B}!");
+        assert_eq!(expand_shortcodes(":+1::-1:"), "\u{1F44D}\u{1F44E}");
+        assert_eq!(expand_shortcodes(":not:zzz:"), ":not\u{1F4A4}");
+        for text in ["10:30", ":Wave:", ":wave", "::", ":", "a:b c:d"] {
+            assert_eq!(expand_shortcodes(text), text);
+        }
+    }
+}
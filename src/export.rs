@@ -0,0 +1,317 @@
+use std::fs;
+use std::io::{self, Error as IoError, ErrorKind};
+use std::path::Path;
+
+use crate::sqlite::{self, Database};
+use crate::{create_file, file_options, Entry};
+
+const SLUG_LENGTH: usize = 50;
+
+/* Schema of the SQL dump and of SQLite exports, tags are normalized in their own table. */
+const ENTRIES_TABLE: &str = "CREATE TABLE entries (id INTEGER PRIMARY KEY, date TEXT NOT NULL, reply INTEGER, message TEXT NOT NULL, prompt TEXT)";
+const TAGS_TABLE: &str =
+    "CREATE TABLE tags (entry_id INTEGER NOT NULL REFERENCES entries(id), tag TEXT NOT NULL)";
+
+pub fn json<W>(mut writer: W, entries: &[Entry]) -> Result<(), IoError>
+where
+    W: io::Write,
+{
+    serde_json::to_writer_pretty(&mut writer, entries)?;
+    writeln!(writer)
+}
+
+/* Newline-delimited JSON holds one message per line, which `vent import --format jsonl` reads
+ * back.
+ */
+pub fn ndjson<W>(mut writer: W, entries: &[Entry]) -> Result<(), IoError>
+where
+    W: io::Write,
+{
+    for entry in entries.iter() {
+        serde_json::to_writer(&mut writer, entry)?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/* The canonical database has every line formatted like vent writes it, with its metadata in the
+ * same order, escaped the same way and with the configured reply storage. Lines are neither
+ * added nor removed so that IDs are unchanged.
+ */
+pub fn csv<W>(mut writer: W, entries: &[Entry]) -> Result<(), IoError>
+where
+    W: io::Write,
+{
+    for entry in entries.iter() {
+        let raw_entry = entry.to_raw_entry().map_err(|error| {
+            IoError::new(error.kind(), format!("Line {}: {error}", entry.id + 1))
+        })?;
+        writeln!(writer, "{raw_entry}")?;
+    }
+    Ok(())
+}
+
+fn sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn sql_optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(|| String::from("NULL"), |v| v.to_string())
+}
+
+/* The dump is a plain SQL script meant to be piped into a fresh database, e.g.
+ * `vent export --format sql | sqlite3 vent.db`, tags are normalized in their own table.
+ */
+pub fn sql<W>(mut writer: W, entries: &[Entry]) -> Result<(), IoError>
+where
+    W: io::Write,
+{
+    writeln!(writer, "BEGIN TRANSACTION;")?;
+    writeln!(writer, "{ENTRIES_TABLE};")?;
+    writeln!(writer, "{TAGS_TABLE};")?;
+    for entry in entries.iter() {
+        writeln!(
+            writer,
+            "INSERT INTO entries VALUES ({}, {}, {}, {}, {});",
+            entry.id,
+            sql_string(&entry.date),
+            sql_optional(entry.reply),
+            sql_string(&entry.message),
+            sql_optional(entry.prompt.as_deref().map(sql_string)),
+        )?;
+        for tag in entry.tags.iter() {
+            writeln!(
+                writer,
+                "INSERT INTO tags VALUES ({}, {});",
+                entry.id,
+                sql_string(tag)
+            )?;
+        }
+    }
+    writeln!(writer, "COMMIT;")?;
+    Ok(())
+}
+
+/* Same tables as the SQL dump, written as a fresh SQLite database without needing SQLite. An
+ * existing file is never overwritten. Rows are stored by ID, which must be unique.
+ */
+pub fn sqlite(path: &Path, entries: &[Entry]) -> Result<(), IoError> {
+    let mut entries = entries.iter().collect::<Vec<&Entry>>();
+    entries.sort_by_key(|e| e.id);
+    if let Some(pair) = entries.windows(2).find(|pair| pair[0].id == pair[1].id) {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!("Message ID {} is exported more than once", pair[0].id),
+        ));
+    }
+    let text = |text: &str| sqlite::Value::Text(text.to_owned());
+    let integer = |id: usize| sqlite::Value::Integer(id as i64);
+    let rows = entries
+        .iter()
+        .map(|entry| {
+            let values = vec![
+                sqlite::Value::Null,
+                text(&entry.date),
+                entry.reply.map_or(sqlite::Value::Null, integer),
+                text(&entry.message),
+                entry.prompt.as_deref().map_or(sqlite::Value::Null, text),
+            ];
+            (entry.id as i64, values)
+        })
+        .collect::<Vec<_>>();
+    let tags = entries
+        .iter()
+        .flat_map(|entry| {
+            entry
+                .tags
+                .iter()
+                .map(|tag| vec![integer(entry.id), text(tag)])
+        })
+        .enumerate()
+        .map(|(i, values)| (i as i64 + 1, values))
+        .collect::<Vec<_>>();
+
+    let mut database = Database::default();
+    database.create_table("entries", ENTRIES_TABLE, &rows)?;
+    database.create_table("tags", TAGS_TABLE, &tags)?;
+    let file = file_options()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|error| match error.kind() {
+            ErrorKind::AlreadyExists => IoError::new(
+                ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ),
+            _ => error,
+        })?;
+    database.write(io::BufWriter::new(file))
+}
+
+/* Slugs keep the lowercase alphanumeric characters of the message, any run of other characters
+ * becomes a single dash.
+ */
+fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        if slug.chars().count() >= SLUG_LENGTH {
+            break;
+        }
+    }
+    slug.trim_end_matches('-').to_owned()
+}
+
+/* Each message is written to `{id}-{slug}.md` with a YAML front matter, strings are written as JSON
+ * strings which YAML reads as double-quoted scalars.
+ */
+pub fn markdown_front_matter(output_dir: &Path, entries: &[Entry]) -> Result<(), IoError> {
+    fs::create_dir_all(output_dir)?;
+    for entry in entries.iter() {
+        let slug = slug(&entry.message);
+        let file_name = if slug.is_empty() {
+            format!("{}.md", entry.id)
+        } else {
+            format!("{}-{slug}.md", entry.id)
+        };
+        let date =
+            crate::parse_date(&entry.date).map_or_else(|| entry.date.clone(), |d| d.to_rfc3339());
+
+        let mut document = String::from("---\n");
+        document.push_str(&format!("date: {}\n", serde_json::to_string(&date)?));
+        document.push_str(&format!("id: {}\n", entry.id));
+        document.push_str(&format!("tags: {}\n", serde_json::to_string(&entry.tags)?));
+        if let Some(reply) = entry.reply {
+            document.push_str(&format!("reply: {reply}\n"));
+        }
+        document.push_str("---\n\n");
+        document.push_str(&entry.message);
+        document.push('\n');
+        io::Write::write_all(
+            &mut create_file(output_dir.join(file_name))?,
+            document.as_bytes(),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlite::tests::Reader;
+    use crate::sqlite::Value;
+    use crate::tests::{parse_lines, temp_dir, with_env};
+
+    #[test]
+    fn sqlite_exports_read_back() {
+        let path = temp_dir("sqlite").join("vent.db");
+        with_env(&[], || {
+            let mut entries = parse_lines(&[
+                "2024-01-01 10:00:00 +0000,first #rust #til",
+                "2024-01-02 10:00:00 +0000;prompt=How are you?,second",
+                "2024-01-03 10:00:00 +0000,>>0 third #rust",
+            ]);
+            entries.reverse();
+            sqlite(&path, &entries).unwrap();
+            let error = sqlite(&path, &entries).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+        });
+
+        let file = fs::read(&path).unwrap();
+        let reader = Reader { file: &file };
+        assert_eq!(reader.count("entries"), 3);
+        assert_eq!(reader.count("tags"), 3);
+        let rows = reader.table("entries");
+        assert_eq!(
+            rows[1],
+            (
+                1,
+                vec![
+                    Value::Null,
+                    Value::Text(String::from("2024-01-02 10:00:00 +0000")),
+                    Value::Null,
+                    Value::Text(String::from("second")),
+                    Value::Text(String::from("How are you?")),
+                ]
+            )
+        );
+        assert_eq!(rows[2].1[2], Value::Integer(0));
+        let tags = reader
+            .table("tags")
+            .into_iter()
+            .map(|(_, values)| values)
+            .collect::<Vec<_>>();
+        let tag = |id, tag: &str| vec![Value::Integer(id), Value::Text(tag.to_owned())];
+        assert_eq!(tags, [tag(0, "rust"), tag(0, "til"), tag(2, "rust")]);
+    }
+
+    #[test]
+    fn json_exports_sort_the_metadata() {
+        let export = |line: &str| {
+            with_env(&[], || {
+                let mut json = Vec::new();
+                ndjson(&mut json, &parse_lines(&[line])).unwrap();
+                String::from_utf8(json).unwrap()
+            })
+        };
+        let sorted = export("2024-01-01 10:00:00 +0000;zeta=1;alpha=2;mid=3,message");
+        assert_eq!(
+            sorted,
+            export("2024-01-01 10:00:00 +0000;mid=3;zeta=1;alpha=2,message")
+        );
+        assert!(sorted.contains(r#""metadata":{"alpha":"2","mid":"3","zeta":"1"}"#));
+    }
+
+    #[test]
+    fn markdown_files_get_a_front_matter() {
+        let directory = temp_dir("markdown").join("posts");
+        with_env(&[], || {
+            let entries = parse_lines(&[
+                "2024-01-01 10:00:00 +0000,Hello, \"World\"! #rust",
+                "2024-01-02 10:00:00 +0000,>>0 !!!",
+            ]);
+            markdown_front_matter(&directory, &entries).unwrap();
+        });
+        assert_eq!(
+            fs::read_to_string(directory.join("0-hello-world-rust.md")).unwrap(),
+            "---\ndate: \"2024-01-01T10:00:00+00:00\"\nid: 0\ntags: [\"rust\"]\n---\n\nHello, \"World\"! #rust\n"
+        );
+        assert_eq!(
+            fs::read_to_string(directory.join("1.md")).unwrap(),
+            "---\ndate: \"2024-01-02T10:00:00+00:00\"\nid: 1\ntags: []\nreply: 0\n---\n\n!!!\n"
+        );
+        assert!(slug(&"word ".repeat(50)).chars().count() <= SLUG_LENGTH);
+        assert!(!slug(&"word ".repeat(50)).ends_with('-'));
+    }
+
+    #[test]
+    fn csv_exports_rewrite_lines_canonically() {
+        let canonical = |vars: &[(&str, &str)], lines: &[&str]| {
+            with_env(vars, || {
+                let mut csv = Vec::new();
+                super::csv(&mut csv, &parse_lines(lines)).unwrap();
+                String::from_utf8(csv).unwrap()
+            })
+        };
+        let lines = [
+            "2024-01-01 10:00:00 +0000;zeta=1;prompt=why,first",
+            "2024-01-02 10:00:00 +0000;reply=0,second",
+        ];
+        let expected = canonical(&[], &lines);
+        assert_eq!(
+            expected,
+            "2024-01-01 10:00:00 +0000;prompt=why;zeta=1,first\n\
+             2024-01-02 10:00:00 +0000,>>0 second\n"
+        );
+        assert_eq!(
+            canonical(&[], &expected.lines().collect::<Vec<&str>>()),
+            expected
+        );
+        let metadata = canonical(&[("VENT_TXT_REPLY_STORAGE", "metadata")], &lines);
+        assert!(metadata.ends_with(";reply=0,second\n"), "{metadata}");
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::io::{self, Error as IoError};
+
+use crate::{get_document_url, parse_date, truncate_text, Entry};
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn format_feed_date(date: &str) -> String {
+    parse_date(date)
+        .unwrap_or_default()
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+}
+
+/* Without a document URL set in `VENT_TXT_URL` entries can't link back to the rendered
+ * document, they are identified with URNs instead.
+ */
+fn entry_id(document_url: Option<&str>, anchor: &str) -> String {
+    match document_url {
+        Some(url) => format!("{url}#{anchor}"),
+        None => format!("urn:vent-txt:{anchor}"),
+    }
+}
+
+pub fn atom<W>(
+    mut writer: W,
+    entries: &[Entry],
+    anchors: &BTreeMap<usize, String>,
+) -> Result<(), IoError>
+where
+    W: io::Write,
+{
+    let document_url = get_document_url();
+    let updated = entries
+        .iter()
+        .filter_map(|e| parse_date(&e.date))
+        .max()
+        .unwrap_or_default()
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, false);
+
+    writeln!(writer, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+    writeln!(writer, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#)?;
+    writeln!(writer, "\t<title>vent.txt</title>")?;
+    match &document_url {
+        Some(url) => {
+            writeln!(writer, "\t<id>{}</id>", escape_xml(url))?;
+            writeln!(writer, "\t<link href=\"{}\"/>", escape_xml(url))?;
+        }
+        None => writeln!(writer, "\t<id>urn:vent-txt</id>")?,
+    }
+    writeln!(writer, "\t<updated>{updated}</updated>")?;
+    writeln!(writer, "\t<author><name>vent.txt</name></author>")?;
+    for entry in entries.iter().rev() {
+        let id = entry_id(document_url.as_deref(), &anchors[&entry.id]);
+        writeln!(writer, "\t<entry>")?;
+        writeln!(writer, "\t\t<id>{}</id>", escape_xml(&id))?;
+        if document_url.is_some() {
+            writeln!(writer, "\t\t<link href=\"{}\"/>", escape_xml(&id))?;
+        }
+        writeln!(
+            writer,
+            "\t\t<title>{}</title>",
+            escape_xml(&truncate_text(entry.message.trim(), 50))
+        )?;
+        writeln!(
+            writer,
+            "\t\t<updated>{}</updated>",
+            format_feed_date(&entry.date)
+        )?;
+        for tag in entry.tags.iter() {
+            writeln!(writer, "\t\t<category term=\"{}\"/>", escape_xml(tag))?;
+        }
+        let reply = entry.reply.map(|r| format!(">>{r} ")).unwrap_or_default();
+        writeln!(
+            writer,
+            "\t\t<content type=\"text\">{}</content>",
+            escape_xml(&format!("{reply}{}", entry.message.trim_start()))
+        )?;
+        writeln!(writer, "\t</entry>")?;
+    }
+    writeln!(writer, "</feed>")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{parse_lines, with_env};
+
+    fn entries() -> Vec<Entry> {
+        let lines = (0..10)
+            .map(|i| {
+                format!(
+                    "2024-01-{:02} 10:00:00 +0000,message {i} <&> #tag{i}",
+                    i + 1
+                )
+            })
+            .collect::<Vec<String>>();
+        parse_lines(&lines.iter().map(String::as_str).collect::<Vec<&str>>())
+    }
+
+    fn anchors(entries: &[Entry]) -> BTreeMap<usize, String> {
+        entries.iter().map(|e| (e.id, e.id.to_string())).collect()
+    }
+
+    #[test]
+    fn feeds_are_escaped_newest_first() {
+        let feed = with_env(&[("VENT_TXT_URL", "https://example.com/vent.html")], || {
+            let entries = entries();
+            let mut feed = Vec::new();
+            atom(&mut feed, &entries, &anchors(&entries)).unwrap();
+            String::from_utf8(feed).unwrap()
+        });
+        assert!(feed.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed"));
+        assert!(feed.ends_with("</feed>\n"));
+        assert_eq!(feed.matches("<entry>").count(), 10);
+        assert!(feed.contains("<updated>2024-01-10T10:00:00+00:00</updated>"));
+        assert!(feed.contains("<id>https://example.com/vent.html#9</id>"));
+        assert!(feed.contains("<category term=\"tag3\"/>"));
+        assert!(feed.contains("message 3 &lt;&amp;&gt; #tag3</content>"));
+        assert!(feed.find("message 9").unwrap() < feed.find("message 0").unwrap());
+        assert!(!feed.contains("<&>"));
+    }
+}
@@ -0,0 +1,526 @@
+use std::cmp::Ordering;
+use std::io::{Error as IoError, ErrorKind};
+
+use crate::tokens::{tokenize_line, Cursor, Syntax, Token};
+use crate::{display_message_id, Entry};
+
+/* Filters are conditions over the fields of a message, e.g.
+ * `len(message) > 100 && has_tag("rust")`. They are type checked when parsed so that evaluating
+ * one against a message can't fail.
+ */
+pub struct Filter {
+    expression: Expression,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Type {
+    Bool,
+    Int,
+    Str,
+}
+
+impl Type {
+    fn name(self) -> &'static str {
+        match self {
+            Type::Bool => "a condition",
+            Type::Int => "a number",
+            Type::Str => "a string",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Field {
+    Id,
+    Message,
+    Date,
+    Prompt,
+    IsReply,
+    Starred,
+    Sensitive,
+    ReadingTime,
+}
+
+const FIELDS: [(&str, Field, Type); 8] = [
+    ("id", Field::Id, Type::Int),
+    ("message", Field::Message, Type::Str),
+    ("date", Field::Date, Type::Str),
+    ("prompt", Field::Prompt, Type::Str),
+    ("is_reply", Field::IsReply, Type::Bool),
+    ("starred", Field::Starred, Type::Bool),
+    ("sensitive", Field::Sensitive, Type::Bool),
+    ("reading_time", Field::ReadingTime, Type::Int),
+];
+
+#[derive(Clone, Copy)]
+enum Function {
+    Len,
+    Lower,
+    HasTag,
+    Contains,
+    StartsWith,
+}
+
+const FUNCTIONS: [(&str, Function, &[Type], Type); 5] = [
+    ("len", Function::Len, &[Type::Str], Type::Int),
+    ("lower", Function::Lower, &[Type::Str], Type::Str),
+    ("has_tag", Function::HasTag, &[Type::Str], Type::Bool),
+    (
+        "contains",
+        Function::Contains,
+        &[Type::Str, Type::Str],
+        Type::Bool,
+    ),
+    (
+        "starts_with",
+        Function::StartsWith,
+        &[Type::Str, Type::Str],
+        Type::Bool,
+    ),
+];
+
+#[derive(Clone, Copy)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    ("<=", Comparison::LessOrEqual),
+    (">=", Comparison::GreaterOrEqual),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+];
+
+impl Comparison {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Equal => ordering.is_eq(),
+            Comparison::NotEqual => ordering.is_ne(),
+            Comparison::Less => ordering.is_lt(),
+            Comparison::LessOrEqual => ordering.is_le(),
+            Comparison::Greater => ordering.is_gt(),
+            Comparison::GreaterOrEqual => ordering.is_ge(),
+        }
+    }
+}
+
+enum Expression {
+    Value(Value),
+    Field(Field),
+    Not(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Compare(Comparison, Box<Expression>, Box<Expression>),
+    Call(Function, Vec<Expression>),
+}
+
+#[derive(Clone, PartialEq, PartialOrd)]
+enum Value {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl Value {
+    fn as_bool(&self) -> bool {
+        matches!(self, Value::Bool(true))
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Value::Str(s) => s,
+            _ => "",
+        }
+    }
+}
+
+impl Expression {
+    fn evaluate(&self, entry: &Entry) -> Value {
+        match self {
+            Expression::Value(value) => value.clone(),
+            Expression::Field(field) => match field {
+                Field::Id => Value::Int(display_message_id(entry.id) as i64),
+                Field::Message => Value::Str(entry.message.clone()),
+                Field::Date => Value::Str(entry.date.clone()),
+                Field::Prompt => Value::Str(entry.prompt.clone().unwrap_or_default()),
+                Field::IsReply => Value::Bool(entry.reply.is_some()),
+                Field::Starred => Value::Bool(entry.starred),
+                Field::Sensitive => Value::Bool(entry.spoiler),
+                Field::ReadingTime => Value::Int(entry.reading_time_seconds as i64),
+            },
+            Expression::Not(operand) => Value::Bool(!operand.evaluate(entry).as_bool()),
+            Expression::And(left, right) => {
+                Value::Bool(left.evaluate(entry).as_bool() && right.evaluate(entry).as_bool())
+            }
+            Expression::Or(left, right) => {
+                Value::Bool(left.evaluate(entry).as_bool() || right.evaluate(entry).as_bool())
+            }
+            Expression::Compare(comparison, left, right) => {
+                let ordering = left
+                    .evaluate(entry)
+                    .partial_cmp(&right.evaluate(entry))
+                    .unwrap_or(Ordering::Equal);
+                Value::Bool(comparison.holds(ordering))
+            }
+            Expression::Call(function, arguments) => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| argument.evaluate(entry))
+                    .collect::<Vec<Value>>();
+                let text = |i: usize| arguments[i].as_str();
+                match function {
+                    Function::Len => Value::Int(text(0).chars().count() as i64),
+                    Function::Lower => Value::Str(text(0).to_lowercase()),
+                    Function::HasTag => {
+                        let tag = text(0).trim_start_matches('#').to_lowercase();
+                        Value::Bool(entry.tags.contains(&tag))
+                    }
+                    Function::Contains => Value::Bool(text(0).contains(text(1))),
+                    Function::StartsWith => Value::Bool(text(0).starts_with(text(1))),
+                }
+            }
+        }
+    }
+}
+
+const OPERATORS: [&str; 12] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "(", ")", ",",
+];
+
+fn error(column: usize, message: &str) -> IoError {
+    IoError::new(
+        ErrorKind::InvalidInput,
+        format!("Invalid filter at column {column}: {message}"),
+    )
+}
+
+/* Filters are a single line, a new line in one is only whitespace. */
+const SYNTAX: Syntax = Syntax {
+    operators: &OPERATORS,
+    comments: false,
+    newlines: false,
+    error: |(_, column), message| error(column, message),
+};
+
+/* Parsing and evaluating recurse once per nesting level, deeper filters are refused instead of
+ * overflowing the stack.
+ */
+const MAX_DEPTH: usize = 128;
+
+/* The grammar, from the loosest operator to the tightest, is:
+ *   or         = and ("||" and)*
+ *   and        = not ("&&" not)*
+ *   not        = "!" not | comparison
+ *   comparison = primary (("==" | "!=" | "<" | "<=" | ">" | ">=") primary)?
+ *   primary    = number | string | "true" | "false" | field | function "(" arguments ")"
+ *              | "(" or ")"
+ */
+struct Parser {
+    cursor: Cursor,
+    depth: usize,
+}
+
+impl Parser {
+    fn column(&self) -> usize {
+        self.cursor.location().1
+    }
+
+    fn typed(
+        &mut self,
+        expected: Type,
+        parse: fn(&mut Self) -> Result<(Expression, Type), IoError>,
+    ) -> Result<Expression, IoError> {
+        let column = self.column();
+        let (expression, found) = parse(self)?;
+        match found == expected {
+            true => Ok(expression),
+            false => Err(error(
+                column,
+                &format!("expected {}, found {}", expected.name(), found.name()),
+            )),
+        }
+    }
+
+    fn deepen(&mut self) -> Result<(), IoError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(error(self.column(), "nested too deeply"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<(Expression, Type), IoError>,
+    ) -> Result<(Expression, Type), IoError> {
+        self.deepen()?;
+        let parsed = parse(self)?;
+        self.depth -= 1;
+        Ok(parsed)
+    }
+
+    fn or(&mut self) -> Result<(Expression, Type), IoError> {
+        let (mut expression, found) = self.and()?;
+        if !self.cursor.peek("||") {
+            return Ok((expression, found));
+        }
+        if found != Type::Bool {
+            return Err(error(self.column(), "'||' needs conditions"));
+        }
+        let depth = self.depth;
+        while self.cursor.accept("||") {
+            self.deepen()?;
+            let right = self.typed(Type::Bool, Self::and)?;
+            expression = Expression::Or(Box::new(expression), Box::new(right));
+        }
+        self.depth = depth;
+        Ok((expression, Type::Bool))
+    }
+
+    fn and(&mut self) -> Result<(Expression, Type), IoError> {
+        let (mut expression, found) = self.not()?;
+        if !self.cursor.peek("&&") {
+            return Ok((expression, found));
+        }
+        if found != Type::Bool {
+            return Err(error(self.column(), "'&&' needs conditions"));
+        }
+        let depth = self.depth;
+        while self.cursor.accept("&&") {
+            self.deepen()?;
+            let right = self.typed(Type::Bool, Self::not)?;
+            expression = Expression::And(Box::new(expression), Box::new(right));
+        }
+        self.depth = depth;
+        Ok((expression, Type::Bool))
+    }
+
+    fn not(&mut self) -> Result<(Expression, Type), IoError> {
+        if self.cursor.accept("!") {
+            let operand = self.typed(Type::Bool, |parser| parser.nested(Self::not))?;
+            return Ok((Expression::Not(Box::new(operand)), Type::Bool));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<(Expression, Type), IoError> {
+        let (left, left_type) = self.primary()?;
+        let comparison = COMPARISONS
+            .iter()
+            .find(|(operator, _)| self.cursor.accept(operator));
+        let Some(&(operator, comparison)) = comparison else {
+            return Ok((left, left_type));
+        };
+        let ordered = !matches!(comparison, Comparison::Equal | Comparison::NotEqual);
+        if ordered && left_type == Type::Bool {
+            return Err(error(
+                self.column(),
+                &format!("'{operator}' needs numbers or strings"),
+            ));
+        }
+        let right = self.typed(left_type, Self::primary)?;
+        Ok((
+            Expression::Compare(comparison, Box::new(left), Box::new(right)),
+            Type::Bool,
+        ))
+    }
+
+    fn primary(&mut self) -> Result<(Expression, Type), IoError> {
+        let column = self.column();
+        match self.cursor.next() {
+            Some(Token::Int(number)) => Ok((Expression::Value(Value::Int(number)), Type::Int)),
+            Some(Token::Str(string)) => Ok((Expression::Value(Value::Str(string)), Type::Str)),
+            Some(Token::Operator("(")) => {
+                let inner = self.nested(Self::or)?;
+                self.cursor.expect(")")?;
+                Ok(inner)
+            }
+            Some(Token::Identifier(name)) if name == "true" || name == "false" => {
+                Ok((Expression::Value(Value::Bool(name == "true")), Type::Bool))
+            }
+            Some(Token::Identifier(name)) if self.cursor.accept("(") => {
+                let &(_, function, parameters, returned) = FUNCTIONS
+                    .iter()
+                    .find(|(function, ..)| *function == name)
+                    .ok_or_else(|| error(column, &format!("unknown function {name}")))?;
+                let mut arguments = Vec::new();
+                for (i, &parameter) in parameters.iter().enumerate() {
+                    if i > 0 {
+                        self.cursor.expect(",")?;
+                    }
+                    arguments.push(self.typed(parameter, |parser| parser.nested(Self::or))?);
+                }
+                self.cursor.expect(")")?;
+                Ok((Expression::Call(function, arguments), returned))
+            }
+            Some(Token::Identifier(name)) => {
+                let &(_, field, field_type) = FIELDS
+                    .iter()
+                    .find(|(field, ..)| *field == name)
+                    .ok_or_else(|| error(column, &format!("unknown field {name}")))?;
+                Ok((Expression::Field(field), field_type))
+            }
+            Some(Token::Operator(operator)) => {
+                Err(error(column, &format!("unexpected '{operator}'")))
+            }
+            None => Err(error(column, "unexpected end of filter")),
+        }
+    }
+}
+
+impl Filter {
+    pub fn parse(text: &str) -> Result<Self, IoError> {
+        let mut tokens = Vec::new();
+        tokenize_line(&SYNTAX, 1, text, &mut tokens)?;
+        let end = (1, text.chars().count() + 1);
+        let mut parser = Parser {
+            cursor: Cursor::new(&SYNTAX, tokens, end),
+            depth: 0,
+        };
+        let expression = parser.typed(Type::Bool, Parser::or)?;
+        if !parser.cursor.at_end() {
+            return Err(error(parser.column(), "expected the end of the filter"));
+        }
+        Ok(Filter { expression })
+    }
+
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.expression.evaluate(entry).as_bool()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{parse_lines, with_env};
+
+    const DATABASE: [&str; 4] = [
+        "2024-01-01 10:00:00 +0000,first #Rust",
+        "2024-01-02 10:00:00 +0000;starred=1,>>0 a \"quoted\" reply",
+        "2024-01-03 10:00:00 +0000;prompt=Mood?;sensitive=1,Bad day",
+        "2024-02-01 10:00:00 +0000,a much longer message than the others",
+    ];
+
+    fn matching(filter: &str) -> Vec<usize> {
+        with_env(&[], || {
+            let filter = Filter::parse(filter).unwrap();
+            let entries = parse_lines(&DATABASE);
+            entries
+                .iter()
+                .filter(|e| filter.matches(e))
+                .map(|e| e.id)
+                .collect()
+        })
+    }
+
+    fn parse_error(filter: &str) -> String {
+        match Filter::parse(filter) {
+            Ok(_) => panic!("{filter} was parsed"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn fields_and_functions_are_evaluated() {
+        assert_eq!(matching("has_tag(\"#rust\")"), [0]);
+        assert_eq!(matching("is_reply || starred"), [1]);
+        assert_eq!(matching("sensitive && prompt == \"Mood?\""), [2]);
+        assert_eq!(matching("len(message) > 20"), [3]);
+        assert_eq!(matching("contains(message, \"\\\"quoted\\\"\")"), [1]);
+        assert_eq!(matching("starts_with(lower(message), \"bad\")"), [2]);
+        assert_eq!(
+            matching("date >= \"2024-01-02\" && date < \"2024-02\""),
+            [1, 2]
+        );
+        assert_eq!(matching("id != 1 && id <= 2"), [0, 2]);
+        assert_eq!(matching("reading_time > 1"), [3]);
+    }
+
+    #[test]
+    fn operators_follow_their_precedence() {
+        assert_eq!(matching("id == 0 || id == 1 && starred"), [0, 1]);
+        assert_eq!(matching("(id == 0 || id == 1) && !starred"), [0]);
+        assert_eq!(matching("!!is_reply"), [1]);
+        assert_eq!(matching("is_reply == false && id > 1"), [2, 3]);
+        assert_eq!(matching("true"), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn ids_follow_the_displayed_numbering() {
+        let filter = Filter::parse("id == 1").unwrap();
+        with_env(&[("VENT_TXT_ONE_BASED", "1")], || {
+            let entries = parse_lines(&DATABASE);
+            assert!(filter.matches(&entries[0]));
+            assert!(!filter.matches(&entries[1]));
+        });
+    }
+
+    #[test]
+    fn invalid_filters_report_their_column() {
+        for nested in [
+            "!".repeat(100_000) + "starred",
+            "(".repeat(100_000) + "starred",
+            "starred || ".repeat(100_000) + "starred",
+            "len(".repeat(100_000) + "message",
+        ] {
+            assert!(parse_error(&nested).ends_with(": nested too deeply"));
+        }
+        assert_eq!(
+            parse_error(&("!".repeat(200) + "starred")),
+            "Invalid filter at column 130: nested too deeply"
+        );
+        assert!(Filter::parse(&("!".repeat(128) + "starred")).is_ok());
+        assert_eq!(
+            parse_error("len(message)"),
+            "Invalid filter at column 1: expected a condition, found a number"
+        );
+        assert_eq!(
+            parse_error("id == \"1\""),
+            "Invalid filter at column 7: expected a number, found a string"
+        );
+        assert_eq!(
+            parse_error("starred && id"),
+            "Invalid filter at column 12: expected a condition, found a number"
+        );
+        assert_eq!(
+            parse_error("starred < true"),
+            "Invalid filter at column 11: '<' needs numbers or strings"
+        );
+        assert_eq!(
+            parse_error("message == \"a"),
+            "Invalid filter at column 12: unterminated string"
+        );
+        assert_eq!(
+            parse_error("has_tag(\"a\""),
+            "Invalid filter at column 12: expected ')'"
+        );
+        assert_eq!(
+            parse_error("size > 1"),
+            "Invalid filter at column 1: unknown field size"
+        );
+        assert_eq!(
+            parse_error("upper(message) == \"A\""),
+            "Invalid filter at column 1: unknown function upper"
+        );
+        assert_eq!(
+            parse_error("1 < 2 < 3"),
+            "Invalid filter at column 7: expected the end of the filter"
+        );
+        assert_eq!(
+            parse_error("id = 1"),
+            "Invalid filter at column 4: unexpected '='"
+        );
+        assert_eq!(
+            parse_error(""),
+            "Invalid filter at column 1: unexpected end of filter"
+        );
+        assert!(parse_error("id > 99999999999999999999").ends_with("number too large"));
+    }
+}
@@ -0,0 +1,649 @@
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+
+use serde_json::json;
+
+use handlebars as hb;
+use handlebars::{Handlebars, RenderError, Renderable};
+
+use crate::sanitize::{self, Allowlist};
+use crate::script::{Call, Script};
+use crate::{emoji, get_attachments_url, get_helpers_path, get_html_attributes, get_html_tags};
+
+/* Custom helpers may call each other, or themselves, so their nesting is bounded. */
+const MAX_CUSTOM_HELPER_DEPTH: usize = 16;
+
+pub fn register(handlebars: &mut Handlebars) {
+    handlebars.register_helper("if_reply", Box::new(RenderIfReplyHelper));
+    handlebars.register_helper("each_reverse", Box::new(RenderEachReverseHelper));
+    handlebars.register_helper("reply_preview", Box::new(RenderReplyPreviewHelper));
+    handlebars.register_helper("reply_count", Box::new(RenderReplyCountHelper));
+    handlebars.register_helper("reply_link", Box::new(RenderReplyLinkHelper));
+    handlebars.register_helper("anchor", Box::new(RenderAnchorHelper));
+    handlebars.register_helper("attachment_url", Box::new(RenderAttachmentUrlHelper));
+    handlebars.register_helper("emoji", Box::new(RenderEmojiHelper));
+    let allowlist = Allowlist::new(&get_html_tags(), &get_html_attributes());
+    handlebars.register_helper(
+        "html",
+        Box::new(RenderHtmlHelper {
+            allowlist: allowlist.clone(),
+        }),
+    );
+    register_custom(handlebars, Path::new(&get_helpers_path()), &allowlist);
+}
+
+/* Whether `name` is already a helper, provided by handlebars, by vent or by an earlier file.
+ * Handlebars doesn't tell, but in strict mode a missing helper with no parameters renders as a
+ * missing variable while an existing one is called.
+ */
+fn helper_exists(handlebars: &Handlebars, name: &str) -> bool {
+    let mut probe = handlebars.clone();
+    probe.set_strict_mode(true);
+    let missing = RenderError::strict_error(Some(&name.to_owned())).desc;
+    !matches!(
+        probe.render_template(&format!("{{{{{name}}}}}"), &json!({})),
+        Err(e) if e.desc == missing
+    )
+}
+
+/* Every `name.hbs` template and `name.script` script of the directory becomes the `name`
+ * helper, a missing directory simply has no helpers. Templates are as sandboxed as the main
+ * template and scripts, see `script`, can only compute a value from their parameters. Files
+ * which don't compile are reported and skipped without affecting the others.
+ */
+fn register_custom(handlebars: &mut Handlebars, directory: &Path, allowlist: &Allowlist) {
+    let Ok(files) = fs::read_dir(directory) else {
+        return;
+    };
+    let mut paths = files
+        .flatten()
+        .map(|file| file.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|e| e == "hbs" || e == "script")
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if helper_exists(handlebars, name) {
+            eprintln!(
+                "Helper {name} already exists, {} is ignored",
+                path.display()
+            );
+            continue;
+        }
+        if path.extension().is_some_and(|e| e == "script") {
+            match fs::read_to_string(&path).and_then(|script| Script::parse(&script)) {
+                Ok(script) => handlebars.register_helper(
+                    name,
+                    Box::new(RenderScriptHelper {
+                        name: name.to_owned(),
+                        script,
+                    }),
+                ),
+                Err(e) => eprintln!("Helper {name} not loaded: {e}"),
+            }
+            continue;
+        }
+        let template_name = format!("helpers/{name}");
+        match handlebars.register_template_file(&template_name, &path) {
+            Ok(()) => handlebars.register_helper(
+                name,
+                Box::new(RenderCustomHelper {
+                    name: name.to_owned(),
+                    template_name,
+                    allowlist: allowlist.clone(),
+                }),
+            ),
+            Err(e) => eprintln!("Helper {name} not loaded: {e}"),
+        }
+    }
+}
+
+thread_local! {
+    static CUSTOM_HELPER_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+struct RenderCustomHelper {
+    name: String,
+    template_name: String,
+    allowlist: Allowlist,
+}
+
+/* The helper template escaped what it rendered, its own markup is sanitized like messages are. */
+impl hb::HelperDef for RenderCustomHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &hb::Helper<'reg, 'rc>,
+        registry: &'reg Handlebars<'reg>,
+        _context: &'rc hb::Context,
+        _render_context: &mut hb::RenderContext<'reg, 'rc>,
+        out: &mut dyn hb::Output,
+    ) -> Result<(), RenderError> {
+        let args = helper
+            .params()
+            .iter()
+            .map(|param| param.value().clone())
+            .collect::<Vec<_>>();
+        let hash = helper
+            .hash()
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.value().clone()))
+            .collect::<serde_json::Map<_, _>>();
+        let data = serde_json::json!({
+            "value": args.first(),
+            "args": args,
+            "hash": hash,
+        });
+
+        let depth = CUSTOM_HELPER_DEPTH.with(|depth| depth.replace(depth.get() + 1));
+        let rendered = if depth >= MAX_CUSTOM_HELPER_DEPTH {
+            Err(RenderError::new(format!(
+                "Helper \"{}\" nested too deeply",
+                self.name
+            )))
+        } else {
+            // Errors of nested helpers are only described once, by the outermost helper
+            registry
+                .render(&self.template_name, &data)
+                .map_err(|e| match depth {
+                    0 => RenderError::new(format!("Error in helper \"{}\": {}", self.name, e.desc)),
+                    _ => RenderError::new(e.desc),
+                })
+        };
+        CUSTOM_HELPER_DEPTH.with(|d| d.set(depth));
+        match depth {
+            0 => out.write(&sanitize::sanitize(&rendered?, &self.allowlist))?,
+            _ => out.write(&rendered?)?,
+        }
+        Ok(())
+    }
+}
+
+struct RenderScriptHelper {
+    name: String,
+    script: Script,
+}
+
+/* The value computed by the script is escaped like any other value, unless the template asks
+ * for it to be written as HTML, e.g. with `{{html (name ...)}}`.
+ */
+impl hb::HelperDef for RenderScriptHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &hb::Helper<'reg, 'rc>,
+        _registry: &'reg Handlebars<'reg>,
+        _context: &'rc hb::Context,
+        _render_context: &mut hb::RenderContext<'reg, 'rc>,
+    ) -> Result<hb::ScopedJson<'reg, 'rc>, RenderError> {
+        let call = Call {
+            args: helper.params().iter().map(|param| param.value()).collect(),
+            hash: helper
+                .hash()
+                .iter()
+                .map(|(key, value)| (*key, value.value()))
+                .collect(),
+        };
+        let value = self
+            .script
+            .evaluate(&call)
+            .map_err(|e| RenderError::new(format!("Error in helper \"{}\": {e}", self.name)))?;
+        Ok(hb::ScopedJson::Derived(value.to_json()))
+    }
+}
+
+struct RenderIfReplyHelper;
+
+/* We don't use the built-in `if` helper as it follows javascript logic and will treat 0 as false.
+ * This helper will treat null as false and any number as true.
+ */
+impl hb::HelperDef for RenderIfReplyHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &hb::Helper<'reg, 'rc>,
+        registry: &'reg Handlebars<'reg>,
+        context: &'rc hb::Context,
+        render_context: &mut hb::RenderContext<'reg, 'rc>,
+        out: &mut dyn hb::Output,
+    ) -> Result<(), RenderError> {
+        let value = helper
+            .param(0)
+            .ok_or_else(|| RenderError::new("Param not found for helper \"if_reply\""))?
+            .value();
+        if value.is_number() {
+            let template = helper
+                .template()
+                .ok_or_else(|| RenderError::new("Template not found for helper \"if_reply\""))?;
+            template.render(registry, context, render_context, out)
+        } else if value.is_null() {
+            Ok(())
+        } else {
+            Err(RenderError::new(
+                "Param of invalid type for helper \"if_reply\"",
+            ))
+        }
+    }
+}
+
+struct RenderEachReverseHelper;
+
+/* We don't use the built-in `for_each` helper as we can't easily use to it to iterate backwards
+ * while keeping the original indices in the `index` local variable.
+ * When `chronological` is set at the root of the context, by `render --chronological`, the
+ * iteration goes forward instead so the same template can render both orders.
+ */
+impl hb::HelperDef for RenderEachReverseHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &hb::Helper<'reg, 'rc>,
+        registry: &'reg Handlebars<'reg>,
+        context: &'rc hb::Context,
+        render_context: &mut hb::RenderContext<'reg, 'rc>,
+        out: &mut dyn hb::Output,
+    ) -> Result<(), RenderError> {
+        let param = helper
+            .param(0)
+            .ok_or_else(|| RenderError::new("Param not found for helper \"each_reverse\""))?
+            .value();
+        let template = helper
+            .template()
+            .ok_or_else(|| RenderError::new("Template not found for helper \"each_reverse\""))?;
+        let chronological = context
+            .data()
+            .get("chronological")
+            .and_then(|c| c.as_bool())
+            .unwrap_or(false);
+        if param.is_array() {
+            let mut values = param
+                .as_array()
+                .unwrap()
+                .iter()
+                .enumerate()
+                .collect::<Vec<_>>();
+            if !chronological {
+                values.reverse();
+            }
+            // The iteration gets its own block so the context is restored after it
+            render_context.push_block(hb::BlockContext::new());
+            for (index, value) in values {
+                if let Some(block) = render_context.block_mut() {
+                    block.set_local_var("index", serde_json::json!(index));
+                    block.set_base_value(value.clone());
+                }
+                template.render(registry, context, render_context, out)?
+            }
+            render_context.pop_block();
+            Ok(())
+        } else {
+            Err(RenderError::new(
+                "Param of invalid type for helper \"each_reverse\"",
+            ))
+        }
+    }
+}
+
+struct RenderReplyPreviewHelper;
+
+/* Only the rendered entries are searched for the parent message, a reply to a filtered out
+ * message won't leak its content.
+ */
+impl hb::HelperDef for RenderReplyPreviewHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &hb::Helper<'reg, 'rc>,
+        registry: &'reg Handlebars<'reg>,
+        context: &'rc hb::Context,
+        _render_context: &mut hb::RenderContext<'reg, 'rc>,
+        out: &mut dyn hb::Output,
+    ) -> Result<(), RenderError> {
+        let reply = helper
+            .param(0)
+            .ok_or_else(|| RenderError::new("Param not found for helper \"reply_preview\""))?
+            .value();
+        let length = match helper.param(1) {
+            Some(length) => length.value().as_u64().ok_or_else(|| {
+                RenderError::new("Length of invalid type for helper \"reply_preview\"")
+            })? as usize,
+            None => 50,
+        };
+        if reply.is_null() {
+            return Ok(());
+        } else if !reply.is_number() {
+            return Err(RenderError::new(
+                "Param of invalid type for helper \"reply_preview\"",
+            ));
+        }
+
+        let parent_message = context
+            .data()
+            .get("entries")
+            .and_then(|entries| entries.as_array())
+            .and_then(|entries| entries.iter().find(|e| e.get("id") == Some(reply)))
+            .and_then(|parent| parent.get("message"))
+            .and_then(|message| message.as_str());
+        if let Some(message) = parent_message {
+            let preview = crate::truncate_text(message.trim(), length);
+            out.write(&registry.get_escape_fn()(&preview))?;
+        }
+        Ok(())
+    }
+}
+
+struct RenderReplyLinkHelper;
+
+/* The link points to the anchor of the parent message, as given by the `anchor` helper. The
+ * prefix is escaped like any other value.
+ */
+impl hb::HelperDef for RenderReplyLinkHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &hb::Helper<'reg, 'rc>,
+        registry: &'reg Handlebars<'reg>,
+        context: &'rc hb::Context,
+        _render_context: &mut hb::RenderContext<'reg, 'rc>,
+        out: &mut dyn hb::Output,
+    ) -> Result<(), RenderError> {
+        let reply = helper
+            .param(0)
+            .ok_or_else(|| RenderError::new("Param not found for helper \"reply_link\""))?
+            .value();
+        let prefix = match helper.param(1) {
+            Some(prefix) => prefix.value().as_str().ok_or_else(|| {
+                RenderError::new("Prefix of invalid type for helper \"reply_link\"")
+            })?,
+            None => ">>",
+        };
+        if reply.is_null() {
+            return Ok(());
+        }
+        let reply = reply
+            .as_u64()
+            .ok_or_else(|| RenderError::new("Param of invalid type for helper \"reply_link\""))?;
+        let escape = registry.get_escape_fn();
+        let anchor = escape(&anchor(context, reply));
+        let prefix = escape(prefix);
+        out.write(&format!("<a href=\"#{anchor}\">{prefix}{reply}</a>"))?;
+        Ok(())
+    }
+}
+
+/* Messages which aren't rendered have no anchor in the context, their ID is used instead so that
+ * links to them stay the same as before stable anchors.
+ */
+fn anchor(context: &hb::Context, id: u64) -> String {
+    context
+        .data()
+        .get("anchors")
+        .and_then(|anchors| anchors.get(id.to_string()))
+        .and_then(|anchor| anchor.as_str())
+        .map_or_else(|| id.to_string(), str::to_owned)
+}
+
+struct RenderAnchorHelper;
+
+impl hb::HelperDef for RenderAnchorHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &hb::Helper<'reg, 'rc>,
+        _registry: &'reg Handlebars<'reg>,
+        context: &'rc hb::Context,
+        _render_context: &mut hb::RenderContext<'reg, 'rc>,
+    ) -> Result<hb::ScopedJson<'reg, 'rc>, RenderError> {
+        let id = helper
+            .param(0)
+            .ok_or_else(|| RenderError::new("Param not found for helper \"anchor\""))?
+            .value()
+            .as_u64()
+            .ok_or_else(|| RenderError::new("Param of invalid type for helper \"anchor\""))?;
+        Ok(hb::ScopedJson::Derived(serde_json::json!(anchor(
+            context, id
+        ))))
+    }
+}
+
+struct RenderReplyCountHelper;
+
+/* The count is returned as a value rather than written out so that the helper can also be used
+ * as a subexpression, e.g. `{{#if (reply_count id)}}`.
+ */
+impl hb::HelperDef for RenderReplyCountHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &hb::Helper<'reg, 'rc>,
+        _registry: &'reg Handlebars<'reg>,
+        context: &'rc hb::Context,
+        _render_context: &mut hb::RenderContext<'reg, 'rc>,
+    ) -> Result<hb::ScopedJson<'reg, 'rc>, RenderError> {
+        let id = helper
+            .param(0)
+            .ok_or_else(|| RenderError::new("Param not found for helper \"reply_count\""))?
+            .value()
+            .as_u64()
+            .ok_or_else(|| RenderError::new("Param of invalid type for helper \"reply_count\""))?;
+        let count = context
+            .data()
+            .get("backlinks")
+            .and_then(|backlinks| backlinks.get(id.to_string()))
+            .and_then(|replies| replies.as_array())
+            .map_or(0, |replies| replies.len());
+        Ok(hb::ScopedJson::Derived(serde_json::json!(count)))
+    }
+}
+
+struct RenderAttachmentUrlHelper;
+
+impl hb::HelperDef for RenderAttachmentUrlHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &hb::Helper<'reg, 'rc>,
+        _registry: &'reg Handlebars<'reg>,
+        _context: &'rc hb::Context,
+        _render_context: &mut hb::RenderContext<'reg, 'rc>,
+    ) -> Result<hb::ScopedJson<'reg, 'rc>, RenderError> {
+        let name = helper
+            .param(0)
+            .ok_or_else(|| RenderError::new("Param not found for helper \"attachment_url\""))?
+            .value()
+            .as_str()
+            .ok_or_else(|| {
+                RenderError::new("Param of invalid type for helper \"attachment_url\"")
+            })?;
+        let url = get_attachments_url();
+        Ok(hb::ScopedJson::Derived(serde_json::json!(format!(
+            "{}/{name}",
+            url.trim_end_matches('/')
+        ))))
+    }
+}
+
+struct RenderEmojiHelper;
+
+/* Shortcodes are only expanded in the document, messages keep them as they were typed. */
+impl hb::HelperDef for RenderEmojiHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &hb::Helper<'reg, 'rc>,
+        _registry: &'reg Handlebars<'reg>,
+        _context: &'rc hb::Context,
+        _render_context: &mut hb::RenderContext<'reg, 'rc>,
+    ) -> Result<hb::ScopedJson<'reg, 'rc>, RenderError> {
+        let text = helper
+            .param(0)
+            .ok_or_else(|| RenderError::new("Param not found for helper \"emoji\""))?
+            .value()
+            .as_str()
+            .ok_or_else(|| RenderError::new("Param of invalid type for helper \"emoji\""))?;
+        Ok(hb::ScopedJson::Derived(serde_json::json!(
+            emoji::expand_shortcodes(text)
+        )))
+    }
+}
+
+struct RenderHtmlHelper {
+    allowlist: Allowlist,
+}
+
+/* Writes the HTML of a message with only the allowed tags and attributes, see `sanitize`. */
+impl hb::HelperDef for RenderHtmlHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &hb::Helper<'reg, 'rc>,
+        _registry: &'reg Handlebars<'reg>,
+        _context: &'rc hb::Context,
+        _render_context: &mut hb::RenderContext<'reg, 'rc>,
+        out: &mut dyn hb::Output,
+    ) -> Result<(), RenderError> {
+        let html = helper
+            .param(0)
+            .ok_or_else(|| RenderError::new("Param not found for helper \"html\""))?
+            .value()
+            .as_str()
+            .ok_or_else(|| RenderError::new("Param of invalid type for helper \"html\""))?;
+        out.write(&sanitize::sanitize(html, &self.allowlist))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{temp_dir, with_env};
+
+    fn registry_with(files: &[(&str, &str)]) -> Handlebars<'static> {
+        let directory = temp_dir("helpers");
+        for (name, content) in files {
+            fs::write(directory.join(name), content).unwrap();
+        }
+        with_env(&[("VENT_TXT_HELPERS", directory.to_str().unwrap())], || {
+            let mut handlebars = Handlebars::new();
+            register(&mut handlebars);
+            handlebars
+        })
+    }
+
+    #[test]
+    fn script_helpers_are_rendered_escaped() {
+        let handlebars =
+            registry_with(&[("shout.script", "upper(value) + repeat(\"!\", hash(\"n\"))")]);
+        let rendered = handlebars
+            .render_template("{{shout name n=2}}", &json!({"name": "<hi>"}))
+            .unwrap();
+        assert_eq!(rendered, "&lt;HI&gt;!!");
+        let error = handlebars
+            .render_template("{{shout 1}}", &json!({}))
+            .unwrap_err();
+        assert_eq!(
+            error.desc,
+            "Error in helper \"shout\": upper needs a string, found a number"
+        );
+    }
+
+    #[test]
+    fn helpers_fail_one_by_one() {
+        let handlebars = registry_with(&[
+            ("bad.script", "len("),
+            ("bad_template.hbs", "{{#if}}"),
+            ("good.script", "value * 2"),
+        ]);
+        let rendered = handlebars
+            .render_template("{{good 21}}", &json!({}))
+            .unwrap();
+        assert_eq!(rendered, "42");
+        let mut strict = handlebars.clone();
+        strict.set_strict_mode(true);
+        assert!(strict.render_template("{{bad}}", &json!({})).is_err());
+    }
+
+    #[test]
+    fn existing_helpers_are_kept() {
+        let handlebars = registry_with(&[
+            ("if.script", "1"),
+            ("emoji.script", "2"),
+            ("html.hbs", "3"),
+            ("twice.hbs", "{{value}}{{value}}"),
+            ("twice.script", "4"),
+        ]);
+        let rendered = handlebars
+            .render_template(
+                "{{#if true}}{{emoji \":smile:\"}}{{/if}} {{twice \"a\"}}",
+                &json!({}),
+            )
+            .unwrap();
+        assert_eq!(rendered, "😄 aa");
+        for name in ["if", "each", "lookup", "reply_link", "html", "twice"] {
+            assert!(helper_exists(&handlebars, name), "{name}");
+        }
+        assert!(!helper_exists(&handlebars, "missing"));
+    }
+
+    #[test]
+    fn reply_previews_show_the_rendered_parent() {
+        let handlebars = registry_with(&[]);
+        let data = json!({"entries": [
+            {"id": 0, "reply": null, "message": "  <b>a long parent message</b>"},
+            {"id": 2, "reply": 0, "message": "reply"},
+            {"id": 3, "reply": 1, "message": "reply to a filtered out message"},
+        ]});
+        let rendered = handlebars
+            .render_template(
+                "{{#each entries}}[{{reply_preview reply 10}}]{{/each}}",
+                &data,
+            )
+            .unwrap();
+        assert_eq!(rendered, "[][&lt;b&gt;a long …][]");
+        let error = handlebars
+            .render_template("{{reply_preview \"0\"}}", &data)
+            .unwrap_err();
+        assert_eq!(
+            error.desc,
+            "Param of invalid type for helper \"reply_preview\""
+        );
+    }
+
+    #[test]
+    fn each_reverse_follows_the_chronological_hint() {
+        let handlebars = registry_with(&[]);
+        let template = "{{#each_reverse entries}}{{@index}}{{this}} {{/each_reverse}}";
+        let rendered = |data| handlebars.render_template(template, &data).unwrap();
+        assert_eq!(rendered(json!({"entries": ["a", "b", "c"]})), "2c 1b 0a ");
+        assert_eq!(
+            rendered(json!({"entries": ["a", "b", "c"], "chronological": true})),
+            "0a 1b 2c "
+        );
+        assert!(handlebars
+            .render_template("{{#each_reverse 1}}{{/each_reverse}}", &json!({}))
+            .is_err());
+    }
+
+    #[test]
+    fn reply_links_point_to_the_anchor() {
+        let handlebars = registry_with(&[]);
+        let data = json!({"anchors": {"3": "20240105T100000Z"}});
+        let rendered = |template: &str| handlebars.render_template(template, &data).unwrap();
+        assert_eq!(
+            rendered("{{reply_link 3}}"),
+            "<a href=\"#20240105T100000Z\">&gt;&gt;3</a>"
+        );
+        assert_eq!(
+            rendered("{{reply_link 4 \"re: <\"}}"),
+            "<a href=\"#4\">re: &lt;4</a>"
+        );
+        assert_eq!(rendered("{{reply_link null}}"), "");
+        assert!(handlebars
+            .render_template("{{reply_link 1 2}}", &data)
+            .is_err());
+    }
+
+    #[test]
+    fn emoji_shortcodes_are_expanded_and_escaped() {
+        let handlebars = registry_with(&[]);
+        let rendered = handlebars
+            .render_template("{{emoji text}}", &serde_json::json!({"text": ":cat: <b>"}))
+            .unwrap();
+        assert_eq!(rendered, "\u{1F431} &lt;b&gt;");
+    }
+}
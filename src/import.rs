@@ -0,0 +1,454 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Error as IoError, ErrorKind};
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+
+use crate::{display_message_id, fold_text, parse_date, Entry};
+
+/* Bytes 0x80 to 0x9F of Windows-1252, the undefined ones are decoded as the C1 controls like
+ * Latin-1 does.
+ */
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+pub fn decode(bytes: Vec<u8>, encoding: &str) -> Result<String, IoError> {
+    match encoding.to_lowercase().as_str() {
+        "utf-8" | "utf8" => String::from_utf8(bytes).map_err(|e| {
+            IoError::new(
+                ErrorKind::InvalidData,
+                format!("{}, use --encoding for other encodings", e.utf8_error()),
+            )
+        }),
+        "latin-1" | "latin1" | "iso-8859-1" => Ok(bytes.into_iter().map(char::from).collect()),
+        "windows-1252" | "cp1252" => Ok(bytes
+            .into_iter()
+            .map(|b| match b {
+                0x80..=0x9F => WINDOWS_1252[(b - 0x80) as usize],
+                _ => char::from(b),
+            })
+            .collect()),
+        _ => Err(IoError::new(ErrorKind::InvalidInput, "Invalid encoding")),
+    }
+}
+
+/* Month names in English, French, German, Spanish, Italian and Portuguese, in order. */
+const MONTHS: [&str; 6] = [
+    "january february march april may june july august september october november december",
+    "janvier février mars avril mai juin juillet août septembre octobre novembre décembre",
+    "januar februar märz april mai juni juli august september oktober november dezember",
+    "enero febrero marzo abril mayo junio julio agosto septiembre octubre noviembre diciembre",
+    "gennaio febbraio marzo aprile maggio giugno luglio agosto settembre ottobre novembre dicembre",
+    "janeiro fevereiro março abril maio junho julho agosto setembro outubro novembro dezembro",
+];
+
+/* Abbreviations are accepted as long as they are at least three letters long and only match a
+ * single month, e.g. `janv` or `sept` but not `jui`.
+ */
+fn parse_month(word: &str) -> Option<u32> {
+    if word.chars().count() < 3 {
+        return None;
+    }
+    let word = fold_text(word, false);
+    let mut months = MONTHS
+        .iter()
+        .flat_map(|names| names.split(' ').enumerate())
+        .filter(|(_, name)| fold_text(name, false).starts_with(&word))
+        .map(|(index, _)| index as u32 + 1)
+        .collect::<Vec<u32>>();
+    months.sort();
+    months.dedup();
+    match months[..] {
+        [month] => Some(month),
+        _ => None,
+    }
+}
+
+/* Localized dates are made of a month name, a day, a four-digit year and an optional `hh:mm` or
+ * `hh:mm:ss` time in any order, e.g. `janvier 5, 2024` or `5. März 2024 14:30`. Words which
+ * aren't any of those, like `de` in `5 de enero de 2024`, are ignored.
+ */
+pub fn parse_localized_date(date: &str) -> Option<NaiveDateTime> {
+    let (mut year, mut month, mut day, mut time) = (None, None, None, None);
+    for word in date
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .map(|word| word.trim_end_matches('.').to_lowercase())
+        .filter(|word| !word.is_empty())
+    {
+        if word.contains(':') {
+            time = NaiveTime::parse_from_str(&word, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(&word, "%H:%M"))
+                .ok();
+        } else if let Ok(number) = word.parse::<u32>() {
+            match word.len() {
+                4 => year = Some(number as i32),
+                1 | 2 => day = Some(number),
+                _ => return None,
+            }
+        } else if let Some(number) = parse_month(&word) {
+            month = Some(number);
+        }
+    }
+    let date = NaiveDate::from_ymd_opt(year?, month?, day?)?;
+    Some(date.and_time(time.unwrap_or_default()))
+}
+
+pub fn json(text: &str) -> Result<Vec<Entry>, IoError> {
+    serde_json::from_str(text).map_err(|e| IoError::new(ErrorKind::InvalidData, e))
+}
+
+/* JSON lines hold one message per line, like `vent export --format ndjson` writes them, empty
+ * lines are skipped.
+ */
+pub fn jsonl(text: &str) -> Result<Vec<Entry>, IoError> {
+    let entries = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                IoError::new(ErrorKind::InvalidData, format!("Line {}: {e}", index + 1))
+            })
+        })
+        .collect::<Result<Vec<Entry>, IoError>>()?;
+    Ok(entries)
+}
+
+/* Imported messages are appended after the `first_id` messages of the database. Their replies
+ * point to the `id` of another imported message, as written by `vent export`, whose IDs have gaps
+ * where revisions were left out. Files without distinct IDs are taken as written by other tools,
+ * their replies being positions among the imported messages. Replies to messages outside of the
+ * import are dropped.
+ */
+pub fn rebase(mut entries: Vec<Entry>, first_id: usize) -> Vec<Entry> {
+    let mut positions = HashMap::new();
+    for (index, entry) in entries.iter().enumerate() {
+        positions.insert(entry.id, first_id + index);
+    }
+    if positions.len() < entries.len() {
+        positions = (0..entries.len())
+            .map(|index| (index, first_id + index))
+            .collect();
+    }
+    for (index, entry) in entries.iter_mut().enumerate() {
+        entry.id = first_id + index;
+        entry.reply = entry.reply.and_then(|r| positions.get(&r).copied());
+    }
+    entries
+}
+
+const MAX_MISSING_IDS: usize = 10_000;
+
+/* Messages keeping their IDs are put back at their positions, which have to come after the
+ * `first_id` messages of the database. Missing IDs, e.g. revisions left out of an export, are
+ * filled with removed messages dated like the message following them, an import leaving more than
+ * `MAX_MISSING_IDS` of them is refused. Replies keep pointing to the same IDs, an import with a
+ * reply past its last message is refused.
+ */
+pub fn keep_ids(
+    entries: Vec<Entry>,
+    first_id: usize,
+    removed_text: &str,
+) -> Result<Vec<Entry>, IoError> {
+    let mut by_id = BTreeMap::new();
+    for entry in entries {
+        if entry.id < first_id {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Message {} can't keep its ID, the database already has {first_id} messages",
+                    display_message_id(entry.id)
+                ),
+            ));
+        }
+        let id = entry.id;
+        if by_id.insert(id, entry).is_some() {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                format!("Duplicate message ID {}", display_message_id(id)),
+            ));
+        }
+    }
+    let count = match by_id.keys().next_back() {
+        Some(&last) => last.checked_add(1).ok_or_else(|| {
+            IoError::new(
+                ErrorKind::InvalidInput,
+                format!("Invalid message ID {last}"),
+            )
+        })?,
+        None => first_id,
+    };
+    if let Some(entry) = by_id
+        .values()
+        .find(|entry| entry.reply.is_some_and(|r| r >= count))
+    {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Message {} replies to a message which isn't imported",
+                display_message_id(entry.id)
+            ),
+        ));
+    }
+    let missing = count - first_id - by_id.len();
+    if missing > MAX_MISSING_IDS {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Keeping the IDs would leave {missing} missing messages, more than the {MAX_MISSING_IDS} allowed"
+            ),
+        ));
+    }
+    let mut placed = Vec::with_capacity(count - first_id);
+    // Messages are placed from the last one so that the date following each gap is known
+    let mut entries = by_id.into_iter().rev().peekable();
+    let mut next_date = String::new();
+    for id in (first_id..count).rev() {
+        let entry = match entries.next_if(|(entry_id, _)| *entry_id == id) {
+            Some((_, entry)) => entry,
+            None => Entry {
+                id,
+                date: next_date.clone(),
+                reply: None,
+                message: removed_text.to_owned(),
+                prompt: None,
+                tags: Vec::new(),
+                spoiler: false,
+                starred: false,
+                attachments: Vec::new(),
+                reading_time_seconds: 0,
+                metadata: BTreeMap::new(),
+            },
+        };
+        next_date = entry.date.clone();
+        placed.push(entry);
+    }
+    placed.reverse();
+    Ok(placed)
+}
+
+/* Sorting is stable so that messages with the same date, or with dates which can't be parsed and
+ * are put last, keep their order. IDs and replies are rebased on the new positions.
+ */
+pub fn sort_by_date(entries: &mut [Entry], first_id: usize) {
+    entries.sort_by_cached_key(|entry| {
+        let date = parse_date(&entry.date);
+        (date.is_none(), date)
+    });
+    let mut new_ids = vec![0; entries.len()];
+    for (index, entry) in entries.iter().enumerate() {
+        new_ids[entry.id - first_id] = first_id + index;
+    }
+    for (index, entry) in entries.iter_mut().enumerate() {
+        entry.id = first_id + index;
+        entry.reply = entry.reply.map(|r| new_ids[r - first_id]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export;
+    use crate::tests::{parse_lines, with_env};
+
+    /* Exports leave revisions out, like `vent export --format json` does. */
+    fn exported(lines: &[&str]) -> String {
+        let mut entries = Entry::apply_revisions(parse_lines(lines));
+        entries.retain(|entry| entry.revision_of().is_none());
+        let mut json = Vec::new();
+        export::json(&mut json, &entries).unwrap();
+        String::from_utf8(json).unwrap()
+    }
+
+    const DATABASE: [&str; 4] = [
+        "2024-01-01 10:00:00 +0000,first",
+        "2024-01-02 10:00:00 +0000;revision_of=0,first, edited",
+        "2024-01-03 10:00:00 +0000,>>0 second",
+        "2024-01-04 10:00:00 +0000,>>2 third",
+    ];
+
+    #[test]
+    fn rebase_follows_exported_ids_across_revisions() {
+        with_env(&[], || {
+            let entries = rebase(json(&exported(&DATABASE)).unwrap(), 10);
+            let summary = entries
+                .iter()
+                .map(|entry| (entry.id, entry.reply, entry.message.as_str()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                summary,
+                [
+                    (10, None, "first, edited"),
+                    (11, Some(10), "second"),
+                    (12, Some(11), "third"),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn rebase_uses_positions_without_ids() {
+        let text = r#"
+            {"date": "2024-01-01 10:00:00 +0000", "reply": null, "message": "a", "prompt": null}
+            {"date": "2024-01-02 10:00:00 +0000", "reply": 0, "message": "b", "prompt": null}
+            {"date": "2024-01-03 10:00:00 +0000", "reply": 7, "message": "c", "prompt": null}
+        "#;
+        let entries = rebase(jsonl(text).unwrap(), 3);
+        let replies = entries.iter().map(|entry| entry.reply).collect::<Vec<_>>();
+        assert_eq!(replies, [None, Some(3), None]);
+    }
+
+    #[test]
+    fn jsonl_reports_the_line() {
+        let error = jsonl("\n{\"date\": 1}\n").unwrap_err();
+        assert!(error.to_string().starts_with("Line 2: "), "{error}");
+    }
+
+    #[test]
+    fn keep_ids_fills_gaps_with_removed_messages() {
+        with_env(&[], || {
+            let entries = keep_ids(json(&exported(&DATABASE)).unwrap(), 0, "[removed]").unwrap();
+            let summary = entries
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.id,
+                        entry.reply,
+                        entry.message.as_str(),
+                        &entry.date[..10],
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                summary,
+                [
+                    (0, None, "first, edited", "2024-01-01"),
+                    (1, None, "[removed]", "2024-01-03"),
+                    (2, Some(0), "second", "2024-01-03"),
+                    (3, Some(2), "third", "2024-01-04"),
+                ]
+            );
+        });
+    }
+
+    fn entries_with_ids(ids: &[(usize, Option<usize>)]) -> Vec<Entry> {
+        ids.iter()
+            .map(|&(id, reply)| {
+                let reply = reply.map_or(String::from("null"), |r| r.to_string());
+                let text = format!(
+                    r#"{{"id": {id}, "date": "2024-01-01 10:00:00 +0000", "reply": {reply}, "message": "m", "prompt": null}}"#
+                );
+                serde_json::from_str(&text).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keep_ids_refuses_ids_of_the_database() {
+        let error = keep_ids(entries_with_ids(&[(1, None)]), 2, "").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        let error = keep_ids(entries_with_ids(&[(4, None), (4, None)]), 2, "").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn keep_ids_refuses_the_largest_id() {
+        let error = keep_ids(entries_with_ids(&[(usize::MAX, None)]), 0, "").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn keep_ids_refuses_large_gaps() {
+        let error = keep_ids(entries_with_ids(&[(1 << 40, None)]), 0, "").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        let entries = keep_ids(entries_with_ids(&[(MAX_MISSING_IDS, None)]), 0, "").unwrap();
+        assert_eq!(entries.len(), MAX_MISSING_IDS + 1);
+    }
+
+    #[test]
+    fn keep_ids_keeps_replies() {
+        let entries = keep_ids(entries_with_ids(&[(3, Some(1)), (4, Some(3))]), 2, "").unwrap();
+        let replies = entries.iter().map(|entry| entry.reply).collect::<Vec<_>>();
+        assert_eq!(replies, [None, Some(1), Some(3)]);
+        let error = keep_ids(entries_with_ids(&[(3, Some(5))]), 2, "").unwrap_err();
+        assert!(error.to_string().contains("Message 3"), "{error}");
+    }
+
+    #[test]
+    fn sort_by_date_rebases_replies() {
+        let text = r#"[
+            {"id": 0, "date": "2024-01-03 10:00:00 +0000", "reply": 1, "message": "c", "prompt": null},
+            {"id": 1, "date": "2024-01-01 10:00:00 +0000", "reply": null, "message": "a", "prompt": null},
+            {"id": 2, "date": "unknown", "reply": 0, "message": "z", "prompt": null},
+            {"id": 3, "date": "2024-01-02 10:00:00 +0000", "reply": null, "message": "b", "prompt": null}
+        ]"#;
+        let mut entries = rebase(json(text).unwrap(), 5);
+        sort_by_date(&mut entries, 5);
+        let summary = entries
+            .iter()
+            .map(|entry| (entry.id, entry.reply, entry.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                (5, None, "a"),
+                (6, None, "b"),
+                (7, Some(5), "c"),
+                (8, Some(7), "z")
+            ]
+        );
+    }
+
+    #[test]
+    fn encodings_are_decoded() {
+        let bytes = b"caf\xe9 \x80\x93 \x81".to_vec();
+        assert_eq!(
+            decode(bytes.clone(), "Latin-1").unwrap(),
+            "café \u{80}\u{93} \u{81}"
+        );
+        assert_eq!(decode(bytes.clone(), "cp1252").unwrap(), "café €“ \u{81}");
+        assert_eq!(decode("café".into(), "UTF-8").unwrap(), "café");
+        let error = decode(bytes.clone(), "utf8").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error
+            .to_string()
+            .ends_with("use --encoding for other encodings"));
+        assert_eq!(
+            decode(bytes, "ebcdic").unwrap_err().to_string(),
+            "Invalid encoding"
+        );
+    }
+
+    #[test]
+    fn localized_dates_are_parsed_in_any_order() {
+        let date = |text| parse_localized_date(text).map(|d| d.to_string());
+        assert_eq!(
+            date("janvier 5, 2024").as_deref(),
+            Some("2024-01-05 00:00:00")
+        );
+        assert_eq!(
+            date("5. März 2024 14:30").as_deref(),
+            Some("2024-03-05 14:30:00")
+        );
+        assert_eq!(
+            date("5 de enero de 2024").as_deref(),
+            Some("2024-01-05 00:00:00")
+        );
+        assert_eq!(
+            date("2024 sept 3 10:00:05").as_deref(),
+            Some("2024-09-03 10:00:05")
+        );
+        assert_eq!(
+            date("3 agosto 2024").as_deref(),
+            Some("2024-08-03 00:00:00")
+        );
+        // Ambiguous abbreviations, missing parts and impossible dates
+        assert_eq!(date("5 jui 2024"), None);
+        assert_eq!(date("january 2024"), None);
+        assert_eq!(date("5 january 24"), None);
+        assert_eq!(date("30 février 2024"), None);
+    }
+}
@@ -1,14 +1,39 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
-use std::error::Error;
+use std::fmt;
+use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::{self, BufReader, Error as IoError, ErrorKind};
+use std::io::{self, BufReader, BufWriter, Error as IoError, ErrorKind, IsTerminal};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
 
+use builder::EntryBuilder;
 use chrono::prelude::*;
 
-use handlebars as hb;
-use handlebars::{Handlebars, RenderError, Renderable};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+mod builder;
+mod drafts;
+mod emoji;
+mod export;
+mod feed;
+mod filter;
+mod helpers;
+mod import;
+#[cfg(unix)]
+mod mapped;
+mod render;
+mod sanitize;
+mod script;
+mod serve;
+mod sqlite;
+mod stats;
+mod threads;
+mod tokens;
 
 fn get_csv_path() -> String {
     env::var("VENT_TXT_CSV").unwrap_or_else(|_| String::from("vent.csv"))
@@ -18,9 +43,261 @@ fn get_template_path() -> String {
     env::var("VENT_TXT_HBS").unwrap_or_else(|_| String::from("template/vent.hbs"))
 }
 
-fn collect_message_from_args(args: env::Args) -> Result<String, IoError> {
-    let message = args.collect::<Vec<String>>().join(" ").trim().to_owned();
-    if message.is_empty() {
+fn get_helpers_path() -> String {
+    env::var("VENT_TXT_HELPERS").unwrap_or_else(|_| String::from("template/helpers"))
+}
+
+fn get_document_url() -> Option<String> {
+    env::var("VENT_TXT_URL").ok().filter(|s| !s.is_empty())
+}
+
+fn get_removed_text() -> String {
+    env::var("VENT_TXT_REMOVED_TEXT")
+        .ok()
+        .filter(|s| !s.is_empty() && !s.contains('\n') && !s.contains('\r'))
+        .unwrap_or_else(|| String::from("[removed]"))
+}
+
+fn get_read_buffer_size() -> usize {
+    env::var("VENT_TXT_READ_BUFFER")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|&size| size > 0)
+        .unwrap_or(8 * 1024)
+}
+
+#[cfg(unix)]
+fn get_mmap() -> bool {
+    get_flag_setting("VENT_TXT_MMAP", false)
+}
+
+fn get_words_per_minute() -> usize {
+    env::var("VENT_TXT_WPM")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|&wpm| wpm > 0)
+        .unwrap_or(200)
+}
+
+/* Days start at midnight in the local timezone, a later start makes late night messages count
+ * for the previous day.
+ */
+fn get_day_start() -> u32 {
+    env::var("VENT_TXT_DAY_START")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|&hour| hour < 24)
+        .unwrap_or(0)
+}
+
+fn get_drafts_path() -> String {
+    env::var("VENT_TXT_DRAFTS").unwrap_or_else(|_| String::from("drafts.csv"))
+}
+
+fn get_attachments_path() -> String {
+    env::var("VENT_TXT_ATTACHMENTS").unwrap_or_else(|_| String::from("attachments"))
+}
+
+/* Attachments are linked relative to the rendered document unless they are served elsewhere. */
+fn get_attachments_url() -> String {
+    env::var("VENT_TXT_ATTACHMENTS_URL").unwrap_or_else(|_| get_attachments_path())
+}
+
+fn get_max_file_bytes() -> Option<u64> {
+    env::var("VENT_TXT_MAX_FILE_BYTES")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .filter(|&size| size > 0)
+}
+
+fn get_max_chain() -> Option<usize> {
+    env::var("VENT_TXT_MAX_CHAIN")
+        .ok()
+        .and_then(|s| s.parse().ok())
+}
+
+/* Stopwords are read from a file with one word per line, without it a short list of common
+ * English words is used.
+ */
+fn get_stopwords() -> Result<HashSet<String>, IoError> {
+    match env::var("VENT_TXT_STOPWORDS") {
+        Ok(path) if !path.is_empty() => Ok(fs::read_to_string(path)?
+            .lines()
+            .map(|word| word.trim().to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect()),
+        _ => Ok(stats::DEFAULT_STOPWORDS
+            .iter()
+            .map(|word| word.to_string())
+            .collect()),
+    }
+}
+
+fn get_flag_setting(name: &str, default: bool) -> bool {
+    match env::var(name).as_deref() {
+        Ok("1") | Ok("true") | Ok("yes") => true,
+        Ok("0") | Ok("false") | Ok("no") => false,
+        _ => default,
+    }
+}
+
+fn get_one_based_ids() -> bool {
+    get_flag_setting("VENT_TXT_ONE_BASED", false)
+}
+
+fn get_dedupe_tags() -> bool {
+    get_flag_setting("VENT_TXT_DEDUPE_TAGS", true)
+}
+
+fn get_trim_reply_whitespace() -> bool {
+    get_flag_setting("VENT_TXT_TRIM_REPLY_WHITESPACE", true)
+}
+
+fn get_edit_preserves_date() -> bool {
+    get_flag_setting("VENT_TXT_EDIT_PRESERVES_DATE", true)
+}
+
+fn get_reply_metadata() -> bool {
+    env::var("VENT_TXT_REPLY_STORAGE").is_ok_and(|s| s == "metadata")
+}
+
+fn get_indent() -> String {
+    env::var("VENT_TXT_INDENT").unwrap_or_else(|_| String::from("  "))
+}
+
+fn get_line_format() -> String {
+    env::var("VENT_TXT_LINE_FORMAT").unwrap_or_else(|_| String::from("{date}{metadata},{message}"))
+}
+
+/* HTML written in messages is shown as text unless its tags are listed, see `sanitize`. */
+fn get_html_tags() -> String {
+    env::var("VENT_TXT_HTML_TAGS").unwrap_or_default()
+}
+
+fn get_html_attributes() -> String {
+    env::var("VENT_TXT_HTML_ATTRIBUTES").unwrap_or_else(|_| String::from("href title"))
+}
+
+fn get_stable_anchors() -> bool {
+    get_flag_setting("VENT_TXT_STABLE_ANCHORS", false)
+}
+
+fn get_allow_empty() -> bool {
+    get_flag_setting("VENT_TXT_ALLOW_EMPTY", false)
+}
+
+fn get_trim_stored() -> bool {
+    get_flag_setting("VENT_TXT_TRIM_STORED", false)
+}
+
+fn get_fsync() -> bool {
+    get_flag_setting("VENT_TXT_FSYNC", false)
+}
+
+#[cfg(unix)]
+fn get_file_mode() -> Option<u32> {
+    env::var("VENT_TXT_FILE_MODE")
+        .ok()
+        .and_then(|s| u32::from_str_radix(s.trim_start_matches("0o"), 8).ok())
+        .filter(|&mode| mode <= 0o7777)
+}
+
+/* Message IDs are always stored as 0-based positions in the database, they are only converted
+ * when they are read from or displayed to the user.
+ */
+fn parse_message_id(text: &str) -> Option<usize> {
+    let id = text.parse::<usize>().ok()?;
+    if get_one_based_ids() {
+        id.checked_sub(1)
+    } else {
+        Some(id)
+    }
+}
+
+fn display_message_id(message_id: usize) -> usize {
+    if get_one_based_ids() {
+        message_id + 1
+    } else {
+        message_id
+    }
+}
+
+/* Options are `--name` arguments which either stand alone (flags) or take the following argument
+ * as their value. Commands taking a free-form message only accept options before it so that the
+ * message itself is never interpreted, `--` can be used to end the options explicitly.
+ */
+#[derive(Default)]
+struct Options {
+    flags: Vec<String>,
+    values: Vec<(String, String)>,
+}
+
+impl Options {
+    fn parse(
+        args: &mut Vec<String>,
+        flags: &[&str],
+        valued: &[&str],
+        leading_only: bool,
+    ) -> Result<Self, IoError> {
+        let mut options = Options::default();
+        let mut positionals = Vec::new();
+        let mut iter = std::mem::take(args).into_iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--" {
+                positionals.extend(iter);
+                break;
+            } else if !arg.starts_with("--") {
+                positionals.push(arg);
+                if leading_only {
+                    positionals.extend(iter);
+                    break;
+                }
+            } else if flags.contains(&arg.as_str()) {
+                options.flags.push(arg);
+            } else if valued.contains(&arg.as_str()) {
+                let value = iter.next().ok_or_else(|| {
+                    IoError::new(
+                        ErrorKind::InvalidInput,
+                        format!("No value for option {arg}"),
+                    )
+                })?;
+                options.values.push((arg, value));
+            } else {
+                return Err(IoError::new(
+                    ErrorKind::InvalidInput,
+                    format!("Unknown option {arg}"),
+                ));
+            }
+        }
+        *args = positionals;
+        Ok(options)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|f| f == name)
+    }
+
+    fn values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.values
+            .iter()
+            .filter(move |(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/* Empty messages are refused unless `VENT_TXT_ALLOW_EMPTY` is set, e.g. for check-ins which only
+ * record a date.
+ */
+fn validate_message(message: String) -> Result<String, IoError> {
+    if message.is_empty() && !get_allow_empty() {
         Err(IoError::new(ErrorKind::InvalidInput, "Empty message"))
     } else if message.contains('\n') || message.contains('\r') {
         Err(IoError::new(
@@ -32,218 +309,4443 @@ fn collect_message_from_args(args: env::Args) -> Result<String, IoError> {
     }
 }
 
-fn collect_message_id_from_args(args: &mut env::Args) -> Result<usize, IoError> {
-    args.next()
-        .and_then(|s| s.parse().ok())
+/* Search text is matched as typed, unlike messages it isn't a reply marker to normalize. */
+fn collect_search_text(args: Vec<String>) -> Result<String, IoError> {
+    let text = args.join(" ");
+    match text.trim() {
+        "" => Err(IoError::new(ErrorKind::InvalidInput, "No search text")),
+        text => Ok(text.to_owned()),
+    }
+}
+
+/* The whole file is a single message, its new lines are kept and escaped when it is stored, see
+ * `escape_message`.
+ */
+fn read_message_file(path: &str) -> Result<String, IoError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| IoError::new(e.kind(), format!("Unable to read {path} ({e})")))?;
+    Ok(content.replace("\r\n", "\n"))
+}
+
+/* A reply marker alone is refused, like an empty message, instead of adding an empty reply. */
+fn empty_reply_error() -> IoError {
+    IoError::new(ErrorKind::InvalidInput, "Empty reply")
+}
+
+fn collect_argument_from_args(args: &mut Vec<String>, missing: &str) -> Result<String, IoError> {
+    (!args.is_empty())
+        .then(|| args.remove(0))
+        .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, missing.to_owned()))
+}
+
+fn collect_message_id_from_args(args: &mut Vec<String>) -> Result<usize, IoError> {
+    (!args.is_empty())
+        .then(|| args.remove(0))
+        .and_then(|s| parse_message_id(&s))
         .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Invalid message ID"))
 }
 
-#[derive(Serialize)]
+/* Metadata is stored in the first column after the date as `;key=value` pairs. Dates never contain
+ * a semicolon so databases written before metadata was introduced are read unchanged.
+ */
+fn escape_metadata(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace(',', "%2C")
+        .replace(';', "%3B")
+        .replace('=', "%3D")
+}
+
+fn unescape_metadata(value: &str) -> String {
+    value
+        .replace("%3D", "=")
+        .replace("%3B", ";")
+        .replace("%2C", ",")
+        .replace("%25", "%")
+}
+
+/* Messages spanning multiple lines are stored with the `multiline` metadata, their new lines are
+ * written as `\n` and their backslashes as `\\` so that each message still takes one line.
+ */
+fn escape_message(message: &str) -> String {
+    message.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/* With `VENT_TXT_TRIM_STORED`, the trailing whitespace of every line of a message is dropped
+ * before it is written, whichever command wrote it.
+ */
+fn trim_stored_message(message: &str) -> String {
+    match get_trim_stored() {
+        true => message
+            .split('\n')
+            .map(str::trim_end)
+            .collect::<Vec<&str>>()
+            .join("\n"),
+        false => message.to_owned(),
+    }
+}
+
+fn unescape_message(message: &str) -> String {
+    let mut unescaped = String::with_capacity(message.len());
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+fn format_metadata(metadata: &[(&str, &str)]) -> String {
+    metadata
+        .iter()
+        .map(|(key, value)| format!(";{key}={}", escape_metadata(value)))
+        .collect()
+}
+
+/* Errors in the database are wrapped with the line they come from, which can be retrieved
+ * through `IoError::get_ref`.
+ */
+#[derive(Debug)]
+struct LineError {
+    line: usize,
+    error: IoError,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.error)
+    }
+}
+
+impl std::error::Error for LineError {}
+
+#[derive(Debug, Serialize, Deserialize)]
 struct Entry {
+    #[serde(default)]
+    id: usize,
     date: String,
     reply: Option<usize>,
     message: String,
+    prompt: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    /* Sensitive messages are stored with the `sensitive` metadata, templates hide them behind a
+     * spoiler.
+     */
+    #[serde(default)]
+    spoiler: bool,
+    #[serde(default)]
+    starred: bool,
+    /* Attachments are the names of files in the attachments directory, see `read_attachment`. */
+    #[serde(default)]
+    attachments: Vec<String>,
+    /* The reading time is derived from the message and thus never read back. */
+    #[serde(skip_deserializing)]
+    reading_time_seconds: usize,
+    /* Metadata which isn't understood by this version is kept here, a sorted map keeps the
+     * serialization of identical entries identical.
+     */
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
 }
 
 impl Entry {
     fn read_raw_entries() -> Result<Vec<String>, IoError> {
-        BufReader::new(File::open(get_csv_path())?)
+        Self::read_raw_entries_from(&get_csv_path())
+    }
+
+    /* The whole database is held in memory, the size limit guards against reading a runaway or
+     * corrupted file. It is read through a buffer, or from a memory map with `VENT_TXT_MMAP`
+     * which saves copying it, both give the same lines.
+     */
+    fn read_raw_entries_from(path: &str) -> Result<Vec<String>, IoError> {
+        let file = File::open(path)?;
+        if let Some(limit) = get_max_file_bytes() {
+            let size = file.metadata()?.len();
+            if size > limit {
+                return Err(IoError::new(
+                    ErrorKind::FileTooLarge,
+                    format!(
+                        "{path} is {size} bytes, more than the {limit} bytes allowed by VENT_TXT_MAX_FILE_BYTES, prune it or raise the limit"
+                    ),
+                ));
+            }
+        }
+        #[cfg(unix)]
+        if get_mmap() {
+            let mapping = mapped::Mapping::new(&file)?;
+            let text = std::str::from_utf8(mapping.bytes())
+                .map_err(|e| IoError::new(ErrorKind::InvalidData, e))?;
+            return Ok(text.lines().map(String::from).collect());
+        }
+        BufReader::with_capacity(get_read_buffer_size(), file)
             .lines()
             .collect()
     }
 
     fn read_entries() -> Result<Vec<Self>, IoError> {
-        let raw_entries = Self::read_raw_entries()?;
-        raw_entries.iter().map(|s| Self::parse_entry(s)).collect()
+        Ok(Self::apply_revisions(Self::parse_raw_entries(
+            &Self::read_raw_entries()?,
+        )?))
+    }
+
+    fn revision_of(&self) -> Option<usize> {
+        self.metadata
+            .get("revision_of")
+            .and_then(|id| id.parse().ok())
+            .filter(|&id| id < self.id)
+    }
+
+    /* Revisions are appended with the ID of the message they revise in their `revision_of`
+     * metadata and the latest one replaces its message, reply and tags. Revisions stay at their
+     * positions so that IDs are unchanged, filters hide them. Revisions of removed messages are
+     * ignored.
+     */
+    fn apply_revisions(mut entries: Vec<Self>) -> Vec<Self> {
+        let removed_text = get_removed_text();
+        for index in 0..entries.len() {
+            let Some(target) = entries[index].revision_of() else {
+                continue;
+            };
+            if entries[target].message == removed_text || entries[target].revision_of().is_some() {
+                continue;
+            }
+            let revision = &entries[index];
+            let (message, reply, tags, date) = (
+                revision.message.clone(),
+                revision.reply,
+                revision.tags.clone(),
+                revision.date.clone(),
+            );
+            let revised = &mut entries[target];
+            revised.reading_time_seconds = Self::reading_time_seconds(&message);
+            revised.message = message;
+            revised.reply = reply;
+            revised.tags = tags;
+            revised.metadata.insert(String::from("revised_at"), date);
+        }
+        entries
     }
 
-    fn parse_entry(raw_entry: &str) -> Result<Self, IoError> {
+    fn parse_raw_entries(raw_entries: &[String]) -> Result<Vec<Self>, IoError> {
+        raw_entries
+            .iter()
+            .enumerate()
+            .map(|(id, s)| {
+                Self::parse_entry(id, s).map_err(|error| {
+                    IoError::new(
+                        error.kind(),
+                        LineError {
+                            line: id + 1,
+                            error,
+                        },
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn parse_entry(id: usize, raw_entry: &str) -> Result<Self, IoError> {
         let date_end = raw_entry
             .find(',')
             .ok_or_else(|| IoError::new(ErrorKind::InvalidData, "No date in entry"))?;
 
-        let (date, message) = raw_entry.split_at(date_end);
+        let (header, message) = raw_entry.split_at(date_end);
         let message = &message[1..]; // We drop the separating comma
 
-        let (reply, message) =
-            if message.len() > 2 && message.is_char_boundary(2) && &message[..2] == ">>" {
-                let reply_end = message.find(' ').unwrap_or(message.len());
-                let reply_text = &message[2..reply_end];
-                let reply = reply_text.parse().ok();
-                let message_start = if reply.is_some() { reply_end } else { 0 };
-                (reply, &message[message_start..])
-            } else {
-                (None, message)
-            };
+        let mut header_fields = header.split(';');
+        let date = header_fields.next().unwrap_or_default();
+        let mut prompt = None;
+        let mut reply_field = None;
+        let mut spoiler = false;
+        let mut starred = false;
+        let mut multiline = false;
+        let mut attachments = Vec::new();
+        let mut metadata = BTreeMap::new();
+        for field in header_fields {
+            let (key, value) = field.split_once('=').unwrap_or((field, ""));
+            match key {
+                "prompt" => prompt = Some(unescape_metadata(value)),
+                "reply" => reply_field = Some(value.parse().ok()),
+                "sensitive" => spoiler = value == "1",
+                "starred" => starred = value == "1",
+                "multiline" => multiline = value == "1",
+                "attachments" => {
+                    attachments = unescape_metadata(value)
+                        .split(' ')
+                        .filter(|name| !name.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                }
+                _ => {
+                    metadata.insert(unescape_metadata(key), unescape_metadata(value));
+                }
+            }
+        }
+
+        // The space separating the reply marker from the message used to be kept in the message
+        let (reply, message) = match split_reply_marker(message) {
+            _ if reply_field.is_some() => (reply_field.flatten(), message),
+            Some((reply_text, message)) if get_trim_reply_whitespace() => {
+                (reply_text.parse().ok(), message.trim_start())
+            }
+            Some((reply_text, message)) => (reply_text.parse().ok(), message),
+            None => (None, message),
+        };
+        let message = match multiline {
+            true => unescape_message(message),
+            false => message.to_owned(),
+        };
 
         Ok(Entry {
+            id,
             date: date.to_owned(),
             reply,
-            message: message.to_owned(),
+            tags: Self::extract_tags(&message),
+            reading_time_seconds: Self::reading_time_seconds(&message),
+            message,
+            prompt,
+            spoiler,
+            starred,
+            attachments,
+            metadata,
         })
     }
+
+    /* Formatting is the inverse of parsing, the ID of the entry is its position in the database
+     * and isn't stored.
+     */
+    fn to_raw_entry(&self) -> Result<String, IoError> {
+        if self.date.contains([',', ';']) {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                "Invalid date in entry",
+            ));
+        }
+        let message = trim_stored_message(self.message.trim());
+        let mut metadata = Vec::new();
+        if message.contains('\n') {
+            metadata.push(("multiline", "1"));
+        }
+        let message = validate_message(escape_message(&message))?;
+        if let Some(prompt) = &self.prompt {
+            metadata.push(("prompt", prompt.as_str()));
+        }
+        if self.spoiler {
+            metadata.push(("sensitive", "1"));
+        }
+        if self.starred {
+            metadata.push(("starred", "1"));
+        }
+        let attachments = self.attachments.join(" ");
+        if !attachments.is_empty() {
+            metadata.push(("attachments", attachments.as_str()));
+        }
+        metadata.extend(self.metadata.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        let reply = self.reply_marker();
+        Ok(store_raw_entry_reply(&format!(
+            "{}{},{reply}{message}",
+            self.date,
+            format_metadata(&metadata)
+        )))
+    }
+
+    /* New messages are written with `VENT_TXT_LINE_FORMAT`, unlike `to_raw_entry` only the
+     * metadata known when adding a message is written.
+     */
+    fn to_new_raw_entry(&self) -> String {
+        let mut metadata = Vec::new();
+        if let Some(prompt) = &self.prompt {
+            metadata.push(("prompt", prompt.as_str()));
+        }
+        if let Some(anchor) = self.metadata.get("anchor") {
+            metadata.push(("anchor", anchor.as_str()));
+        }
+        if let Some(scheduled) = self.metadata.get("scheduled") {
+            metadata.push(("scheduled", scheduled.as_str()));
+        }
+        if self.spoiler {
+            metadata.push(("sensitive", "1"));
+        }
+        let attachments = self.attachments.join(" ");
+        if !attachments.is_empty() {
+            metadata.push(("attachments", attachments.as_str()));
+        }
+        format_raw_entry_line(
+            &self.date,
+            &format!("{}{}", self.reply_marker(), self.message),
+            &metadata,
+        )
+    }
+
+    // Replies without a message, see `VENT_TXT_ALLOW_EMPTY`, aren't followed by a space
+    fn reply_marker(&self) -> String {
+        match self.reply {
+            Some(reply) if self.message.trim().is_empty() => format!(">>{reply}"),
+            Some(reply) => format!(">>{reply} "),
+            None => String::new(),
+        }
+    }
+
+    /* Reading times are rounded up to the second, at `VENT_TXT_WPM` words per minute. */
+    fn reading_time_seconds(message: &str) -> usize {
+        (message.split_whitespace().count() * 60).div_ceil(get_words_per_minute())
+    }
+
+    /* Tags are words starting with `#` made of alphanumeric characters, `_` and `-`. They are
+     * case-insensitive and thus stored in lowercase.
+     */
+    fn extract_tags(message: &str) -> Vec<String> {
+        let mut tags = message
+            .split_whitespace()
+            .filter_map(|word| word.strip_prefix('#'))
+            .map(|tag| {
+                tag.chars()
+                    .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '-')
+                    .collect::<String>()
+                    .to_lowercase()
+            })
+            .filter(|tag| !tag.is_empty())
+            .collect::<Vec<String>>();
+        if get_dedupe_tags() {
+            let mut seen = HashSet::new();
+            tags.retain(|tag| seen.insert(tag.clone()));
+        }
+        tags
+    }
+}
+
+/* A message starting with `>>` followed by a number is a reply, the returned message keeps the
+ * space separating it from the reply marker. The number may be written `[5]` or `#5`, only its
+ * digits are returned.
+ */
+fn split_reply_marker(message: &str) -> Option<(&str, &str)> {
+    let marked = message.strip_prefix(">>")?;
+    let reply_end = marked.find(' ').unwrap_or(marked.len());
+    let reply_text = &marked[..reply_end];
+    let reply_text = reply_text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'))
+        .or_else(|| reply_text.strip_prefix('#'))
+        .unwrap_or(reply_text);
+    reply_text
+        .parse::<usize>()
+        .is_ok()
+        .then_some((reply_text, &marked[reply_end..]))
+}
+
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+
+/* The current time can be fixed with `VENT_TXT_NOW`, e.g. for reproducible builds of the
+ * rendered document, it is otherwise taken in the local timezone which follows `TZ`.
+ */
+fn get_now() -> DateTime<FixedOffset> {
+    env::var("VENT_TXT_NOW")
+        .ok()
+        .and_then(|now| parse_date(&now))
+        .unwrap_or_else(|| Local::now().fixed_offset())
 }
 
 fn format_local_time() -> String {
-    Local::now().format("%Y-%m-%d %H:%M:%S %z").to_string()
+    get_now().format(DATE_FORMAT).to_string()
 }
 
-fn add(message: &str) -> Result<(), IoError> {
-    let mut file = File::options()
-        .create(true)
-        .append(true)
-        .open(get_csv_path())?;
-    let date = format_local_time();
-    writeln!(file, "{date},{message}")?;
-    Ok(())
+/* Dates written by other tools are accepted as RFC 3339 timestamps, they are still displayed as
+ * they are stored.
+ */
+fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_str(date, DATE_FORMAT)
+        .or_else(|_| DateTime::parse_from_rfc3339(date))
+        .ok()
 }
 
-fn edit(message_id: usize, message: &str) -> Result<(), IoError> {
-    let mut entries = Entry::read_raw_entries()?;
-    let date = format_local_time();
-    match entries.get_mut(message_id) {
-        Some(s) => *s = format!("{date},{message}"),
-        None => {
-            return Err(IoError::new(
-                ErrorKind::InvalidInput,
-                "Out-of-bound message ID",
-            ))
-        }
-    }
+/* Counts the syncs done by the current thread so tests can tell they happened. */
+#[cfg(test)]
+thread_local! {
+    static SYNCS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
 
-    let mut file = File::options()
-        .truncate(true)
-        .write(true)
-        .open(get_csv_path())?;
-    for entry in entries.iter() {
-        writeln!(file, "{entry}")?;
+/* Writes are only guaranteed to survive a power failure once synced, which is slow enough to only
+ * be done when asked for.
+ */
+fn sync_database(file: &File) -> Result<(), IoError> {
+    if get_fsync() {
+        file.sync_all()?;
+        #[cfg(test)]
+        SYNCS.with(|syncs| syncs.set(syncs.get() + 1));
     }
     Ok(())
 }
 
-struct RenderIfReplyHelper;
-
-/* We don't use the built-in `if` helper as it follows javascript logic and will treat 0 as false.
- * This helper will treat null as false and any number as true.
- */
-impl hb::HelperDef for RenderIfReplyHelper {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        helper: &hb::Helper<'reg, 'rc>,
-        registry: &'reg Handlebars<'reg>,
-        context: &'rc hb::Context,
-        render_context: &mut hb::RenderContext<'reg, 'rc>,
-        out: &mut dyn hb::Output,
-    ) -> Result<(), RenderError> {
-        let value = helper
-            .param(0)
-            .ok_or_else(|| RenderError::new("Param not found for helper \"if_reply\""))?
-            .value();
-        if value.is_number() {
-            let template = helper
-                .template()
-                .ok_or_else(|| RenderError::new("Template not found for helper \"if_reply\""))?;
-            template.render(registry, context, render_context, out)
-        } else if value.is_null() {
-            Ok(())
-        } else {
-            Err(RenderError::new(
-                "Param of invalid type for helper \"if_reply\"",
-            ))
-        }
+/* A rename is only durable once the directory holding the renamed file is synced, directories
+ * can't be opened as files on Windows.
+ */
+fn sync_database_directory(path: &str) -> Result<(), IoError> {
+    if get_fsync() && cfg!(unix) {
+        let directory = match Path::new(path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(directory)?.sync_all()?;
+        #[cfg(test)]
+        SYNCS.with(|syncs| syncs.set(syncs.get() + 1));
     }
+    Ok(())
 }
 
-struct RenderEachReverseHelper;
+fn open_database_for_append() -> Result<File, IoError> {
+    open_for_append(&get_csv_path())
+}
+
+fn open_for_append(path: &str) -> Result<File, IoError> {
+    file_options().create(true).append(true).open(path)
+}
 
-/* We don't use the built-in `for_each` helper as we can't easily use to it to iterate backwards
- * while keeping the original indices in the `index` local variable.
+/* Files created by vent take the permissions given by `VENT_TXT_FILE_MODE`, still restricted by
+ * the umask. Other platforms than Unix don't have such modes and ignore it.
  */
-impl hb::HelperDef for RenderEachReverseHelper {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        helper: &hb::Helper<'reg, 'rc>,
-        registry: &'reg Handlebars<'reg>,
-        context: &'rc hb::Context,
-        render_context: &mut hb::RenderContext<'reg, 'rc>,
-        out: &mut dyn hb::Output,
-    ) -> Result<(), RenderError> {
-        let param = helper
-            .param(0)
-            .ok_or_else(|| RenderError::new("Param not found for helper \"each_reverse\""))?
-            .value();
-        let template = helper
-            .template()
-            .ok_or_else(|| RenderError::new("Template not found for helper \"each_reverse\""))?;
-        if param.is_array() {
-            for (index, value) in param.as_array().unwrap().iter().enumerate().rev() {
-                if let Some(block) = render_context.block_mut() {
-                    block.set_local_var("index", serde_json::json!(index));
-                    block.set_base_value(value.clone());
-                }
-                template.render(registry, context, render_context, out)?
-            }
-            Ok(())
-        } else {
-            Err(RenderError::new(
-                "Param of invalid type for helper \"each_reverse\"",
-            ))
-        }
+fn file_options() -> fs::OpenOptions {
+    #[allow(unused_mut)]
+    let mut options = File::options();
+    #[cfg(unix)]
+    if let Some(mode) = get_file_mode() {
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
     }
+    options
 }
 
-fn render<W>(writer: W, entries: &Vec<Entry>) -> Result<(), RenderError>
-where
-    W: io::Write,
-{
-    let template_name = "template";
-    let mut handlebars = Handlebars::new();
-    handlebars.register_template_file(template_name, get_template_path())?;
-    handlebars.register_helper("if_reply", Box::new(RenderIfReplyHelper));
-    handlebars.register_helper("each_reverse", Box::new(RenderEachReverseHelper));
-    handlebars.render_to_write(template_name, &entries, writer)
+fn create_file<P: AsRef<Path>>(path: P) -> Result<File, IoError> {
+    file_options()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
 }
 
-fn usage(program_name: &str) -> ! {
-    eprintln!("Usage: {program_name} add [message]");
-    eprintln!("       {program_name} add '>>[reply id]' [message]");
-    eprintln!("       {program_name} edit [message id] [message]");
-    eprintln!("       {program_name} rm [message id]");
-    eprintln!("       {program_name} render");
-    eprintln!();
-    eprintln!("Environment: VENT_TXT_CSV    Vent database location");
-    eprintln!("                             (default: 'vent.csv')");
-    eprintln!("             VENT_TXT_HBS    Render template");
-    eprintln!("                             (default: 'template/vent.hbs')");
-    std::process::exit(1)
+/* Appended lines are gathered and written in batches of `flush_every` lines, or all at once at the
+ * end without it. A batch is written and synced with whole lines only, so an interrupted bulk add
+ * never leaves a partial line behind in the database.
+ */
+struct BatchWriter {
+    file: File,
+    batch: String,
+    pending: usize,
+    flush_every: Option<usize>,
 }
 
-fn main() -> Result<(), IoError> {
-    let mut args = env::args();
-    let program_name = args.next().unwrap();
-    let action = args.next().unwrap_or_else(|| usage(&program_name));
+impl BatchWriter {
+    fn new(file: File, flush_every: Option<usize>) -> Self {
+        BatchWriter {
+            file,
+            batch: String::new(),
+            pending: 0,
+            flush_every,
+        }
+    }
 
-    match action.as_str() {
-        "add" => add(collect_message_from_args(args)?.as_str()),
-        "edit" => {
-            let message_id = collect_message_id_from_args(&mut args)?;
-            let message = collect_message_from_args(args)?;
-            edit(message_id, &message)
+    fn push(&mut self, raw_entry: &str) -> Result<(), IoError> {
+        self.batch.push_str(raw_entry);
+        self.batch.push('\n');
+        self.pending += 1;
+        if self.flush_every.is_some_and(|n| self.pending >= n) {
+            self.flush()?;
         }
-        "rm" => {
-            let message_id = collect_message_id_from_args(&mut args)?;
-            edit(message_id, "[removed]")
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        if self.pending > 0 {
+            self.file.write_all(self.batch.as_bytes())?;
+            sync_database(&self.file)?;
+            self.batch.clear();
+            self.pending = 0;
         }
-        "render" => match render(io::stdout(), &Entry::read_entries()?) {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                eprintln!("{e}");
-                if let Some(es) = e.source() {
-                    eprintln!("{:?}", es);
-                }
-                Err(IoError::new(ErrorKind::Other, "Render error"))
-            }
-        },
-        _ => usage(&program_name),
+        Ok(())
+    }
+}
+
+fn parse_flush_every(options: &Options) -> Result<Option<usize>, IoError> {
+    options
+        .value("--flush-every")
+        .map(|n| {
+            n.parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Invalid batch size"))
+        })
+        .transpose()
+}
+
+const DEFAULT_TEMPLATE: &str = include_str!("../template/vent.hbs");
+
+/* Nothing is created unless every file can be, so a refused init leaves the directory untouched.
+ */
+fn init(force: bool) -> Result<(), IoError> {
+    let csv_path = get_csv_path();
+    let template_path = get_template_path();
+    let files = [(&csv_path, ""), (&template_path, DEFAULT_TEMPLATE)];
+    if !force {
+        if let Some((path, _)) = files.iter().find(|(path, _)| Path::new(path).exists()) {
+            return Err(IoError::new(
+                ErrorKind::AlreadyExists,
+                format!("{path} already exists, use --force to overwrite it"),
+            ));
+        }
+    }
+    for (path, contents) in files {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        create_file(path)?.write_all(contents.as_bytes())?;
+        println!("Created {path}");
+    }
+    Ok(())
+}
+
+fn format_raw_entry_line(date: &str, message: &str, metadata: &[(&str, &str)]) -> String {
+    let mut metadata = format_metadata(metadata);
+    if message.contains('\n') {
+        metadata.push_str(&format_metadata(&[("multiline", "1")]));
+    }
+    store_raw_entry_reply(&format_line(
+        &get_line_format(),
+        date,
+        &metadata,
+        &escape_message(&trim_stored_message(message)),
+    ))
+}
+
+/* Line formats replace `{date}`, `{metadata}` and `{message}` in a single pass so that a message
+ * containing one of them is written as is. Anything else is written literally, e.g. fixed
+ * metadata in `{date};source=phone{metadata},{message}`.
+ */
+fn format_line(format: &str, date: &str, metadata: &str, message: &str) -> String {
+    let mut line = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('{') {
+        line.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let (value, length) = [
+            ("{date}", date),
+            ("{metadata}", metadata),
+            ("{message}", message),
+        ]
+        .iter()
+        .find(|(placeholder, _)| rest.starts_with(placeholder))
+        .map_or(("{", 1), |(placeholder, value)| (value, placeholder.len()));
+        line.push_str(value);
+        rest = &rest[length..];
+    }
+    line.push_str(rest);
+    line
+}
+
+/* The line format is checked when vent starts, a line written with it must be read back as the
+ * message, date and prompt it was formatted from.
+ */
+fn validate_line_format() -> Result<(), IoError> {
+    let date = "2000-01-01 00:00:00 +0000";
+    let line = format_line(
+        &get_line_format(),
+        date,
+        &format_metadata(&[("prompt", "prompt")]),
+        "message",
+    );
+    match Entry::parse_entry(0, &line) {
+        Ok(entry)
+            if entry.date == date
+                && entry.message == "message"
+                && entry.prompt.as_deref() == Some("prompt") =>
+        {
+            Ok(())
+        }
+        _ => Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid line format, {line} can't be read back"),
+        )),
+    }
+}
+
+/* Confirmation reads the answer from the terminal, non-interactive uses have to confirm upfront
+ * with `--yes`.
+ */
+fn confirm_raw_entry(raw_entry: &str, yes: bool) -> Result<bool, IoError> {
+    eprintln!("{raw_entry}");
+    if yes {
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "Confirmation requires a terminal, use --yes",
+        ));
+    }
+    eprint!("Add this message? [y/N] ");
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(confirm_answer(&answer))
+}
+
+fn confirm_answer(answer: &str) -> bool {
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/* Scheduled messages are stored with a `scheduled` date in their metadata and hidden until
+ * `publish-due` is run after that date.
+ */
+fn parse_publish_date(date: &str) -> Result<String, IoError> {
+    NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M")
+        .ok()
+        .and_then(|d| Local.from_local_datetime(&d).single())
+        .map(|d| d.format(DATE_FORMAT).to_string())
+        .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Invalid publish date"))
+}
+
+fn is_scheduled(entry: &Entry) -> bool {
+    entry.metadata.contains_key("scheduled")
+}
+
+/* A missing database renders as an empty one when the pipeline always expects output. */
+fn read_entries_or_empty(null_data_ok: bool) -> Result<Vec<Entry>, IoError> {
+    match Entry::read_entries() {
+        Err(e) if e.kind() == ErrorKind::NotFound && null_data_ok => Ok(Vec::new()),
+        entries => entries,
+    }
+}
+
+/* Revisions and scheduled messages are never shown, the other messages are shown unless they
+ * are filtered out.
+ */
+fn count_shown_entries(entries: &[Entry]) -> usize {
+    entries
+        .iter()
+        .filter(|e| !is_scheduled(e) && e.revision_of().is_none())
+        .count()
+}
+
+/* The clipboard is read through the usual command line tools of each platform, or the command in
+ * `VENT_TXT_CLIPBOARD` when set.
+ */
+fn read_clipboard() -> Result<String, IoError> {
+    let command = match env::var("VENT_TXT_CLIPBOARD") {
+        Ok(command) if !command.is_empty() => vec![String::from("sh"), String::from("-c"), command],
+        _ if cfg!(target_os = "macos") => vec![String::from("pbpaste")],
+        _ if cfg!(target_os = "windows") => {
+            ["powershell", "-NoProfile", "-Command", "Get-Clipboard"]
+                .map(String::from)
+                .to_vec()
+        }
+        _ if env::var_os("WAYLAND_DISPLAY").is_some() => {
+            ["wl-paste", "--no-newline"].map(String::from).to_vec()
+        }
+        _ if env::var_os("DISPLAY").is_some() => ["xclip", "-selection", "clipboard", "-o"]
+            .map(String::from)
+            .to_vec(),
+        _ => {
+            return Err(IoError::new(
+                ErrorKind::NotFound,
+                "No clipboard available, is a graphical session running?",
+            ))
+        }
+    };
+    let output = Command::new(&command[0])
+        .args(&command[1..])
+        .output()
+        .map_err(|e| IoError::new(e.kind(), format!("Unable to read the clipboard ({e})")))?;
+    if !output.status.success() {
+        return Err(IoError::other(format!(
+            "Clipboard command exited with {}",
+            output.status
+        )));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| IoError::new(ErrorKind::InvalidData, "Clipboard isn't valid UTF-8"))
+}
+
+/* Attachments are named after the SHA-256 of their content followed by their extension, so that
+ * attaching the same file twice stores it once and its name never changes.
+ */
+fn read_attachment(path: &str) -> Result<(String, Vec<u8>), IoError> {
+    let content = fs::read(path)
+        .map_err(|e| IoError::new(e.kind(), format!("Unable to read attachment {path}: {e}")))?;
+    let extension = Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .filter(|e| e.chars().all(|c| c.is_ascii_alphanumeric()))
+        .map(|e| format!(".{e}"))
+        .unwrap_or_default();
+    let name = format!("{:x}{extension}", Sha256::digest(&content));
+    Ok((name, content))
+}
+
+fn store_attachment(name: &str, content: &[u8]) -> Result<(), IoError> {
+    let directory = get_attachments_path();
+    fs::create_dir_all(&directory)?;
+    let path = Path::new(&directory).join(name);
+    if !path.exists() {
+        let temporary_path = Path::new(&directory).join(format!("{name}.tmp"));
+        create_file(&temporary_path)?.write_all(content)?;
+        fs::rename(&temporary_path, path)?;
+    }
+    Ok(())
+}
+
+/* Adding to a database which doesn't exist yet creates it. */
+fn read_entries_for_add() -> Result<Vec<Entry>, IoError> {
+    match Entry::read_entries() {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        entries => entries,
+    }
+}
+
+fn add(mut entry: Entry, attachments: &[String], confirm: bool, yes: bool) -> Result<(), IoError> {
+    let attachments = attachments
+        .iter()
+        .map(|path| read_attachment(path))
+        .collect::<Result<Vec<(String, Vec<u8>)>, IoError>>()?;
+    entry.attachments = attachments.iter().map(|(name, _)| name.clone()).collect();
+    let raw_entry = entry.to_new_raw_entry();
+    if confirm && !confirm_raw_entry(&raw_entry, yes)? {
+        eprintln!("Message not added");
+        return Ok(());
+    }
+    // Attachments are stored before the message so that it never links to a missing file
+    for (name, content) in attachments.iter() {
+        store_attachment(name, content)?;
+    }
+    let mut file = open_database_for_append()?;
+    writeln!(file, "{raw_entry}")?;
+    sync_database(&file)
+}
+
+/* Each line is added as soon as it is read so that live pipes are handled, invalid lines are
+ * reported and skipped.
+ */
+fn add_stream<R: BufRead>(
+    input: R,
+    prompt: Option<&str>,
+    flush_every: usize,
+) -> Result<(), IoError> {
+    let mut entries = read_entries_for_add()?;
+    let mut writer = BatchWriter::new(open_database_for_append()?, Some(flush_every));
+    let mut count = 0;
+    for (line_number, line) in input.lines().enumerate() {
+        match EntryBuilder::new(&line?).prompt(prompt).build(&entries) {
+            Ok(mut entry) => {
+                render::store_anchor(&mut entry, &entries);
+                writer.push(&entry.to_new_raw_entry())?;
+                entries.push(entry);
+                count += 1;
+            }
+            Err(e) => eprintln!("Line {}: {e}", line_number + 1),
+        }
+    }
+    writer.flush()?;
+    eprintln!("Added {count} messages");
+    Ok(())
+}
+
+fn replace_raw_entry<F>(message_id: usize, replace: F) -> Result<(), IoError>
+where
+    F: FnOnce(&str) -> Result<String, IoError>,
+{
+    let mut entries = Entry::read_raw_entries()?;
+    match entries.get_mut(message_id) {
+        Some(s) => *s = replace(s)?,
+        None => {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "Out-of-bound message ID",
+            ))
+        }
+    }
+    write_raw_entries(&entries)
+}
+
+/* The database is replaced atomically by writing a temporary file next to it and renaming it
+ * over the database, an interrupted write can't leave a partial database behind. Renaming fails
+ * across filesystems, e.g. when the database itself is a bind mount, in which case the temporary
+ * file is copied over the database and synced instead, which isn't atomic anymore.
+ */
+fn write_raw_entries(entries: &[String]) -> Result<(), IoError> {
+    write_raw_entries_to(&get_csv_path(), entries)
+}
+
+fn write_raw_entries_to(path: &str, entries: &[String]) -> Result<(), IoError> {
+    let temporary_path = format!("{path}.tmp");
+    let mut file = BufWriter::new(create_file(&temporary_path)?);
+    for entry in entries.iter() {
+        writeln!(file, "{entry}")?;
+    }
+    sync_database(&file.into_inner()?)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(&temporary_path, metadata.permissions())?;
+    }
+    match fs::rename(&temporary_path, path) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => copy_over(&temporary_path, path),
+        result => result,
+    }?;
+    sync_database_directory(path)
+}
+
+fn copy_over(temporary_path: &str, path: &str) -> Result<(), IoError> {
+    fs::copy(temporary_path, path)?;
+    File::open(path)?.sync_all()?;
+    fs::remove_file(temporary_path)
+}
+
+/* Replies are stored inline as a `>>N` marker at the start of the message or, with
+ * `VENT_TXT_REPLY_STORAGE=metadata`, in a `reply` metadata field which leaves the message as it
+ * was typed. Both forms are always read, the reply is rewritten in the configured one.
+ */
+fn set_raw_entry_reply(raw_entry: &str, reply: Option<usize>) -> String {
+    let (header, message) = raw_entry.split_once(',').unwrap_or_default();
+    let has_reply_field = header.split(';').skip(1).any(|f| f.starts_with("reply="));
+    let header = header
+        .split(';')
+        .filter(|f| !f.starts_with("reply="))
+        .collect::<Vec<&str>>()
+        .join(";");
+    // The text following an inline marker keeps its separating space, if any
+    let (marked, body) = match split_reply_marker(message) {
+        Some((_, rest)) if !has_reply_field => (true, rest),
+        _ => (false, message),
+    };
+    match reply {
+        Some(reply) if get_reply_metadata() => {
+            format!("{header};reply={reply},{}", body.trim_start())
+        }
+        Some(reply) if marked => format!("{header},>>{reply}{body}"),
+        Some(reply) => format!("{header},>>{reply} {body}"),
+        None => format!("{header},{}", body.trim_start()),
+    }
+}
+
+/* New and edited messages are typed with an inline reply marker which replaces any stored reply. */
+fn store_raw_entry_reply(raw_entry: &str) -> String {
+    let (header, message) = raw_entry.split_once(',').unwrap_or_default();
+    let header = header
+        .split(';')
+        .filter(|f| !f.starts_with("reply="))
+        .collect::<Vec<&str>>()
+        .join(";");
+    match split_reply_marker(message) {
+        Some((reply_text, _)) => {
+            set_raw_entry_reply(&format!("{header},{message}"), reply_text.parse().ok())
+        }
+        None => format!("{header},{message}"),
+    }
+}
+
+/* Dry runs print the changes a command would make to the database instead of writing it, as
+ * sentences or as a JSON array of changes for scripts.
+ */
+#[derive(Clone, Copy, PartialEq)]
+enum DryRun {
+    Off,
+    Text,
+    Json,
+}
+
+impl DryRun {
+    const FLAGS: [&'static str; 2] = ["--dry-run", "--dry-run-json"];
+
+    fn from_options(options: &Options) -> Self {
+        if options.flag("--dry-run-json") {
+            DryRun::Json
+        } else if options.flag("--dry-run") {
+            DryRun::Text
+        } else {
+            DryRun::Off
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PlannedChange<'a> {
+    action: &'static str,
+    id: usize,
+    old: &'a str,
+    new: Option<&'a str>,
+}
+
+/* Changes are given with the IDs of the messages before the change. Without `new_ids` the
+ * messages keep their position, otherwise it maps them to their new position or `None` when they
+ * are deleted.
+ */
+fn plan_changes<'a>(
+    old: &'a [String],
+    new: &'a [String],
+    new_ids: Option<&[Option<usize>]>,
+) -> Vec<PlannedChange<'a>> {
+    old.iter()
+        .enumerate()
+        .filter_map(|(id, old_line)| {
+            let new_id = match new_ids {
+                Some(new_ids) => new_ids[id],
+                None => Some(id),
+            };
+            let (action, new_line) = match new_id.map(|new_id| new[new_id].as_str()) {
+                Some(new_line) if new_line == old_line => return None,
+                Some(new_line) => ("edit", Some(new_line)),
+                None => ("delete", None),
+            };
+            Some(PlannedChange {
+                action,
+                id: display_message_id(id),
+                old: old_line,
+                new: new_line,
+            })
+        })
+        .collect()
+}
+
+fn write_plan<W: Write>(
+    mut writer: W,
+    plan: &[PlannedChange],
+    dry_run: DryRun,
+) -> Result<(), IoError> {
+    if dry_run == DryRun::Json {
+        serde_json::to_writer_pretty(&mut writer, plan)?;
+        return writeln!(writer);
+    }
+    for change in plan.iter() {
+        match change.new {
+            Some(new) => writeln!(writer, "Message {} would be rewritten as {new}", change.id)?,
+            None => writeln!(writer, "Message {} would be deleted", change.id)?,
+        }
+    }
+    Ok(())
+}
+
+/* Migrating rewrites every reply in the storage form selected by `VENT_TXT_REPLY_STORAGE`. */
+fn migrate_replies(dry_run: DryRun) -> Result<(), IoError> {
+    let raw_entries = Entry::read_raw_entries()?;
+    let entries = Entry::parse_raw_entries(&raw_entries)?;
+    let migrated = raw_entries
+        .iter()
+        .zip(entries.iter())
+        .map(|(raw_entry, entry)| match entry.reply {
+            Some(reply) => set_raw_entry_reply(raw_entry, Some(reply)),
+            None => raw_entry.clone(),
+        })
+        .collect::<Vec<String>>();
+    let count = migrated
+        .iter()
+        .zip(raw_entries.iter())
+        .filter(|(a, b)| a != b)
+        .count();
+    if dry_run != DryRun::Off {
+        return write_plan(
+            io::stdout(),
+            &plan_changes(&raw_entries, &migrated, None),
+            dry_run,
+        );
+    }
+    if count > 0 {
+        write_raw_entries(&migrated)?;
+    }
+    println!("Migrated {count} replies");
+    Ok(())
+}
+
+fn is_removed_raw_entry(raw_entry: &str) -> bool {
+    raw_entry.split_once(',').map(|(_, message)| message) == Some(get_removed_text().as_str())
+}
+
+/* The original message is kept untouched, `show --history` lists every version. */
+fn edit_revision(message_id: usize, message: &str) -> Result<(), IoError> {
+    let raw_entries = Entry::read_raw_entries()?;
+    let entries = Entry::parse_raw_entries(&raw_entries)?;
+    let entry = entries
+        .get(message_id)
+        .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Out-of-bound message ID"))?;
+    if is_removed_raw_entry(&raw_entries[message_id]) || entry.revision_of().is_some() {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Message {} can't be revised",
+                display_message_id(message_id)
+            ),
+        ));
+    }
+    let prompt = entry.prompt.clone();
+    let revision = EntryBuilder::new(message)
+        .id(message_id)
+        .prompt(prompt.as_deref())
+        .build(&Entry::apply_revisions(entries))?;
+    let raw_entry = revision.to_new_raw_entry();
+    let (header, message) = raw_entry.split_once(',').unwrap_or_default();
+    let revision_of = format_metadata(&[("revision_of", &message_id.to_string())]);
+    let mut file = open_for_append(&get_csv_path())?;
+    writeln!(file, "{header}{revision_of},{message}")?;
+    sync_database(&file)
+}
+
+fn edit(message_id: usize, message: &str, restore: bool) -> Result<(), IoError> {
+    let date = format_local_time();
+    let entries = Entry::parse_raw_entries(&Entry::read_raw_entries()?)?;
+    // Overwriting a revised message would be hidden by its latest revision
+    if entries.iter().any(|e| e.revision_of() == Some(message_id)) {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Message {} has revisions, use --revision to edit it",
+                display_message_id(message_id)
+            ),
+        ));
+    }
+    let entries = Entry::apply_revisions(entries);
+    if message_id >= entries.len() {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "Out-of-bound message ID",
+        ));
+    }
+    let edited = EntryBuilder::new(message).id(message_id).build(&entries)?;
+    // A message without a stored anchor would derive another one from its new date
+    let stored_anchor = entries[message_id].metadata.contains_key("anchor");
+    let anchor = match get_stable_anchors() && !stored_anchor {
+        true => render::stable_anchors(&entries).remove(&message_id),
+        false => None,
+    };
+    let message = trim_stored_message(&format!("{}{}", edited.reply_marker(), edited.message));
+    replace_raw_entry(message_id, |raw_entry| {
+        // Editing a removed message would silently bring it back
+        if is_removed_raw_entry(raw_entry) && !restore {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Message {} was removed, use --restore to edit it",
+                    display_message_id(message_id)
+                ),
+            ));
+        }
+        // Metadata such as the prompt of the entry is kept as-is
+        let header = raw_entry.split(',').next().unwrap_or_default();
+        let mut fields = header.split(';');
+        let original_date = fields.next().unwrap_or_default();
+        if get_edit_preserves_date() {
+            let metadata = fields
+                .filter(|field| !field.starts_with("edited_at="))
+                .map(|field| format!(";{field}"))
+                .collect::<String>();
+            let edited_at = format_metadata(&[("edited_at", &date)]);
+            Ok(store_raw_entry_reply(&format!(
+                "{original_date}{metadata}{edited_at},{message}"
+            )))
+        } else {
+            let metadata = header.find(';').map(|i| &header[i..]).unwrap_or_default();
+            let anchor = anchor.as_ref().map_or(String::new(), |anchor| {
+                format_metadata(&[("anchor", anchor)])
+            });
+            Ok(store_raw_entry_reply(&format!(
+                "{date}{metadata}{anchor},{message}"
+            )))
+        }
+    })
+}
+
+/* Published messages take their scheduled date so they appear as if they were added then. */
+fn publish_due() -> Result<(), IoError> {
+    let mut raw_entries = Entry::read_raw_entries()?;
+    let now = get_now();
+    let mut count = 0;
+    for (id, raw_entry) in raw_entries.iter_mut().enumerate() {
+        let entry = Entry::parse_entry(id, raw_entry)?;
+        let scheduled = match entry.metadata.get("scheduled").and_then(|d| parse_date(d)) {
+            Some(scheduled) if scheduled <= now => entry.metadata["scheduled"].clone(),
+            _ => continue,
+        };
+        let (header, message) = raw_entry.split_once(',').unwrap_or_default();
+        let metadata = header
+            .split(';')
+            .skip(1)
+            .filter(|field| !field.starts_with("scheduled="))
+            .map(|field| format!(";{field}"))
+            .collect::<String>();
+        *raw_entry = format!("{scheduled}{metadata},{message}");
+        count += 1;
+    }
+    if count > 0 {
+        write_raw_entries(&raw_entries)?;
+    }
+    println!("Published {count} messages");
+    Ok(())
+}
+
+fn remove(message_id: usize) -> Result<(), IoError> {
+    let date = format_local_time();
+    let removed_text = get_removed_text();
+    replace_raw_entry(message_id, |_| Ok(format!("{date},{removed_text}")))
+}
+
+/* Replaces the metadata fields named `key` by a single one at the end of the header, or drops
+ * them without a value.
+ */
+fn set_raw_entry_field(raw_entry: &str, key: &str, value: Option<&str>) -> String {
+    let (header, message) = raw_entry.split_once(',').unwrap_or_default();
+    let mut header = header
+        .split(';')
+        .enumerate()
+        .filter(|(index, field)| *index == 0 || field.split('=').next() != Some(key))
+        .map(|(_, field)| field)
+        .collect::<Vec<&str>>()
+        .join(";");
+    if let Some(value) = value {
+        header.push_str(&format_metadata(&[(key, value)]));
+    }
+    format!("{header},{message}")
+}
+
+fn star(message_id: usize, starred: bool) -> Result<(), IoError> {
+    replace_raw_entry(message_id, |raw_entry| {
+        Ok(set_raw_entry_field(
+            raw_entry,
+            "starred",
+            starred.then_some("1"),
+        ))
+    })
+}
+
+/* Soft pruning removes messages but keeps their dates and IDs, hard pruning deletes them from the
+ * database. The later changes the IDs of the following messages so replies are renumbered, replies
+ * to deleted messages become top-level messages.
+ */
+fn prune(before: NaiveDate, hard: bool, apply: bool, dry_run: DryRun) -> Result<(), IoError> {
+    let raw_entries = Entry::read_raw_entries()?;
+    let entries = Entry::parse_raw_entries(&raw_entries)?;
+    let mut pruned = entries
+        .iter()
+        .zip(raw_entries.iter())
+        .map(|(entry, raw_entry)| {
+            parse_date(&entry.date).is_some_and(|d| d.date_naive() < before)
+                && (hard || !is_removed_raw_entry(raw_entry))
+        })
+        .collect::<Vec<bool>>();
+    // Revisions are deleted with their message, they are already ignored once it is removed
+    if hard {
+        for entry in entries.iter() {
+            if let Some(target) = entry.revision_of() {
+                pruned[entry.id] |= pruned[target];
+            }
+        }
+    }
+    let count = pruned.iter().filter(|&&p| p).count();
+    if !apply && dry_run == DryRun::Off {
+        println!("{count} messages would be pruned, use --yes to apply");
+        return Ok(());
+    }
+
+    let mut next_id = 0;
+    let new_ids = pruned
+        .iter()
+        .map(|&p| {
+            (!(hard && p)).then(|| {
+                next_id += 1;
+                next_id - 1
+            })
+        })
+        .collect::<Vec<Option<usize>>>();
+    let pruned_entries = if hard {
+        raw_entries
+            .iter()
+            .zip(entries.iter())
+            .zip(pruned.iter())
+            .filter(|(_, &p)| !p)
+            .map(|((raw_entry, entry), _)| {
+                let raw_entry = match entry.reply.map(|reply| new_ids.get(reply)) {
+                    Some(Some(&reply)) => set_raw_entry_reply(raw_entry, reply),
+                    _ => raw_entry.clone(),
+                };
+                match entry.revision_of().and_then(|target| new_ids[target]) {
+                    Some(target) => {
+                        set_raw_entry_field(&raw_entry, "revision_of", Some(&target.to_string()))
+                    }
+                    None => raw_entry,
+                }
+            })
+            .collect::<Vec<String>>()
+    } else {
+        raw_entries
+            .iter()
+            .zip(pruned.iter())
+            .map(|(raw_entry, &p)| {
+                if p {
+                    let date = raw_entry.split([',', ';']).next().unwrap_or_default();
+                    format!("{date},{}", get_removed_text())
+                } else {
+                    raw_entry.clone()
+                }
+            })
+            .collect::<Vec<String>>()
+    };
+    if dry_run != DryRun::Off {
+        let plan = plan_changes(&raw_entries, &pruned_entries, Some(&new_ids));
+        return write_plan(io::stdout(), &plan, dry_run);
+    }
+    write_raw_entries(&pruned_entries)?;
+    println!("Pruned {count} messages");
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct TagSummary<'a> {
+    tag: &'a str,
+    count: usize,
+    co_occurrences: BTreeMap<&'a str, usize>,
+}
+
+#[derive(Clone, Copy)]
+enum TagSort {
+    Count,
+    Alpha,
+    AlphaDesc,
+}
+
+/* Co-occurrences count the messages in which two tags both appear, regardless of how many times
+ * they are repeated in them.
+ */
+fn tag_summaries(entries: &[Entry], sort: TagSort, min_count: usize) -> Vec<TagSummary<'_>> {
+    let mut summaries = HashMap::<&str, TagSummary>::new();
+    for entry in entries.iter() {
+        for tag in entry.tags.iter() {
+            summaries
+                .entry(tag)
+                .or_insert_with(|| TagSummary {
+                    tag,
+                    count: 0,
+                    co_occurrences: BTreeMap::new(),
+                })
+                .count += 1;
+        }
+        let unique_tags = entry.tags.iter().collect::<HashSet<&String>>();
+        for tag in unique_tags.iter() {
+            let summary = summaries.get_mut(tag.as_str()).unwrap();
+            for other in unique_tags.iter().filter(|other| *other != tag) {
+                *summary.co_occurrences.entry(other).or_default() += 1;
+            }
+        }
+    }
+    let mut summaries = summaries
+        .into_values()
+        .filter(|summary| summary.count >= min_count)
+        .collect::<Vec<TagSummary>>();
+    match sort {
+        TagSort::Count => summaries.sort_by(|a, b| b.count.cmp(&a.count).then(a.tag.cmp(b.tag))),
+        TagSort::Alpha => summaries.sort_by(|a, b| a.tag.cmp(b.tag)),
+        TagSort::AlphaDesc => summaries.sort_by(|a, b| b.tag.cmp(a.tag)),
+    }
+    summaries
+}
+
+fn tags(entries: &[Entry], sort: TagSort, min_count: usize, json: bool) -> Result<(), IoError> {
+    let summaries = tag_summaries(entries, sort, min_count);
+    if json {
+        serde_json::to_writer_pretty(io::stdout(), &summaries)?;
+        println!();
+    } else {
+        for summary in summaries {
+            println!("{} {}", summary.count, summary.tag);
+        }
+    }
+    Ok(())
+}
+
+/* Orphaned replies point to a message which doesn't exist, e.g. after a hard prune, or which was
+ * removed.
+ */
+fn orphaned_reply(entry: &Entry, raw_entries: &[String]) -> Option<&'static str> {
+    match entry.reply {
+        Some(reply) if reply >= raw_entries.len() => Some("replies to a non-existent message"),
+        Some(reply) if is_removed_raw_entry(&raw_entries[reply]) => {
+            Some("replies to a removed message")
+        }
+        _ => None,
+    }
+}
+
+/* Replies to removed or non-existent messages, and to themselves, are broken. Fixing them turns
+ * them into top-level messages, lines which can't be parsed are only reported.
+ */
+fn verify(fix: bool) -> Result<(), IoError> {
+    let mut raw_entries = Entry::read_raw_entries()?;
+    let mut problems = 0;
+    let mut fixes = 0;
+    for id in 0..raw_entries.len() {
+        let entry = match Entry::parse_entry(id, &raw_entries[id]) {
+            Ok(entry) => entry,
+            Err(e) => {
+                println!("Line {}: {e}", id + 1);
+                problems += 1;
+                continue;
+            }
+        };
+        let problem = match entry.reply {
+            Some(reply) if reply == id => "replies to itself",
+            _ => match orphaned_reply(&entry, &raw_entries) {
+                Some(problem) => problem,
+                None => continue,
+            },
+        };
+        let display_id = display_message_id(id);
+        if fix {
+            raw_entries[id] = set_raw_entry_reply(&raw_entries[id], None);
+            println!("Message {display_id} {problem}, fixed as a top-level message");
+            fixes += 1;
+        } else {
+            println!("Message {display_id} {problem}");
+            problems += 1;
+        }
+    }
+
+    if fixes > 0 {
+        write_raw_entries(&raw_entries)?;
+    }
+    if problems > 0 {
+        Err(IoError::new(
+            ErrorKind::InvalidData,
+            match problems {
+                1 => String::from("1 problem found"),
+                _ => format!("{problems} problems found"),
+            },
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/* Unlike `verify`, only orphaned replies are looked at and unparseable lines are errors. A dry run
+ * prints the changes `--fix` would make.
+ */
+fn orphans(fix: bool, dry_run: DryRun) -> Result<(), IoError> {
+    let raw_entries = Entry::read_raw_entries()?;
+    let entries = Entry::parse_raw_entries(&raw_entries)?;
+    let orphans = entries
+        .iter()
+        .filter(|e| orphaned_reply(e, &raw_entries).is_some())
+        .collect::<Vec<&Entry>>();
+    let mut fixed_entries = raw_entries.clone();
+    for entry in orphans.iter() {
+        fixed_entries[entry.id] = set_raw_entry_reply(&raw_entries[entry.id], None);
+    }
+    if dry_run != DryRun::Off {
+        return write_plan(
+            io::stdout(),
+            &plan_changes(&raw_entries, &fixed_entries, None),
+            dry_run,
+        );
+    }
+    for entry in orphans.iter() {
+        print_entry(entry, "");
+    }
+    if fix && !orphans.is_empty() {
+        write_raw_entries(&fixed_entries)?;
+        println!("Fixed {} orphaned replies", orphans.len());
+    }
+    Ok(())
+}
+
+/* Revisions belong to the message they revise and aren't part of the threads. Without `--list`,
+ * only the totals are printed.
+ */
+fn threads(list: bool) -> Result<(), IoError> {
+    let raw_entries = Entry::read_raw_entries()?;
+    let entries = Entry::parse_raw_entries(&raw_entries)?
+        .into_iter()
+        .filter(|e| e.revision_of().is_none())
+        .collect::<Vec<Entry>>();
+    let threads = threads::reply_threads(&entries);
+    let mut cycles = 0;
+    let mut orphans = 0;
+    for thread in threads.iter() {
+        let root = &entries[thread.root];
+        let mut anomalies = Vec::new();
+        if thread.cycle {
+            anomalies.push("cycle");
+            cycles += 1;
+        }
+        if let Some(problem) = orphaned_reply(root, &raw_entries) {
+            anomalies.push(problem);
+            orphans += 1;
+        }
+        if list {
+            let anomalies = anomalies
+                .iter()
+                .map(|anomaly| format!(", {anomaly}"))
+                .collect::<String>();
+            println!(
+                "{} {} messages, depth {}{anomalies}",
+                display_message_id(root.id),
+                thread.size,
+                thread.depth
+            );
+        }
+    }
+    println!(
+        "{} threads, {cycles} cycles, {orphans} orphans",
+        threads.len()
+    );
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Version {
+    version: &'static str,
+    git_commit: &'static str,
+    build_date: &'static str,
+    rustc: &'static str,
+}
+
+/* The build information is gathered by `build.rs`, fields it couldn't find are `unknown`. */
+fn version<W: Write>(mut writer: W, json: bool) -> Result<(), IoError> {
+    let version = Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("VENT_TXT_GIT_COMMIT"),
+        build_date: env!("VENT_TXT_BUILD_DATE"),
+        rustc: env!("VENT_TXT_RUSTC"),
+    };
+    if json {
+        serde_json::to_writer_pretty(&mut writer, &version)?;
+        return writeln!(writer);
+    }
+    writeln!(writer, "vent.txt {}", version.version)?;
+    writeln!(writer, "commit: {}", version.git_commit)?;
+    writeln!(writer, "built: {}", version.build_date)?;
+    writeln!(writer, "rustc: {}", version.rustc)
+}
+
+/* Counting raw entries skips parsing entirely, which is faster on huge databases but can't tell
+ * replies and removed messages apart.
+ */
+fn counts(raw: bool) -> Result<Vec<(&'static str, usize)>, IoError> {
+    let raw_entries = Entry::read_raw_entries()?;
+    if raw {
+        let total = raw_entries.iter().filter(|r| !r.trim().is_empty()).count();
+        return Ok(vec![("total", total)]);
+    }
+
+    let entries = Entry::parse_raw_entries(&raw_entries)?;
+    let replies = entries.iter().filter(|e| e.reply.is_some()).count();
+    let removed = raw_entries
+        .iter()
+        .filter(|r| is_removed_raw_entry(r))
+        .count();
+    Ok(vec![
+        ("total", entries.len()),
+        ("replies", replies),
+        ("removed", removed),
+    ])
+}
+
+fn count(raw: bool) -> Result<(), IoError> {
+    for (name, count) in counts(raw)? {
+        println!("{name}: {count}");
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MergePolicy {
+    Ours,
+    Theirs,
+    Interactive,
+}
+
+fn merge_answer(answer: &str) -> Option<char> {
+    match answer.trim() {
+        "o" | "ours" => Some('o'),
+        "t" | "theirs" => Some('t'),
+        "b" | "both" => Some('b'),
+        _ => None,
+    }
+}
+
+fn prompt_merge_conflict(message_id: usize, ours: &str, theirs: &str) -> Result<char, IoError> {
+    eprintln!("Conflict on message {}", display_message_id(message_id));
+    eprintln!("  ours:   {ours}");
+    eprintln!("  theirs: {theirs}");
+    loop {
+        eprint!("Keep [o]urs, [t]heirs or [b]oth? ");
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Err(IoError::new(
+                ErrorKind::UnexpectedEof,
+                "No answer to merge conflict",
+            ));
+        }
+        if let Some(choice) = merge_answer(&answer) {
+            return Ok(choice);
+        }
+    }
+}
+
+/* Message IDs are positions in the database, two databases are merged position by position.
+ * Entries only present in the longer database are kept, entries differing at the same position
+ * are conflicts resolved by the policy. Keeping both versions appends theirs at the end.
+ */
+fn merge(other_path: &str, policy: Option<MergePolicy>) -> Result<(), IoError> {
+    let ours = Entry::read_raw_entries()?;
+    let theirs = Entry::read_raw_entries_from(other_path)?;
+
+    let conflicts = ours
+        .iter()
+        .zip(theirs.iter())
+        .enumerate()
+        .filter(|(_, (o, t))| o != t)
+        .map(|(id, _)| id)
+        .collect::<Vec<usize>>();
+    let policy = match policy {
+        Some(policy) => policy,
+        None if conflicts.is_empty() => MergePolicy::Ours,
+        None => {
+            let ids = conflicts
+                .iter()
+                .map(|&id| display_message_id(id).to_string())
+                .collect::<Vec<String>>()
+                .join(", ");
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                format!("Conflicting messages {ids}, use --prefer or --interactive"),
+            ));
+        }
+    };
+
+    let mut merged = ours.clone();
+    if theirs.len() > ours.len() {
+        merged.extend_from_slice(&theirs[ours.len()..]);
+    }
+    let mut appended = Vec::new();
+    for id in conflicts {
+        let choice = match policy {
+            MergePolicy::Ours => 'o',
+            MergePolicy::Theirs => 't',
+            MergePolicy::Interactive => prompt_merge_conflict(id, &ours[id], &theirs[id])?,
+        };
+        match choice {
+            't' => merged[id] = theirs[id].clone(),
+            'b' => appended.push(theirs[id].clone()),
+            _ => {}
+        }
+    }
+    merged.extend(appended);
+    write_raw_entries(&merged)
+}
+
+/* Entries are kept when they have any of the included tags, if there is any, and none of the
+ * excluded ones, when their ID is within the bounds, when they are starred with `--starred-only`
+ * and when they match the `--filter` expression. Scheduled entries and revisions are never kept.
+ */
+struct EntryFilter {
+    tags: Vec<String>,
+    excluded_tags: Vec<String>,
+    starred_only: bool,
+    ids: (Bound<usize>, Bound<usize>),
+    expression: Option<filter::Filter>,
+}
+
+impl EntryFilter {
+    const FLAGS: [&'static str; 1] = ["--starred-only"];
+    const VALUED: [&'static str; 7] = [
+        "--tag",
+        "--exclude-tag",
+        "--since-id",
+        "--until-id",
+        "--after-id",
+        "--before-id",
+        "--filter",
+    ];
+
+    fn from_options(options: &Options) -> Result<Self, IoError> {
+        let normalize = |tag: &str| tag.trim_start_matches('#').to_lowercase();
+        let id = |name| {
+            options
+                .value(name)
+                .map(|id| {
+                    parse_message_id(id)
+                        .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Invalid message ID"))
+                })
+                .transpose()
+        };
+        // Exclusive bounds take precedence over inclusive ones
+        let start = match (id("--after-id")?, id("--since-id")?) {
+            (Some(id), _) => Bound::Excluded(id),
+            (None, Some(id)) => Bound::Included(id),
+            (None, None) => Bound::Unbounded,
+        };
+        let end = match (id("--before-id")?, id("--until-id")?) {
+            (Some(id), _) => Bound::Excluded(id),
+            (None, Some(id)) => Bound::Included(id),
+            (None, None) => Bound::Unbounded,
+        };
+        Ok(EntryFilter {
+            tags: options.values("--tag").map(normalize).collect(),
+            excluded_tags: options.values("--exclude-tag").map(normalize).collect(),
+            starred_only: options.flag("--starred-only"),
+            ids: (start, end),
+            expression: options
+                .value("--filter")
+                .map(filter::Filter::parse)
+                .transpose()?,
+        })
+    }
+
+    fn matches(&self, entry: &Entry) -> bool {
+        !is_scheduled(entry)
+            && entry.revision_of().is_none()
+            && self.ids.contains(&entry.id)
+            && (!self.starred_only || entry.starred)
+            && (self.tags.is_empty() || self.tags.iter().any(|t| entry.tags.contains(t)))
+            && !self.excluded_tags.iter().any(|t| entry.tags.contains(t))
+            && self.expression.as_ref().is_none_or(|e| e.matches(entry))
+    }
+
+    fn apply(&self, entries: Vec<Entry>) -> Vec<Entry> {
+        entries.into_iter().filter(|e| self.matches(e)).collect()
+    }
+}
+
+/* Transforms only change how messages are displayed or exported, e.g. stripped tags are still
+ * listed in the `tags` of the entries.
+ */
+struct EntryTransform {
+    strip_tags: bool,
+    fold_duplicates: bool,
+}
+
+impl EntryTransform {
+    const FLAGS: [&'static str; 2] = ["--strip-tags", "--fold-duplicates"];
+
+    fn from_options(options: &Options) -> Self {
+        EntryTransform {
+            strip_tags: options.flag("--strip-tags"),
+            fold_duplicates: options.flag("--fold-duplicates"),
+        }
+    }
+
+    /* Runs of consecutive messages with the same text, reply and prompt are folded into their first
+     * message, the length of the run is set in its `folded` metadata.
+     */
+    fn fold_duplicates(entries: Vec<Entry>) -> Vec<Entry> {
+        let mut folded: Vec<Entry> = Vec::new();
+        let mut run = 1;
+        for entry in entries {
+            match folded.last_mut() {
+                Some(last)
+                    if last.message == entry.message
+                        && last.reply == entry.reply
+                        && last.prompt == entry.prompt =>
+                {
+                    run += 1;
+                    last.metadata
+                        .insert(String::from("folded"), run.to_string());
+                }
+                _ => {
+                    run = 1;
+                    folded.push(entry);
+                }
+            }
+        }
+        folded
+    }
+
+    /* Mentions are words starting with `@`, they are stripped along with tags. Lines are kept so
+     * that multiline messages stay multiline.
+     */
+    fn strip_tags(message: &str) -> String {
+        message
+            .split('\n')
+            .map(|line| {
+                line.split_whitespace()
+                    .filter(|word| {
+                        !(word.len() > 1 && (word.starts_with('#') || word.starts_with('@')))
+                    })
+                    .collect::<Vec<&str>>()
+                    .join(" ")
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn apply(&self, mut entries: Vec<Entry>) -> Vec<Entry> {
+        if self.fold_duplicates {
+            entries = Self::fold_duplicates(entries);
+        }
+        if self.strip_tags {
+            for entry in entries.iter_mut() {
+                entry.message = Self::strip_tags(&entry.message);
+            }
+        }
+        entries
+    }
+}
+
+/* Characters are counted as user-perceived ones by keeping combining marks, variation selectors
+ * and zero width joiners with the character they follow.
+ */
+fn truncate_text(text: &str, length: usize) -> String {
+    let is_extending =
+        |c: char| matches!(c, '\u{300}'..='\u{36f}' | '\u{200d}' | '\u{fe00}'..='\u{fe0f}');
+    let mut count = 0;
+    let mut end = text.len();
+    for (index, c) in text.char_indices() {
+        if !is_extending(c) {
+            if count == length {
+                end = index;
+                break;
+            }
+            count += 1;
+        }
+    }
+    if end < text.len() {
+        format!("{}…", &text[..end])
+    } else {
+        text.to_owned()
+    }
+}
+
+/* Accents are stripped from lowercase latin letters, combining marks are dropped entirely so that
+ * decomposed letters are folded like precomposed ones.
+ */
+fn strip_accent(c: char) -> Option<char> {
+    Some(match c {
+        '\u{300}'..='\u{36F}' => return None,
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => 'a',
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => 'c',
+        'ď' | 'đ' => 'd',
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => 'e',
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => 'g',
+        'ĥ' | 'ħ' => 'h',
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => 'i',
+        'ĵ' => 'j',
+        'ķ' => 'k',
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => 'l',
+        'ñ' | 'ń' | 'ņ' | 'ň' => 'n',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => 'o',
+        'ŕ' | 'ŗ' | 'ř' => 'r',
+        'ś' | 'ŝ' | 'ş' | 'š' => 's',
+        'ţ' | 'ť' | 'ŧ' => 't',
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => 'u',
+        'ŵ' => 'w',
+        'ý' | 'ÿ' | 'ŷ' => 'y',
+        'ź' | 'ż' | 'ž' => 'z',
+        c => c,
+    })
+}
+
+/* Search compares lowercase text, without accents with `--accent-insensitive`. */
+fn fold_text(text: &str, keep_accents: bool) -> String {
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .filter_map(|c| {
+            if keep_accents {
+                Some(c)
+            } else {
+                strip_accent(c)
+            }
+        })
+        .collect()
+}
+
+/* Matches are highlighted like search compares them, which means the folded text is searched and
+ * the matches are mapped back to the characters they come from.
+ */
+struct Highlight {
+    pattern: String,
+    keep_accents: bool,
+    start: &'static str,
+    end: &'static str,
+}
+
+impl Highlight {
+    fn new(pattern: String, keep_accents: bool, porcelain: bool) -> Option<Self> {
+        if porcelain {
+            Some(Self {
+                pattern,
+                keep_accents,
+                start: "**",
+                end: "**",
+            })
+        } else if io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none() {
+            Some(Self {
+                pattern,
+                keep_accents,
+                start: "\x1b[1;7m",
+                end: "\x1b[0m",
+            })
+        } else {
+            None
+        }
+    }
+
+    fn apply(&self, text: &str) -> String {
+        if self.pattern.is_empty() {
+            return text.to_owned();
+        }
+        let mut lowercase = String::new();
+        let mut origins = Vec::new();
+        for (i, c) in text.char_indices() {
+            for l in fold_text(c.encode_utf8(&mut [0; 4]), self.keep_accents).chars() {
+                origins.extend(std::iter::repeat_n((i, i + c.len_utf8()), l.len_utf8()));
+                lowercase.push(l);
+            }
+        }
+
+        let mut highlighted = String::new();
+        let mut copied = 0;
+        for (i, _) in lowercase.match_indices(&self.pattern) {
+            let (start, _) = origins[i];
+            let (_, mut end) = origins[i + self.pattern.len() - 1];
+            if start < copied {
+                continue;
+            }
+            // Combining marks dropped by folding stay with the character they modify
+            for c in text[end..].chars() {
+                if !fold_text(c.encode_utf8(&mut [0; 4]), self.keep_accents).is_empty() {
+                    break;
+                }
+                end += c.len_utf8();
+            }
+            highlighted.push_str(&text[copied..start]);
+            highlighted.push_str(self.start);
+            highlighted.push_str(&text[start..end]);
+            highlighted.push_str(self.end);
+            copied = end;
+        }
+        highlighted.push_str(&text[copied..]);
+        highlighted
+    }
+}
+
+fn print_entry(entry: &Entry, indent: &str) {
+    print_entry_with_prefix(entry, indent, indent, None, None);
+}
+
+fn print_entry_with_prefix(
+    entry: &Entry,
+    prefix: &str,
+    continuation: &str,
+    preview: Option<usize>,
+    highlight: Option<&Highlight>,
+) {
+    print!(
+        "{}",
+        format_entry(entry, prefix, continuation, preview, highlight)
+    );
+}
+
+fn format_entry(
+    entry: &Entry,
+    prefix: &str,
+    continuation: &str,
+    preview: Option<usize>,
+    highlight: Option<&Highlight>,
+) -> String {
+    let reply = entry
+        .reply
+        .map(|r| format!(">>{} ", display_message_id(r)))
+        .unwrap_or_default();
+    let message = entry.message.trim_start();
+    let message = match preview {
+        Some(length) => truncate_text(message, length),
+        None => message.to_owned(),
+    };
+    let message = match highlight {
+        Some(highlight) => highlight.apply(&message),
+        None => message,
+    };
+    // Continuation lines are aligned under the ID of the message
+    let message = message.replace('\n', &format!("\n{continuation}    "));
+    let mut text = format!(
+        "{prefix}{} {} {reply}{message}\n",
+        display_message_id(entry.id),
+        entry.date,
+    );
+    if let Some(prompt) = &entry.prompt {
+        text.push_str(&format!("{continuation}    prompt: {prompt}\n"));
+    }
+    text
+}
+
+/* Only the IDs are printed, to be given to the commands editing messages. */
+fn find_ids<W: Write>(mut writer: W, entries: &[Entry]) -> Result<(), IoError> {
+    for entry in entries.iter() {
+        writeln!(writer, "{}", display_message_id(entry.id))?;
+    }
+    Ok(())
+}
+
+fn list(entries: &[Entry], tree: bool, preview: Option<usize>, highlight: Option<&Highlight>) {
+    if !tree {
+        for entry in entries.iter() {
+            print_entry_with_prefix(entry, "", "", preview, highlight);
+        }
+        return;
+    }
+
+    for node in threads::reply_forest(entries) {
+        let depth = node.last.len();
+        let mut prefix = String::new();
+        for &last in node.last.iter().take(depth.saturating_sub(1)) {
+            prefix.push_str(if last { "    " } else { "│   " });
+        }
+        let mut continuation = prefix.clone();
+        if let Some(&last) = node.last.last() {
+            prefix.push_str(if last { "└── " } else { "├── " });
+            continuation.push_str(if last { "    " } else { "│   " });
+        }
+        print_entry_with_prefix(
+            &entries[node.index],
+            &prefix,
+            &continuation,
+            preview,
+            highlight,
+        );
+    }
+}
+
+/* Details of a single message, with the fields computed from the whole database. */
+#[derive(Serialize)]
+struct EntryDetails<'a> {
+    #[serde(flatten)]
+    entry: &'a Entry,
+    depth: usize,
+    backlinks: Vec<usize>,
+    timestamp: Option<i64>,
+}
+
+fn show_json<W: Write>(mut writer: W, entries: &[Entry], message_id: usize) -> Result<(), IoError> {
+    let entry = entries
+        .get(message_id)
+        .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Out-of-bound message ID"))?;
+    let details = EntryDetails {
+        entry,
+        depth: threads::reply_ancestors(entries, message_id).len(),
+        backlinks: threads::reply_children(entries)[message_id]
+            .iter()
+            .filter(|&&id| entries[id].revision_of().is_none())
+            .copied()
+            .collect(),
+        timestamp: parse_date(&entry.date).map(|date| date.timestamp()),
+    };
+    serde_json::to_writer_pretty(&mut writer, &details)?;
+    writeln!(writer)
+}
+
+/* History starts with the message as it was first added, followed by its revisions. */
+fn show_history(message_id: usize) -> Result<(), IoError> {
+    let entries = Entry::parse_raw_entries(&Entry::read_raw_entries()?)?;
+    if message_id >= entries.len() {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "Out-of-bound message ID",
+        ));
+    }
+    print_entry(&entries[message_id], "");
+    for revision in entries
+        .iter()
+        .filter(|e| e.revision_of() == Some(message_id))
+    {
+        print_entry(revision, &get_indent());
+    }
+    Ok(())
+}
+
+/* Messages shown for `message_id` with their depth, its ancestors come first and its replies
+ * follow depth-first when `follow_replies` is given.
+ */
+fn conversation(entries: &[Entry], message_id: usize, follow_replies: bool) -> Vec<(usize, usize)> {
+    let ancestors = threads::reply_ancestors(entries, message_id);
+    let mut shown = ancestors
+        .iter()
+        .enumerate()
+        .map(|(depth, &id)| (id, depth))
+        .collect::<Vec<(usize, usize)>>();
+    shown.push((message_id, ancestors.len()));
+
+    if follow_replies {
+        let children = threads::reply_children(entries);
+        let mut visited = vec![false; entries.len()];
+        visited[message_id] = true;
+        ancestors.iter().for_each(|&id| visited[id] = true);
+        let mut stack = children[message_id]
+            .iter()
+            .rev()
+            .map(|&id| (id, ancestors.len() + 1))
+            .collect::<Vec<(usize, usize)>>();
+        while let Some((id, depth)) = stack.pop() {
+            if visited[id] {
+                continue;
+            }
+            visited[id] = true;
+            if entries[id].revision_of().is_some() {
+                continue;
+            }
+            shown.push((id, depth));
+            stack.extend(children[id].iter().rev().map(|&child| (child, depth + 1)));
+        }
+    }
+    shown
+}
+
+fn show<W: Write>(
+    mut writer: W,
+    entries: &[Entry],
+    message_id: usize,
+    follow_replies: bool,
+) -> Result<(), IoError> {
+    if message_id >= entries.len() {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "Out-of-bound message ID",
+        ));
+    }
+
+    let indent = get_indent();
+    for (id, depth) in conversation(entries, message_id, follow_replies) {
+        let indent = indent.repeat(depth);
+        write!(
+            writer,
+            "{}",
+            format_entry(&entries[id], &indent, &indent, None, None)
+        )?;
+    }
+    Ok(())
+}
+
+/* Imported messages are appended in the order of the array unless `sort_by_date` is given. With
+ * `locale_dates`, localized dates are first converted to the stored format in local time, those
+ * which still can't be parsed are kept as they are.
+ */
+fn import(
+    path: &str,
+    format: &str,
+    encoding: &str,
+    locale_dates: bool,
+    sort_by_date: bool,
+    preserve_ids: bool,
+    flush_every: Option<usize>,
+) -> Result<(), IoError> {
+    let text = import::decode(fs::read(path)?, encoding)?;
+    let first_id = match Entry::read_raw_entries() {
+        Ok(raw_entries) => raw_entries.len(),
+        Err(e) if e.kind() == ErrorKind::NotFound => 0,
+        Err(e) => return Err(e),
+    };
+    let entries = match format {
+        "json" => import::json(&text)?,
+        "jsonl" | "ndjson" => import::jsonl(&text)?,
+        _ => {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "Invalid import format",
+            ))
+        }
+    };
+    let mut entries = match preserve_ids {
+        true => import::keep_ids(entries, first_id, &get_removed_text())?,
+        false => import::rebase(entries, first_id),
+    };
+    for (index, entry) in entries.iter_mut().enumerate() {
+        if !locale_dates || parse_date(&entry.date).is_some() {
+            continue;
+        }
+        match import::parse_localized_date(&entry.date)
+            .and_then(|date| Local.from_local_datetime(&date).earliest())
+        {
+            Some(date) => entry.date = date.format(DATE_FORMAT).to_string(),
+            None => eprintln!(
+                "Imported message {index}: unknown date format, {} kept as is",
+                entry.date
+            ),
+        }
+    }
+    if sort_by_date {
+        import::sort_by_date(&mut entries, first_id);
+    }
+    let raw_entries = entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            entry.to_raw_entry().map_err(|error| {
+                IoError::new(error.kind(), format!("Imported message {index}: {error}"))
+            })
+        })
+        .collect::<Result<Vec<String>, IoError>>()?;
+
+    let mut writer = BatchWriter::new(open_database_for_append()?, flush_every);
+    for raw_entry in raw_entries.iter() {
+        writer.push(raw_entry)?;
+    }
+    writer.flush()?;
+    println!("Imported {} messages", raw_entries.len());
+    Ok(())
+}
+
+/* Messages are sorted by their parsed dates, messages sharing a date by ID. Dates which can't be
+ * parsed come before every other date.
+ */
+fn sort_by_date(entries: &mut [Entry], newest_first: bool) {
+    entries.sort_by_cached_key(|entry| (parse_date(&entry.date), entry.id));
+    if newest_first {
+        entries.reverse();
+    }
+}
+
+/* Watching renders again whenever the database or the template changes, serving makes the
+ * directory of the output available to a browser which reloads the document after each render.
+ */
+fn watch_and_serve<F>(
+    options: &Options,
+    render_options: &render::RenderOptions,
+    render_all: F,
+) -> Result<(), IoError>
+where
+    F: FnMut() -> Result<(), IoError>,
+{
+    let output = render_options.output().ok_or_else(|| {
+        IoError::new(
+            ErrorKind::InvalidInput,
+            "Watching and serving require an output file",
+        )
+    })?;
+    if options.flag("--watch") && options.flag("--stdin-template") {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "Templates read from the standard input can't be watched",
+        ));
+    }
+    let port = match options.value("--port") {
+        Some(port) => port
+            .parse::<u16>()
+            .map_err(|_| IoError::new(ErrorKind::InvalidInput, "Invalid port"))?,
+        None => 8000,
+    };
+    let max_connections = match options.value("--max-connections") {
+        Some(count) => count
+            .parse::<usize>()
+            .ok()
+            .filter(|&count| count > 0)
+            .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Invalid connection count"))?,
+        None => 16,
+    };
+    let reloads = serve::Reloads::default();
+    if options.flag("--serve") {
+        serve::serve(Path::new(output), port, max_connections, &reloads)?;
+    }
+    if options.flag("--watch") {
+        let paths = [
+            PathBuf::from(get_csv_path()),
+            render_options.template_path().to_owned(),
+        ];
+        return serve::watch(&paths, render_all, &reloads);
+    }
+    let mut render_all = render_all;
+    render_all()?;
+    loop {
+        thread::park();
+    }
+}
+
+fn read_sample_entries(sample_path: &str) -> Result<Vec<Entry>, IoError> {
+    let sample = BufReader::new(File::open(sample_path)?);
+    let mut entries: Vec<Entry> =
+        serde_json::from_reader(sample).map_err(|e| IoError::new(ErrorKind::InvalidData, e))?;
+    for (id, entry) in entries.iter_mut().enumerate() {
+        entry.id = id;
+    }
+    Ok(entries)
+}
+
+fn usage(program_name: &str) -> ! {
+    eprintln!("Usage: {program_name} init [--force]");
+    eprintln!("       {program_name} add [message]");
+    eprintln!("       {program_name} add '>>[reply id]' [message]");
+    eprintln!("       {program_name} add --prompt [prompt] [message]");
+    eprintln!(
+        "       {program_name} add [--reply [id]] [--date [date]] [--tag [tag]]... [message]"
+    );
+    eprintln!("       {program_name} add --confirm [--yes] [message]");
+    eprintln!("       {program_name} add --from-clipboard");
+    eprintln!("       {program_name} add --file [path]");
+    eprintln!("       {program_name} add --sensitive [message]");
+    eprintln!("       {program_name} add --attach [file]... [message]");
+    eprintln!("       {program_name} add --publish-at [yyyy-mm-dd hh:mm] [message]");
+    eprintln!("       {program_name} publish-due");
+    eprintln!("       {program_name} add --stream [--flush-every [count]] < [messages]");
+    eprintln!("       {program_name} draft [--prompt [prompt]] [message]");
+    eprintln!("       {program_name} draft --edit [draft id] [message]");
+    eprintln!("       {program_name} draft --rm [draft id]");
+    eprintln!("       {program_name} drafts");
+    eprintln!("       {program_name} publish [draft id]");
+    eprintln!("       {program_name} edit [--restore] [message id] [message]");
+    eprintln!("       {program_name} edit --revision [message id] [message]");
+    eprintln!("       {program_name} rm [message id]");
+    eprintln!("       {program_name} star|unstar [message id]");
+    eprintln!("       {program_name} starred [filters]");
+    eprintln!("       {program_name} prune [--hard] [--yes|dry run] --before [yyyy-mm-dd]");
+    eprintln!("       {program_name} merge [--prefer ours|theirs] [other database]");
+    eprintln!("       {program_name} merge --interactive [other database]");
+    eprintln!("       {program_name} list [--tree] [--preview [length]] [filters]");
+    eprintln!("       {program_name} search [--preview [length]] [--highlight [--porcelain]] [filters] [text]");
+    eprintln!("       {program_name} search --accent-insensitive [search options] [text]");
+    eprintln!("       {program_name} find-id [--accent-insensitive] [filters] [text]");
+    eprintln!("       {program_name} verify [--fix]");
+    eprintln!("       {program_name} orphans [--fix|dry run]");
+    eprintln!("       {program_name} threads [--list]");
+    eprintln!("       {program_name} migrate-replies [dry run]");
+    eprintln!(
+        "       {program_name} tags [--json] [--sort count|alpha[:desc]] [--min-count n] [filters]"
+    );
+    eprintln!("       {program_name} count [--raw]");
+    eprintln!("       {program_name} stats [filters]");
+    eprintln!("       {program_name} stats --streak [filters]");
+    eprintln!("       {program_name} words [--top [count]] [filters]");
+    eprintln!("       {program_name} duplicates [--threshold [similarity]] [filters]");
+    eprintln!("       {program_name} show [--follow-replies] [message id]");
+    eprintln!("       {program_name} show --history [message id]");
+    eprintln!("       {program_name} show --raw-json [message id]");
+    eprintln!("       {program_name} render [--output [file]] [--timeout [seconds]]");
+    eprintln!(
+        "                   [--template-name [name]] [--chronological] [--null-data-ok] [filters]"
+    );
+    eprintln!(
+        "       {program_name} render --stdin-template [render options] [filters] < [template]"
+    );
+    eprintln!("       {program_name} render --feed atom [--output [file]] [filters]");
+    eprintln!("                   [--numbering absolute|sequential]");
+    eprintln!(
+        "       {program_name} render --per-page [count] --output [file] [--base-path [path]]"
+    );
+    eprintln!("       {program_name} render --group-by tag|month|year [render options] [filters]");
+    eprintln!("       {program_name} render --preview [render options] [filters]");
+    eprintln!("       {program_name} render --watch [--serve [--port [port]]] --output [file]");
+    eprintln!("                   [--max-connections [count]] [render options] [filters]");
+    eprintln!("       {program_name} open [render options] [filters]");
+    eprintln!("       {program_name} build [--chronological] [--numbering absolute|sequential]");
+    eprintln!("                   [filters] [output]:[template name|atom]...");
+    eprintln!("       {program_name} export [--format json|sql]");
+    eprintln!("       {program_name} export --format md-frontmatter --output-dir [directory]");
+    eprintln!("       {program_name} export --format sqlite [sqlite database]");
+    eprintln!("       {program_name} export --format csv > [database]");
+    eprintln!("       {program_name} export --format ndjson > [json lines file]");
+    eprintln!("       {program_name} export --order oldest-first|newest-first [export options]");
+    eprintln!("       {program_name} export --preserve-ids [export options]");
+    eprintln!("       {program_name} import [--encoding utf-8|windows-1252|latin-1] [json file]");
+    eprintln!("       {program_name} import --locale-date-parse [json file]");
+    eprintln!("       {program_name} import [--preserve-order|--sort-by-date] [json file]");
+    eprintln!("       {program_name} import --flush-every [count] [json file]");
+    eprintln!("       {program_name} import --format jsonl [json lines file]");
+    eprintln!("       {program_name} import --preserve-ids [import options] [json file]");
+    eprintln!("       {program_name} template-test [sample json]");
+    eprintln!("       {program_name} version [--json]");
+    eprintln!();
+    eprintln!("Global:      --json-errors   Print errors as JSON objects");
+    eprintln!("                             (before the command)");
+    eprintln!();
+    eprintln!("Dry run:     --dry-run       Print the changes instead of writing them");
+    eprintln!("             --dry-run-json  Print the changes as JSON");
+    eprintln!("                             (prune, orphans and migrate-replies)");
+    eprintln!();
+    eprintln!("Filters:     --tag [tag]     Keep messages with this tag");
+    eprintln!("             --exclude-tag [tag]");
+    eprintln!("                             Drop messages with this tag");
+    eprintln!("             --starred-only  Keep starred messages");
+    eprintln!("             --since-id [id], --until-id [id]");
+    eprintln!("                             Keep messages from/up to this ID");
+    eprintln!("             --after-id [id], --before-id [id]");
+    eprintln!("                             Keep messages after/before this ID");
+    eprintln!("             --filter [expression]");
+    eprintln!("                             Keep messages matching the expression");
+    eprintln!();
+    eprintln!("Transforms:  --strip-tags    Drop tags and mentions from messages");
+    eprintln!("             --fold-duplicates");
+    eprintln!("                             Fold repeated consecutive messages");
+    eprintln!("                             (render, open, build and export)");
+    eprintln!();
+    eprintln!("Environment: VENT_TXT_CSV    Vent database location");
+    eprintln!("                             (default: 'vent.csv')");
+    eprintln!("             VENT_TXT_HBS    Render template");
+    eprintln!("                             (default: 'template/vent.hbs')");
+    eprintln!("             VENT_TXT_HELPERS");
+    eprintln!("                             Template helpers directory");
+    eprintln!("                             (default: 'template/helpers')");
+    eprintln!("             VENT_TXT_DRAFTS Drafts location");
+    eprintln!("                             (default: 'drafts.csv')");
+    eprintln!("             VENT_TXT_ATTACHMENTS");
+    eprintln!("                             Attachments location");
+    eprintln!("                             (default: 'attachments')");
+    eprintln!("             VENT_TXT_ATTACHMENTS_URL");
+    eprintln!("                             URL of the attachments in documents");
+    eprintln!("                             (default: VENT_TXT_ATTACHMENTS)");
+    eprintln!("             VENT_TXT_URL    URL of the rendered document");
+    eprintln!("                             (default: unset)");
+    eprintln!("             VENT_TXT_REMOVED_TEXT");
+    eprintln!("                             Text replacing removed messages");
+    eprintln!("                             (default: '[removed]')");
+    eprintln!("             VENT_TXT_READ_BUFFER");
+    eprintln!("                             Database read buffer size in bytes");
+    eprintln!("                             (default: 8192)");
+    eprintln!("             VENT_TXT_MMAP   Read the database from a memory map");
+    eprintln!("                             (default: 0)");
+    eprintln!("             VENT_TXT_MAX_CHAIN");
+    eprintln!("                             Deepest reply accepted by add");
+    eprintln!("                             (default: unset)");
+    eprintln!("             VENT_TXT_MAX_FILE_BYTES");
+    eprintln!("                             Largest database read in bytes");
+    eprintln!("                             (default: unset)");
+    eprintln!("             VENT_TXT_CLIPBOARD");
+    eprintln!("                             Command printing the clipboard");
+    eprintln!("                             (default: unset, platform tools)");
+    eprintln!("             VENT_TXT_STOPWORDS");
+    eprintln!("                             Words ignored by words, one per line");
+    eprintln!("                             (default: unset, common English words)");
+    eprintln!("             VENT_TXT_DEDUPE_TAGS");
+    eprintln!("                             Count repeated tags of a message once");
+    eprintln!("                             (default: 1)");
+    eprintln!("             VENT_TXT_TRIM_REPLY_WHITESPACE");
+    eprintln!("                             Drop the space following replies");
+    eprintln!("                             (default: 1)");
+    eprintln!("             VENT_TXT_EDIT_PRESERVES_DATE");
+    eprintln!("                             Keep the date of edited messages");
+    eprintln!("                             (default: 1)");
+    eprintln!("             VENT_TXT_ALLOW_EMPTY");
+    eprintln!("                             Accept empty messages");
+    eprintln!("                             (default: 0)");
+    eprintln!("             VENT_TXT_TRIM_STORED");
+    eprintln!("                             Drop trailing whitespace when writing");
+    eprintln!("                             (default: 0)");
+    eprintln!("             VENT_TXT_FILE_MODE");
+    eprintln!("                             Octal permissions of created files");
+    eprintln!("                             (default: unset, umask)");
+    eprintln!("             VENT_TXT_FSYNC  Sync the database to disk after writes");
+    eprintln!("                             (default: 0)");
+    eprintln!("             VENT_TXT_REPLY_STORAGE");
+    eprintln!("                             Store replies inline or in metadata");
+    eprintln!("                             (default: 'inline')");
+    eprintln!("             VENT_TXT_NOW    Current time, e.g. for reproducible renders");
+    eprintln!("                             (default: unset)");
+    eprintln!("             VENT_TXT_INDENT Indentation of each reply level in show");
+    eprintln!("                             (default: two spaces)");
+    eprintln!("             VENT_TXT_WPM    Reading speed in words per minute");
+    eprintln!("                             (default: 200)");
+    eprintln!("             VENT_TXT_DAY_START");
+    eprintln!("                             Hour at which days start for streaks");
+    eprintln!("                             (default: 0)");
+    eprintln!("             VENT_TXT_LINE_FORMAT");
+    eprintln!("                             Layout of the lines written by add");
+    eprintln!("                             (default: '{{date}}{{metadata}},{{message}}')");
+    eprintln!("             VENT_TXT_HTML_TAGS");
+    eprintln!("                             HTML tags rendered from messages");
+    eprintln!("                             (default: unset, shown as text)");
+    eprintln!("             VENT_TXT_HTML_ATTRIBUTES");
+    eprintln!("                             HTML attributes kept on those tags");
+    eprintln!("                             (default: 'href title')");
+    eprintln!("             VENT_TXT_STABLE_ANCHORS");
+    eprintln!("                             Derive anchors from dates, not IDs");
+    eprintln!("                             (default: 0)");
+    eprintln!("             VENT_TXT_ONE_BASED");
+    eprintln!("                             Use message IDs starting at 1");
+    eprintln!("                             (default: unset)");
+    std::process::exit(1)
+}
+
+fn json_error(error: &IoError) -> serde_json::Value {
+    let line_error = error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<LineError>());
+    let mut json = serde_json::json!({
+        "error": line_error.map_or_else(|| error.to_string(), |e| e.error.to_string()),
+        "kind": format!("{:?}", error.kind()),
+    });
+    if let Some(line_error) = line_error {
+        json["line"] = serde_json::json!(line_error.line);
+    }
+    json
+}
+
+fn main() -> Result<(), IoError> {
+    let mut args = env::args();
+    let program_name = args.next().unwrap();
+    let mut args = args.collect::<Vec<String>>();
+    let json_errors = args.first().is_some_and(|a| a == "--json-errors");
+    if json_errors {
+        args.remove(0);
+    }
+
+    run(&program_name, args).or_else(|e| {
+        if json_errors {
+            eprintln!("{}", json_error(&e));
+            std::process::exit(1)
+        } else {
+            Err(e)
+        }
+    })
+}
+
+fn run(program_name: &str, mut args: Vec<String>) -> Result<(), IoError> {
+    let action = collect_argument_from_args(&mut args, "").unwrap_or_else(|_| usage(program_name));
+    validate_line_format()?;
+
+    match action.as_str() {
+        "add" => {
+            let flags = [
+                "--stream",
+                "--confirm",
+                "--yes",
+                "--from-clipboard",
+                "--sensitive",
+            ];
+            let valued = [
+                "--prompt",
+                "--publish-at",
+                "--attach",
+                "--file",
+                "--reply",
+                "--date",
+                "--tag",
+                "--flush-every",
+            ];
+            let options = Options::parse(&mut args, &flags, &valued, true)?;
+            let publish_at = options
+                .value("--publish-at")
+                .map(parse_publish_date)
+                .transpose()?;
+            let prompt = options.value("--prompt");
+            if options.flag("--stream") {
+                add_stream(
+                    io::stdin().lock(),
+                    prompt,
+                    parse_flush_every(&options)?.unwrap_or(1),
+                )
+            } else {
+                let builder = if options.flag("--from-clipboard") {
+                    EntryBuilder::new(&read_clipboard()?)
+                } else if let Some(path) = options.value("--file") {
+                    EntryBuilder::new(&read_message_file(path)?).multiline(true)
+                } else {
+                    EntryBuilder::new(&args.join(" "))
+                };
+                let reply = options
+                    .value("--reply")
+                    .map(|reply| {
+                        parse_message_id(reply).ok_or_else(|| {
+                            IoError::new(ErrorKind::InvalidInput, "Invalid reply ID")
+                        })
+                    })
+                    .transpose()?;
+                let entries = read_entries_for_add()?;
+                let mut entry = builder
+                    .prompt(prompt)
+                    .reply(reply)
+                    .date(options.value("--date"))
+                    .tags(&options.values("--tag").collect::<Vec<&str>>())
+                    .build(&entries)?;
+                render::store_anchor(&mut entry, &entries);
+                if let Some(publish_at) = publish_at {
+                    entry.metadata.insert(String::from("scheduled"), publish_at);
+                }
+                entry.spoiler = options.flag("--sensitive");
+                add(
+                    entry,
+                    &options
+                        .values("--attach")
+                        .map(str::to_owned)
+                        .collect::<Vec<String>>(),
+                    options.flag("--confirm"),
+                    options.flag("--yes"),
+                )
+            }
+        }
+        "draft" => {
+            let options = Options::parse(&mut args, &["--edit", "--rm"], &["--prompt"], true)?;
+            if options.flag("--rm") {
+                drafts::remove(collect_message_id_from_args(&mut args)?)
+            } else if options.flag("--edit") {
+                let draft_id = collect_message_id_from_args(&mut args)?;
+                drafts::edit(draft_id, &args.join(" "))
+            } else {
+                drafts::add(&args.join(" "), options.value("--prompt"))
+            }
+        }
+        "drafts" => drafts::list(),
+        "publish-due" => publish_due(),
+        "migrate-replies" => {
+            let options = Options::parse(&mut args, &DryRun::FLAGS, &[], false)?;
+            migrate_replies(DryRun::from_options(&options))
+        }
+        "publish" => drafts::publish(collect_message_id_from_args(&mut args)?),
+        "edit" => {
+            let options = Options::parse(&mut args, &["--restore", "--revision"], &[], true)?;
+            let message_id = collect_message_id_from_args(&mut args)?;
+            let message = args.join(" ");
+            if options.flag("--revision") {
+                edit_revision(message_id, &message)
+            } else {
+                edit(message_id, &message, options.flag("--restore"))
+            }
+        }
+        "rm" => {
+            let message_id = collect_message_id_from_args(&mut args)?;
+            remove(message_id)
+        }
+        "star" | "unstar" => star(collect_message_id_from_args(&mut args)?, action == "star"),
+        "starred" => {
+            let options = Options::parse(&mut args, &[], &EntryFilter::VALUED, false)?;
+            let mut entries = EntryFilter::from_options(&options)?.apply(Entry::read_entries()?);
+            entries.retain(|e| e.starred);
+            list(&entries, false, None, None);
+            Ok(())
+        }
+        "prune" => {
+            let flags = [&DryRun::FLAGS[..], &["--hard", "--yes"]].concat();
+            let options = Options::parse(&mut args, &flags, &["--before"], false)?;
+            let before = options
+                .value("--before")
+                .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+                .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Invalid prune date"))?;
+            prune(
+                before,
+                options.flag("--hard"),
+                options.flag("--yes"),
+                DryRun::from_options(&options),
+            )
+        }
+        "merge" => {
+            let options = Options::parse(&mut args, &["--interactive"], &["--prefer"], false)?;
+            let policy = match (options.flag("--interactive"), options.value("--prefer")) {
+                (true, None) => Some(MergePolicy::Interactive),
+                (false, Some("ours")) => Some(MergePolicy::Ours),
+                (false, Some("theirs")) => Some(MergePolicy::Theirs),
+                (false, None) => None,
+                _ => {
+                    return Err(IoError::new(
+                        ErrorKind::InvalidInput,
+                        "Invalid merge policy",
+                    ))
+                }
+            };
+            let other_path = collect_argument_from_args(&mut args, "No database to merge")?;
+            merge(&other_path, policy)
+        }
+        "find-id" => {
+            let flags = [&EntryFilter::FLAGS[..], &["--accent-insensitive"]].concat();
+            let options = Options::parse(&mut args, &flags, &EntryFilter::VALUED, false)?;
+            let mut entries = EntryFilter::from_options(&options)?.apply(Entry::read_entries()?);
+            let keep_accents = !options.flag("--accent-insensitive");
+            let pattern = fold_text(&collect_search_text(args)?, keep_accents);
+            entries.retain(|e| fold_text(&e.message, keep_accents).contains(&pattern));
+            find_ids(io::stdout(), &entries)
+        }
+        "list" | "search" => {
+            let flags = [
+                &EntryFilter::FLAGS[..],
+                &[
+                    "--tree",
+                    "--with-replies",
+                    "--highlight",
+                    "--porcelain",
+                    "--accent-insensitive",
+                ],
+            ]
+            .concat();
+            let valued = [&EntryFilter::VALUED[..], &["--preview"]].concat();
+            let options = Options::parse(&mut args, &flags, &valued, false)?;
+            let filter = EntryFilter::from_options(&options)?;
+            let tree = options.flag("--tree") || options.flag("--with-replies");
+            let preview = options
+                .value("--preview")
+                .map(|p| {
+                    p.parse::<usize>().map_err(|_| {
+                        IoError::new(ErrorKind::InvalidInput, "Invalid preview length")
+                    })
+                })
+                .transpose()?;
+            let mut entries = filter.apply(Entry::read_entries()?);
+            let mut highlight = None;
+            if action == "search" {
+                let keep_accents = !options.flag("--accent-insensitive");
+                let pattern = fold_text(&collect_search_text(args)?, keep_accents);
+                entries.retain(|e| fold_text(&e.message, keep_accents).contains(&pattern));
+                if options.flag("--highlight") {
+                    highlight = Highlight::new(pattern, keep_accents, options.flag("--porcelain"));
+                }
+            }
+            list(&entries, tree, preview, highlight.as_ref());
+            Ok(())
+        }
+        "init" => {
+            let options = Options::parse(&mut args, &["--force"], &[], false)?;
+            init(options.flag("--force"))
+        }
+        "verify" => {
+            let options = Options::parse(&mut args, &["--fix"], &[], false)?;
+            verify(options.flag("--fix"))
+        }
+        "orphans" => {
+            let flags = [&DryRun::FLAGS[..], &["--fix"]].concat();
+            let options = Options::parse(&mut args, &flags, &[], false)?;
+            orphans(options.flag("--fix"), DryRun::from_options(&options))
+        }
+        "threads" => {
+            let options = Options::parse(&mut args, &["--list"], &[], false)?;
+            threads(options.flag("--list"))
+        }
+        "tags" => {
+            let flags = [&EntryFilter::FLAGS[..], &["--json"]].concat();
+            let valued = [&EntryFilter::VALUED[..], &["--sort", "--min-count"]].concat();
+            let options = Options::parse(&mut args, &flags, &valued, false)?;
+            let sort = match options.value("--sort") {
+                Some("count") | None => TagSort::Count,
+                Some("alpha") | Some("alpha:asc") => TagSort::Alpha,
+                Some("alpha:desc") => TagSort::AlphaDesc,
+                Some(_) => return Err(IoError::new(ErrorKind::InvalidInput, "Invalid tag sort")),
+            };
+            let min_count = match options.value("--min-count") {
+                Some(count) => count
+                    .parse::<usize>()
+                    .map_err(|_| IoError::new(ErrorKind::InvalidInput, "Invalid tag count"))?,
+                None => 1,
+            };
+            let entries = EntryFilter::from_options(&options)?.apply(Entry::read_entries()?);
+            tags(&entries, sort, min_count, options.flag("--json"))
+        }
+        "stats" => {
+            let flags = [&EntryFilter::FLAGS[..], &["--streak"]].concat();
+            let options = Options::parse(&mut args, &flags, &EntryFilter::VALUED, false)?;
+            let entries = EntryFilter::from_options(&options)?.apply(Entry::read_entries()?);
+            if options.flag("--streak") {
+                stats::streaks(
+                    io::stdout(),
+                    &entries,
+                    &get_removed_text(),
+                    get_day_start(),
+                    get_now(),
+                )?;
+            } else {
+                stats::stats(io::stdout(), &entries, &get_removed_text())?;
+            }
+            Ok(())
+        }
+        "words" => {
+            let valued = [&EntryFilter::VALUED[..], &["--top"]].concat();
+            let options = Options::parse(&mut args, &EntryFilter::FLAGS, &valued, false)?;
+            let top = match options.value("--top") {
+                Some(top) => top
+                    .parse::<usize>()
+                    .map_err(|_| IoError::new(ErrorKind::InvalidInput, "Invalid word count"))?,
+                None => 20,
+            };
+            let entries = EntryFilter::from_options(&options)?.apply(Entry::read_entries()?);
+            stats::words(
+                io::stdout(),
+                &entries,
+                &get_removed_text(),
+                &get_stopwords()?,
+                top,
+            )
+        }
+        "duplicates" => {
+            let valued = [&EntryFilter::VALUED[..], &["--threshold"]].concat();
+            let options = Options::parse(&mut args, &EntryFilter::FLAGS, &valued, false)?;
+            let threshold = match options.value("--threshold") {
+                Some(threshold) => threshold
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|t| *t > 0.0 && *t <= 1.0)
+                    .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Invalid threshold"))?,
+                None => 0.9,
+            };
+            let entries = EntryFilter::from_options(&options)?.apply(Entry::read_entries()?);
+            stats::duplicates(io::stdout(), &entries, &get_removed_text(), threshold)?;
+            Ok(())
+        }
+        "count" => {
+            let options = Options::parse(&mut args, &["--raw"], &[], false)?;
+            count(options.flag("--raw"))
+        }
+        "show" => {
+            let flags = ["--follow-replies", "--history", "--raw-json"];
+            let options = Options::parse(&mut args, &flags, &[], false)?;
+            let message_id = collect_message_id_from_args(&mut args)?;
+            if options.flag("--history") {
+                return show_history(message_id);
+            }
+            if options.flag("--raw-json") {
+                return show_json(io::stdout(), &Entry::read_entries()?, message_id);
+            }
+            show(
+                io::stdout(),
+                &Entry::read_entries()?,
+                message_id,
+                options.flag("--follow-replies"),
+            )
+        }
+        "render" | "open" => {
+            let flags = [
+                &EntryFilter::FLAGS[..],
+                &EntryTransform::FLAGS[..],
+                &render::RenderOptions::FLAGS[..],
+                &["--null-data-ok", "--watch", "--serve"],
+            ]
+            .concat();
+            let valued = [
+                &EntryFilter::VALUED[..],
+                &render::RenderOptions::VALUED[..],
+                &["--port", "--max-connections"],
+            ]
+            .concat();
+            let options = Options::parse(&mut args, &flags, &valued, false)?;
+            let filter = EntryFilter::from_options(&options)?;
+            let render_options = render::RenderOptions::from_options(&options)?;
+            let read_entries = || read_entries_or_empty(options.flag("--null-data-ok"));
+            let render_all = || {
+                let entries = read_entries()?;
+                let render_options = render_options
+                    .clone()
+                    .with_total(count_shown_entries(&entries))
+                    .with_anchors(&entries);
+                let entries = EntryTransform::from_options(&options).apply(filter.apply(entries));
+                render::render_with_options(entries, &render_options)
+            };
+            if action == "render" && (options.flag("--watch") || options.flag("--serve")) {
+                return watch_and_serve(&options, &render_options, render_all);
+            }
+            let entries = read_entries()?;
+            let render_options = render_options
+                .with_total(count_shown_entries(&entries))
+                .with_anchors(&entries);
+            let entries = EntryTransform::from_options(&options).apply(filter.apply(entries));
+            if action == "open" {
+                render::open(entries, render_options)
+            } else {
+                render::render_with_options(entries, &render_options)
+            }
+        }
+        "build" => {
+            let flags = [
+                &EntryFilter::FLAGS[..],
+                &EntryTransform::FLAGS[..],
+                &["--chronological", "--reverse"],
+            ]
+            .concat();
+            let valued = [&EntryFilter::VALUED[..], &["--numbering"]].concat();
+            let options = Options::parse(&mut args, &flags, &valued, false)?;
+            let filter = EntryFilter::from_options(&options)?;
+            let transform = EntryTransform::from_options(&options);
+            if args.is_empty() {
+                return Err(IoError::new(ErrorKind::InvalidInput, "No build target"));
+            }
+            let entries = Entry::read_entries()?;
+            let render_options = render::RenderOptions::from_options(&options)?
+                .with_total(count_shown_entries(&entries))
+                .with_anchors(&entries);
+            render::build(
+                &transform.apply(filter.apply(entries)),
+                &render_options,
+                &args,
+            )
+        }
+        "import" => {
+            let flags = [
+                "--locale-date-parse",
+                "--preserve-order",
+                "--sort-by-date",
+                "--preserve-ids",
+            ];
+            let valued = ["--encoding", "--flush-every", "--format"];
+            let options = Options::parse(&mut args, &flags, &valued, false)?;
+            for preserved in ["--preserve-order", "--preserve-ids"] {
+                if options.flag(preserved) && options.flag("--sort-by-date") {
+                    return Err(IoError::new(
+                        ErrorKind::InvalidInput,
+                        format!("Sorting by date can't be used with {preserved}"),
+                    ));
+                }
+            }
+            let path = collect_argument_from_args(&mut args, "No file to import")?;
+            import(
+                &path,
+                options.value("--format").unwrap_or("json"),
+                options.value("--encoding").unwrap_or("utf-8"),
+                options.flag("--locale-date-parse"),
+                options.flag("--sort-by-date"),
+                options.flag("--preserve-ids"),
+                parse_flush_every(&options)?,
+            )
+        }
+        "export" => {
+            let flags = [&EntryTransform::FLAGS[..], &["--preserve-ids"]].concat();
+            let valued = ["--format", "--output-dir", "--order"];
+            let options = Options::parse(&mut args, &flags, &valued, false)?;
+            let newest_first = match options.value("--order") {
+                Some("oldest-first") => Some(false),
+                Some("newest-first") => Some(true),
+                Some(_) => return Err(IoError::new(ErrorKind::InvalidInput, "Invalid order")),
+                None => None,
+            };
+            // Messages are exported as they are stored, revisions and transforms aren't applied
+            if options.value("--format") == Some("csv") {
+                if newest_first.is_some() {
+                    return Err(IoError::new(
+                        ErrorKind::InvalidInput,
+                        "Databases can't be reordered, their IDs are their positions",
+                    ));
+                }
+                let entries = Entry::parse_raw_entries(&Entry::read_raw_entries()?)?;
+                return export::csv(io::stdout().lock(), &entries);
+            }
+            // Every stored message keeps its position as its ID, revisions included
+            let mut entries = match options.flag("--preserve-ids") {
+                true => Entry::parse_raw_entries(&Entry::read_raw_entries()?)?,
+                false => Entry::read_entries()?,
+            };
+            if !options.flag("--preserve-ids") {
+                entries.retain(|e| e.revision_of().is_none());
+            }
+            if let Some(newest_first) = newest_first {
+                sort_by_date(&mut entries, newest_first);
+            }
+            let entries = EntryTransform::from_options(&options).apply(entries);
+            match options.value("--format").unwrap_or("json") {
+                "json" => export::json(io::stdout(), &entries),
+                "ndjson" => export::ndjson(io::stdout().lock(), &entries),
+                "sql" => export::sql(io::stdout(), &entries),
+                "sqlite" => {
+                    let path = collect_argument_from_args(&mut args, "No database to export to")?;
+                    export::sqlite(Path::new(&path), &entries)
+                }
+                "md-frontmatter" => {
+                    let output_dir = options.value("--output-dir").ok_or_else(|| {
+                        IoError::new(ErrorKind::InvalidInput, "Missing output directory")
+                    })?;
+                    export::markdown_front_matter(Path::new(output_dir), &entries)
+                }
+                _ => Err(IoError::new(
+                    ErrorKind::InvalidInput,
+                    "Invalid export format",
+                )),
+            }
+        }
+        "template-test" => {
+            let sample_path = collect_argument_from_args(&mut args, "No sample file")?;
+            let entries = read_sample_entries(&sample_path)?;
+            let render_options = render::RenderOptions::from_options(&Options::default())?;
+            render::render(io::stdout(), &entries, &render_options)
+                .map_err(render::report_render_error)
+        }
+        "version" => {
+            let options = Options::parse(&mut args, &["--json"], &[], false)?;
+            version(io::stdout(), options.flag("--json"))
+        }
+        _ => usage(program_name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    struct RestoreEnv(Vec<(String, String)>);
+
+    impl Drop for RestoreEnv {
+        fn drop(&mut self) {
+            for (key, _) in env::vars().filter(|(key, _)| key.starts_with("VENT_TXT_")) {
+                env::remove_var(key);
+            }
+            for (key, value) in self.0.drain(..) {
+                env::set_var(key, value);
+            }
+        }
+    }
+
+    /* Settings are read from the environment, which is shared by the tests running in parallel.
+     * The closure runs with only the given settings set, the previous ones are put back after.
+     */
+    pub fn with_env<T>(vars: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+        let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let previous = env::vars()
+            .filter(|(key, _)| key.starts_with("VENT_TXT_"))
+            .collect::<Vec<(String, String)>>();
+        let _restore = RestoreEnv(previous);
+        for (key, _) in env::vars().filter(|(key, _)| key.starts_with("VENT_TXT_")) {
+            env::remove_var(key);
+        }
+        for (key, value) in vars {
+            env::set_var(key, value);
+        }
+        f()
+    }
+
+    /* Every call gives a new empty directory, left behind for inspection when a test fails. */
+    pub fn temp_dir(name: &str) -> PathBuf {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "vent-test-{}-{name}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    pub fn parse_lines(lines: &[&str]) -> Vec<Entry> {
+        let raw_entries = lines
+            .iter()
+            .map(|line| line.to_string())
+            .collect::<Vec<String>>();
+        Entry::parse_raw_entries(&raw_entries).unwrap()
+    }
+
+    fn with_database<T>(lines: &[&str], f: impl FnOnce() -> T) -> T {
+        let csv = temp_dir("database").join("vent.csv");
+        fs::write(
+            &csv,
+            lines
+                .iter()
+                .map(|line| format!("{line}\n"))
+                .collect::<String>(),
+        )
+        .unwrap();
+        with_env(&[("VENT_TXT_CSV", csv.to_str().unwrap())], f)
+    }
+
+    const DATABASE: [&str; 3] = [
+        "2024-01-01 10:00:00 +0000,first",
+        "2024-01-02 10:00:00 +0000,>>0 second",
+        "2024-01-03 10:00:00 +0000,third",
+    ];
+
+    #[test]
+    fn edit_builds_the_message() {
+        with_database(&DATABASE, || {
+            let error = |message| edit(1, message, false).unwrap_err().to_string();
+            assert_eq!(error(">>99 hi"), "Out-of-bound reply ID");
+            assert_eq!(error(">>1 self"), "Message can't reply to itself");
+            assert_eq!(
+                error(">>2 later"),
+                "Message 1 can only reply to an earlier message"
+            );
+            assert_eq!(error(" "), "Empty message");
+            edit(2, ">>1 edited", false).unwrap();
+            let entries = Entry::read_entries().unwrap();
+            assert_eq!(
+                (entries[2].reply, entries[2].message.as_str()),
+                (Some(1), "edited")
+            );
+        });
+    }
+
+    #[test]
+    fn edit_respects_the_maximum_chain() {
+        with_database(&DATABASE, || {
+            env::set_var("VENT_TXT_MAX_CHAIN", "1");
+            assert!(edit(2, ">>1 deep", false).is_err());
+            assert!(edit_revision(2, ">>1 deep").is_err());
+            edit_revision(2, ">>0 shallow").unwrap();
+            let entries = Entry::read_entries().unwrap();
+            assert_eq!(
+                (entries[2].reply, entries[2].message.as_str()),
+                (Some(0), "shallow")
+            );
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn attachments_take_the_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = temp_dir("attachments");
+        with_env(
+            &[
+                ("VENT_TXT_ATTACHMENTS", directory.to_str().unwrap()),
+                ("VENT_TXT_FILE_MODE", "0o600"),
+            ],
+            || store_attachment("a.txt", b"content").unwrap(),
+        );
+        let metadata = fs::metadata(directory.join("a.txt")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert!(!directory.join("a.txt.tmp").exists());
+    }
+
+    #[test]
+    fn stable_anchors_survive_hard_prunes() {
+        let lines = [
+            "2023-12-31 10:00:00 +0000,old",
+            "2024-01-05 10:00:00 +0000,first",
+            "2024-01-05 10:00:00 +0000,>>1 second",
+        ];
+        with_database(&lines, || {
+            env::set_var("VENT_TXT_STABLE_ANCHORS", "1");
+            let before = render::stable_anchors(&Entry::read_entries().unwrap());
+            let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+            prune(date, true, true, DryRun::Off).unwrap();
+            let after = render::stable_anchors(&Entry::read_entries().unwrap());
+            assert_eq!(after[&0], before[&1]);
+            assert_eq!(after[&1], before[&2]);
+            assert_eq!(after[&1], "20240105T100000Z-2");
+        });
+    }
+
+    #[test]
+    fn edits_keep_stable_anchors() {
+        with_database(&DATABASE, || {
+            env::set_var("VENT_TXT_STABLE_ANCHORS", "1");
+            env::set_var("VENT_TXT_EDIT_PRESERVES_DATE", "0");
+            env::set_var("VENT_TXT_NOW", "2024-02-01 10:00:00 +0000");
+            let before = render::stable_anchors(&Entry::read_entries().unwrap());
+            edit(1, "edited", false).unwrap();
+            let entries = Entry::read_entries().unwrap();
+            assert_eq!(entries[1].date, "2024-02-01 10:00:00 +0000");
+            assert_eq!(render::stable_anchors(&entries), before);
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mapped_reads_give_the_same_lines() {
+        let directory = temp_dir("mmap");
+        let read = |content: &[u8], mmap: &str| {
+            let path = directory.join("vent.csv");
+            fs::write(&path, content).unwrap();
+            with_env(&[("VENT_TXT_MMAP", mmap)], || {
+                Entry::read_raw_entries_from(path.to_str().unwrap())
+            })
+        };
+        for content in [
+            &b""[..],
+            b"\n",
+            b"2024-01-01 10:00:00 +0000,first\n2024-01-02 10:00:00 +0000,s\xc3\xa9cond\n",
+            b"a\r\nb\r\n\nc",
+            b"no newline\r",
+        ] {
+            assert_eq!(read(content, "1").unwrap(), read(content, "0").unwrap());
+        }
+        let invalid = b"valid\n\xff\n";
+        assert_eq!(
+            read(invalid, "1").unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            read(invalid, "0").unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    /* Run with `cargo test --release -- --ignored read_benchmark --nocapture` */
+    #[cfg(unix)]
+    #[test]
+    #[ignore]
+    fn read_benchmark() {
+        let path = temp_dir("read-benchmark").join("vent.csv");
+        let line =
+            "2024-01-01 10:00:00 +0000;prompt=How was your day?,a message of average length #tag\n";
+        fs::write(&path, line.repeat(2_000_000)).unwrap();
+        let path = path.to_str().unwrap();
+        for (name, vars) in [
+            ("default buffer", &[][..]),
+            ("1 MiB buffer", &[("VENT_TXT_READ_BUFFER", "1048576")][..]),
+            ("mmap", &[("VENT_TXT_MMAP", "1")][..]),
+        ] {
+            let elapsed = with_env(vars, || {
+                let start = std::time::Instant::now();
+                for _ in 0..5 {
+                    assert_eq!(Entry::read_raw_entries_from(path).unwrap().len(), 2_000_000);
+                }
+                start.elapsed() / 5
+            });
+            println!("{name}: {elapsed:?}");
+        }
+    }
+
+    #[test]
+    fn template_tests_render_sample_entries() {
+        let directory = temp_dir("template-test");
+        let sample = directory.join("sample.json");
+        fs::write(
+            &sample,
+            r#"[{"date": "2024-01-01 10:00:00 +0000", "reply": null, "message": "sample one", "prompt": null},
+                {"date": "2024-01-02 10:00:00 +0000", "reply": 0, "message": "sample two", "prompt": null, "tags": ["x"]}]"#,
+        )
+        .unwrap();
+        let entries = read_sample_entries(sample.to_str().unwrap()).unwrap();
+        assert_eq!(entries.iter().map(|e| e.id).collect::<Vec<_>>(), [0, 1]);
+        let rendered = with_env(&[], || {
+            let options = render::RenderOptions::from_options(&Options::default()).unwrap();
+            let mut rendered = Vec::new();
+            render::render(&mut rendered, &entries, &options).unwrap();
+            String::from_utf8(rendered).unwrap()
+        });
+        assert!(rendered.contains("sample one"));
+        assert!(rendered.contains("href=\"#0\""));
+
+        fs::write(&sample, "[{\"date\": 1}]").unwrap();
+        let error = read_sample_entries(sample.to_str().unwrap()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn options_stop_at_the_message() {
+        let mut message = args(&["--prompt", "a", "--prompt", "b", "hi", "--prompt", "c"]);
+        let options = Options::parse(&mut message, &[], &["--prompt"], true).unwrap();
+        assert_eq!(options.value("--prompt"), Some("b"));
+        assert_eq!(message, ["hi", "--prompt", "c"]);
+
+        let mut message = args(&["--", "--prompt"]);
+        let options = Options::parse(&mut message, &[], &["--prompt"], true).unwrap();
+        assert_eq!(options.value("--prompt"), None);
+        assert_eq!(message, ["--prompt"]);
+
+        let error = |list: &[&str]| {
+            Options::parse(&mut args(list), &["--yes"], &["--prompt"], false)
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(error(&["--prompt"]), "No value for option --prompt");
+        assert_eq!(error(&["--no"]), "Unknown option --no");
+    }
+
+    #[test]
+    fn prompts_are_stored_in_the_metadata() {
+        with_env(&[], || {
+            let prompt = "What, today; a=b 100%?";
+            let entry = parse_lines(&[&format!(
+                "2024-01-01 10:00:00 +0000;prompt={},answer",
+                escape_metadata(prompt)
+            )])
+            .remove(0);
+            assert_eq!(entry.prompt.as_deref(), Some(prompt));
+            assert_eq!(entry.message, "answer");
+            assert_eq!(
+                entry.to_raw_entry().unwrap(),
+                "2024-01-01 10:00:00 +0000;prompt=What%2C today%3B a%3Db 100%25?,answer"
+            );
+            assert_eq!(unescape_metadata(&escape_metadata(prompt)), prompt);
+        });
+    }
+
+    #[test]
+    fn merges_resolve_conflicts_by_position() {
+        let theirs_path = temp_dir("merge").join("theirs.csv");
+        fs::write(
+            &theirs_path,
+            "2024-01-01 10:00:00 +0000,first\n2024-01-02 10:00:00 +0000,changed\n2024-01-03 10:00:00 +0000,third\n2024-01-04 10:00:00 +0000,fourth\n",
+        )
+        .unwrap();
+        let theirs = theirs_path.to_str().unwrap();
+        let merged = |policy| {
+            with_database(&DATABASE, || {
+                merge(theirs, policy)?;
+                Entry::read_raw_entries()
+            })
+        };
+        assert_eq!(
+            merged(None).unwrap_err().to_string(),
+            "Conflicting messages 1, use --prefer or --interactive"
+        );
+        let ours = merged(Some(MergePolicy::Ours)).unwrap();
+        assert_eq!(ours.len(), 4);
+        assert_eq!(ours[1], DATABASE[1]);
+        assert_eq!(ours[3], "2024-01-04 10:00:00 +0000,fourth");
+        let theirs = merged(Some(MergePolicy::Theirs)).unwrap();
+        assert_eq!(theirs[1], "2024-01-02 10:00:00 +0000,changed");
+
+        assert_eq!(merge_answer("both\n"), Some('b'));
+        assert_eq!(merge_answer(" t "), Some('t'));
+        assert_eq!(merge_answer("maybe"), None);
+    }
+
+    #[test]
+    fn conversations_follow_replies() {
+        let entries = parse_lines(&[
+            "2024-01-01 10:00:00 +0000,root",
+            "2024-01-02 10:00:00 +0000,>>0 reply",
+            "2024-01-03 10:00:00 +0000,>>1 nested",
+            "2024-01-04 10:00:00 +0000,>>0 sibling",
+            "2024-01-05 10:00:00 +0000,unrelated",
+        ]);
+        assert_eq!(conversation(&entries, 1, false), [(0, 0), (1, 1)]);
+        assert_eq!(conversation(&entries, 2, false), [(0, 0), (1, 1), (2, 2)]);
+        assert_eq!(
+            conversation(&entries, 0, true),
+            [(0, 0), (1, 1), (2, 2), (3, 1)]
+        );
+        assert_eq!(conversation(&entries, 1, true), [(0, 0), (1, 1), (2, 2)]);
+        assert_eq!(conversation(&entries, 4, true), [(4, 0)]);
+        with_env(&[], || {
+            assert_eq!(
+                show(io::sink(), &entries, 5, false)
+                    .unwrap_err()
+                    .to_string(),
+                "Out-of-bound message ID"
+            );
+        });
+    }
+
+    #[test]
+    fn removed_messages_need_a_restore() {
+        with_database(&DATABASE, || {
+            remove(2).unwrap();
+            assert_eq!(
+                edit(2, "back", false).unwrap_err().to_string(),
+                "Message 2 was removed, use --restore to edit it"
+            );
+            assert_eq!(
+                edit_revision(2, "back").unwrap_err().to_string(),
+                "Message 2 can't be revised"
+            );
+            edit(2, "back", true).unwrap();
+            assert_eq!(Entry::read_entries().unwrap()[2].message, "back");
+
+            env::set_var("VENT_TXT_REMOVED_TEXT", "gone");
+            remove(0).unwrap();
+            assert!(Entry::read_raw_entries().unwrap()[0].ends_with(",gone"));
+            assert!(edit(0, "back", false).is_err());
+        });
+    }
+
+    #[test]
+    fn one_based_ids_are_converted_at_the_edges() {
+        with_env(&[], || {
+            assert_eq!(parse_message_id("0"), Some(0));
+            assert_eq!(display_message_id(0), 0);
+        });
+        with_database(&DATABASE, || {
+            env::set_var("VENT_TXT_ONE_BASED", "1");
+            assert_eq!(parse_message_id("1"), Some(0));
+            assert_eq!(parse_message_id("0"), None);
+            assert_eq!(display_message_id(2), 3);
+            assert_eq!(
+                collect_message_id_from_args(&mut args(&["0"]))
+                    .unwrap_err()
+                    .to_string(),
+                "Invalid message ID"
+            );
+            // Replies typed by the user are 1-based too but stored 0-based
+            edit(2, ">>2 edited", false).unwrap();
+            assert_eq!(Entry::read_entries().unwrap()[2].reply, Some(1));
+        });
+    }
+
+    fn filtered_ids(entries: Vec<Entry>, options: &[&str]) -> Vec<usize> {
+        with_env(&[], || {
+            let options = Options::parse(
+                &mut args(options),
+                &EntryFilter::FLAGS,
+                &EntryFilter::VALUED,
+                false,
+            )
+            .unwrap();
+            let filter = EntryFilter::from_options(&options).unwrap();
+            filter.apply(entries).iter().map(|e| e.id).collect()
+        })
+    }
+
+    #[test]
+    fn tags_include_and_exclude_messages() {
+        let entries = || {
+            parse_lines(&[
+                "2024-01-01 10:00:00 +0000,#rust first",
+                "2024-01-02 10:00:00 +0000,#Rust #private second",
+                "2024-01-03 10:00:00 +0000,#go third",
+                "2024-01-04 10:00:00 +0000,plain",
+            ])
+        };
+        assert_eq!(filtered_ids(entries(), &[]), [0, 1, 2, 3]);
+        assert_eq!(filtered_ids(entries(), &["--tag", "rust"]), [0, 1]);
+        assert_eq!(
+            filtered_ids(entries(), &["--tag", "#RUST", "--tag", "go"]),
+            [0, 1, 2]
+        );
+        assert_eq!(
+            filtered_ids(entries(), &["--exclude-tag", "private"]),
+            [0, 2, 3]
+        );
+        assert_eq!(
+            filtered_ids(entries(), &["--tag", "rust", "--exclude-tag", "private"]),
+            [0]
+        );
+    }
+
+    #[test]
+    fn read_buffers_dont_change_the_lines() {
+        let path = temp_dir("buffer").join("vent.csv");
+        fs::write(&path, "a,é\nb,second line\n").unwrap();
+        let read = |size: &str| {
+            with_env(&[("VENT_TXT_READ_BUFFER", size)], || {
+                let expected = size.parse().ok().filter(|&size| size > 0);
+                assert_eq!(get_read_buffer_size(), expected.unwrap_or(8192));
+                Entry::read_raw_entries_from(path.to_str().unwrap()).unwrap()
+            })
+        };
+        for size in ["1", "3", "0", "invalid"] {
+            assert_eq!(read(size), ["a,é", "b,second line"], "{size}");
+        }
+    }
+
+    #[test]
+    fn prunes_remove_or_delete_old_messages() {
+        let lines = [
+            "2023-12-30 10:00:00 +0000,old",
+            "2023-12-31 10:00:00 +0000,>>0 old reply",
+            "2024-01-01 10:00:00 +0000,>>1 new",
+            "2024-01-02 10:00:00 +0000,>>2 newer",
+        ];
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        with_database(&lines, || {
+            prune(date, false, false, DryRun::Off).unwrap();
+            assert_eq!(Entry::read_raw_entries().unwrap(), lines);
+            prune(date, false, true, DryRun::Off).unwrap();
+            assert_eq!(
+                Entry::read_raw_entries().unwrap(),
+                [
+                    "2023-12-30 10:00:00 +0000,[removed]",
+                    "2023-12-31 10:00:00 +0000,[removed]",
+                    lines[2],
+                    lines[3],
+                ]
+            );
+        });
+        with_database(&lines, || {
+            prune(date, true, true, DryRun::Off).unwrap();
+            let entries = Entry::read_entries().unwrap();
+            assert_eq!(entries.len(), 2);
+            assert_eq!(
+                (entries[0].reply, entries[0].message.as_str()),
+                (None, "new")
+            );
+            assert_eq!(
+                (entries[1].reply, entries[1].message.as_str()),
+                (Some(0), "newer")
+            );
+        });
+    }
+
+    #[test]
+    fn counts_skip_parsing_when_raw() {
+        let lines = [
+            "2024-01-01 10:00:00 +0000,first",
+            "2024-01-02 10:00:00 +0000,>>0 second",
+            "2024-01-03 10:00:00 +0000,[removed]",
+            "no date",
+        ];
+        with_database(&lines, || {
+            assert_eq!(counts(true).unwrap(), [("total", 4)]);
+            assert_eq!(
+                counts(false).unwrap_err().to_string(),
+                "Line 4: No date in entry"
+            );
+        });
+        with_database(&lines[..3], || {
+            assert_eq!(
+                counts(false).unwrap(),
+                [("total", 3), ("replies", 1), ("removed", 1)]
+            );
+        });
+    }
+
+    #[test]
+    fn removed_text_is_configurable() {
+        with_env(&[], || assert_eq!(get_removed_text(), "[removed]"));
+        for invalid in ["", "multi\nline", "carriage\rreturn"] {
+            with_env(&[("VENT_TXT_REMOVED_TEXT", invalid)], || {
+                assert_eq!(get_removed_text(), "[removed]")
+            });
+        }
+        with_database(&DATABASE, || {
+            env::set_var("VENT_TXT_REMOVED_TEXT", "(gone)");
+            remove(0).unwrap();
+            let raw_entries = Entry::read_raw_entries().unwrap();
+            assert!(raw_entries[0].ends_with(",(gone)"));
+            assert!(is_removed_raw_entry(&raw_entries[0]));
+            assert!(!is_removed_raw_entry("2024-01-01 10:00:00 +0000,[removed]"));
+            assert_eq!(counts(false).unwrap()[2], ("removed", 1));
+        });
+    }
+
+    #[test]
+    fn streamed_lines_are_added_one_by_one() {
+        with_database(&DATABASE, || {
+            let input = ">>2 reply\n \n>>99 invalid\n>>3 reply to the stream\n#tagged\n";
+            add_stream(input.as_bytes(), Some("stream"), 2).unwrap();
+            let entries = Entry::read_entries().unwrap();
+            let added = entries[3..]
+                .iter()
+                .map(|e| (e.reply, e.message.as_str(), e.prompt.as_deref()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                added,
+                [
+                    (Some(2), "reply", Some("stream")),
+                    (Some(3), "reply to the stream", Some("stream")),
+                    (None, "#tagged", Some("stream")),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn json_errors_carry_the_database_line() {
+        with_database(&["2024-01-01 10:00:00 +0000,first", "no date"], || {
+            let error = Entry::read_entries().unwrap_err();
+            assert_eq!(
+                json_error(&error),
+                serde_json::json!({"error": "No date in entry", "kind": "InvalidData", "line": 2})
+            );
+        });
+        let error = IoError::new(ErrorKind::InvalidInput, "Invalid message ID");
+        assert_eq!(
+            json_error(&error),
+            serde_json::json!({"error": "Invalid message ID", "kind": "InvalidInput"})
+        );
+    }
+
+    #[test]
+    fn tag_feeds_keep_the_ids_of_the_whole_feed() {
+        let lines = [
+            "2024-01-01 10:00:00 +0000,#rust first",
+            "2024-01-01 10:00:00 +0000,#go second",
+            "2024-01-01 10:00:00 +0000,#rust third",
+        ];
+        let output = temp_dir("feeds").join("rust.atom");
+        let feed = with_database(&lines, || {
+            env::set_var("VENT_TXT_STABLE_ANCHORS", "1");
+            let mut args = args(&["--feed", "atom", "--tag", "rust", "--output"]);
+            args.push(output.to_str().unwrap().to_owned());
+            let valued = [&EntryFilter::VALUED[..], &render::RenderOptions::VALUED[..]].concat();
+            let options = Options::parse(&mut args, &[], &valued, false).unwrap();
+            let entries = Entry::read_entries().unwrap();
+            let render_options = render::RenderOptions::from_options(&options)
+                .unwrap()
+                .with_anchors(&entries);
+            let entries = EntryFilter::from_options(&options).unwrap().apply(entries);
+            render::render_with_options(entries, &render_options).unwrap();
+            fs::read_to_string(&output).unwrap()
+        });
+        assert_eq!(feed.matches("<entry>").count(), 2);
+        assert!(!feed.contains("second"));
+        assert!(feed.contains("<id>urn:vent-txt:20240101T100000Z</id>"));
+        assert!(feed.contains("<id>urn:vent-txt:20240101T100000Z-3</id>"));
+    }
+
+    #[test]
+    fn previews_cut_whole_characters() {
+        assert_eq!(truncate_text("short", 5), "short");
+        assert_eq!(truncate_text("shorter", 5), "short…");
+        assert_eq!(truncate_text("", 0), "");
+        assert_eq!(truncate_text("abc", 0), "…");
+        assert_eq!(truncate_text("héllo wörld", 7), "héllo w…");
+        // Combining marks and joiners stay with the character before them
+        assert_eq!(truncate_text("e\u{301}e\u{301}e", 2), "e\u{301}e\u{301}…");
+        assert_eq!(truncate_text("👍\u{fe0f}!", 1), "👍\u{fe0f}…");
+    }
+
+    #[test]
+    fn reply_whitespace_is_trimmed_unless_disabled() {
+        let lines = [
+            "2024-01-01 10:00:00 +0000,first",
+            "2024-01-02 10:00:00 +0000,>>0   second",
+        ];
+        let message =
+            |vars: &[(&str, &str)]| with_env(vars, || parse_lines(&lines)[1].message.clone());
+        assert_eq!(message(&[]), "second");
+        assert_eq!(
+            message(&[("VENT_TXT_TRIM_REPLY_WHITESPACE", "0")]),
+            "   second"
+        );
+        assert_eq!(
+            message(&[("VENT_TXT_TRIM_REPLY_WHITESPACE", "no")]),
+            "   second"
+        );
+        assert_eq!(
+            message(&[("VENT_TXT_TRIM_REPLY_WHITESPACE", "maybe")]),
+            "second"
+        );
+    }
+
+    #[test]
+    fn repeated_tags_are_counted_once() {
+        let lines = [
+            "2024-01-01 10:00:00 +0000,#rust #Rust #rust! #go",
+            "2024-01-02 10:00:00 +0000,#go #til-2024 # #",
+        ];
+        let tags = |vars: &[(&str, &str)]| with_env(vars, || parse_lines(&lines)[0].tags.clone());
+        assert_eq!(tags(&[]), ["rust", "go"]);
+        assert_eq!(
+            tags(&[("VENT_TXT_DEDUPE_TAGS", "0")]),
+            ["rust", "rust", "rust", "go"]
+        );
+
+        let entries = with_env(&[], || parse_lines(&lines));
+        let counts = |sort, min_count| {
+            tag_summaries(&entries, sort, min_count)
+                .iter()
+                .map(|s| (s.tag.to_owned(), s.count))
+                .collect::<Vec<(String, usize)>>()
+        };
+        let count = |tag: &str, count| (tag.to_owned(), count);
+        assert_eq!(
+            counts(TagSort::Count, 0),
+            [count("go", 2), count("rust", 1), count("til-2024", 1)]
+        );
+        assert_eq!(
+            counts(TagSort::AlphaDesc, 0),
+            [count("til-2024", 1), count("rust", 1), count("go", 2)]
+        );
+        assert_eq!(counts(TagSort::Alpha, 2), [count("go", 2)]);
+    }
+
+    #[test]
+    fn verify_fixes_broken_replies() {
+        let lines = [
+            "2024-01-01 10:00:00 +0000,[removed]",
+            "2024-01-02 10:00:00 +0000,>>0 reply to a removed message",
+            "2024-01-03 10:00:00 +0000,>>2 reply to itself",
+            "2024-01-04 10:00:00 +0000,>>9 reply to nothing",
+            "2024-01-05 10:00:00 +0000,>>1 fine",
+        ];
+        with_database(&lines, || {
+            assert_eq!(verify(false).unwrap_err().to_string(), "3 problems found");
+            assert_eq!(Entry::read_raw_entries().unwrap(), lines);
+            verify(true).unwrap();
+            let replies = Entry::read_entries()
+                .unwrap()
+                .iter()
+                .map(|e| e.reply)
+                .collect::<Vec<Option<usize>>>();
+            assert_eq!(replies, [None, None, None, None, Some(1)]);
+            verify(false).unwrap();
+        });
+        with_database(&["no date"], || {
+            assert_eq!(verify(true).unwrap_err().to_string(), "1 problem found");
+        });
+    }
+
+    #[test]
+    fn edits_keep_the_date_and_record_edited_at() {
+        with_database(&DATABASE, || {
+            env::set_var("VENT_TXT_NOW", "2024-02-01 10:00:00 +0000");
+            edit(0, "edited", false).unwrap();
+            env::set_var("VENT_TXT_NOW", "2024-02-02 10:00:00 +0000");
+            edit(0, "edited again", false).unwrap();
+            assert_eq!(
+                Entry::read_raw_entries().unwrap()[0],
+                "2024-01-01 10:00:00 +0000;edited_at=2024-02-02 10:00:00 +0000,edited again"
+            );
+
+            env::set_var("VENT_TXT_EDIT_PRESERVES_DATE", "0");
+            edit(2, "overwritten", false).unwrap();
+            assert_eq!(
+                Entry::read_raw_entries().unwrap()[2],
+                "2024-02-02 10:00:00 +0000,overwritten"
+            );
+        });
+    }
+
+    #[test]
+    fn highlights_mark_the_original_text() {
+        let highlight = |pattern: &str, keep_accents, text: &str| {
+            Highlight::new(fold_text(pattern, keep_accents), keep_accents, true)
+                .unwrap()
+                .apply(text)
+        };
+        assert_eq!(
+            highlight("Rust", true, "I like rust and RUST"),
+            "I like **rust** and **RUST**"
+        );
+        assert_eq!(highlight("aa", true, "aaa"), "**aa**a");
+        assert_eq!(highlight("", true, "text"), "text");
+        assert_eq!(highlight("cafe", true, "Café"), "Café");
+        assert_eq!(highlight("cafe", false, "Un Café"), "Un **Café**");
+        assert_eq!(highlight("e", false, "e\u{301}t"), "**e\u{301}**t");
+    }
+
+    #[test]
+    fn init_creates_the_files_unless_they_exist() {
+        let directory = temp_dir("init");
+        let csv = directory.join("vent.csv");
+        let template = directory.join("template").join("vent.hbs");
+        let vars = [
+            ("VENT_TXT_CSV", csv.to_str().unwrap()),
+            ("VENT_TXT_HBS", template.to_str().unwrap()),
+        ];
+        with_env(&vars, || {
+            init(false).unwrap();
+            assert_eq!(fs::read_to_string(&csv).unwrap(), "");
+            assert_eq!(fs::read_to_string(&template).unwrap(), DEFAULT_TEMPLATE);
+
+            fs::write(&csv, "2024-01-01 10:00:00 +0000,kept\n").unwrap();
+            fs::remove_file(&template).unwrap();
+            let error = init(false).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+            assert!(!template.exists());
+            init(true).unwrap();
+            assert_eq!(fs::read_to_string(&csv).unwrap(), "");
+            assert!(template.exists());
+        });
+    }
+
+    #[test]
+    fn missing_databases_can_render_empty() {
+        let csv = temp_dir("null-data").join("missing.csv");
+        with_env(&[("VENT_TXT_CSV", csv.to_str().unwrap())], || {
+            let error = read_entries_or_empty(false).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::NotFound);
+            let entries = read_entries_or_empty(true).unwrap();
+            assert!(entries.is_empty());
+            let options = render::RenderOptions::from_options(&Options::default()).unwrap();
+            let mut rendered = Vec::new();
+            render::render(&mut rendered, &entries, &options).unwrap();
+            assert!(!rendered.is_empty());
+        });
+        with_database(&["no date"], || {
+            assert!(read_entries_or_empty(true).is_err())
+        });
+    }
+
+    #[test]
+    fn confirmed_adds_show_the_stored_line() {
+        with_database(&DATABASE, || {
+            env::set_var("VENT_TXT_NOW", "2024-02-01 10:00:00 +0000");
+            let mut entry = EntryBuilder::new(">>2 confirmed")
+                .build(&Entry::read_entries().unwrap())
+                .unwrap();
+            entry.spoiler = true;
+            add(entry, &[], true, true).unwrap();
+            assert_eq!(
+                Entry::read_raw_entries().unwrap()[3],
+                "2024-02-01 10:00:00 +0000;sensitive=1,>>2 confirmed"
+            );
+        });
+        assert!(confirm_answer("y\n"));
+        assert!(confirm_answer(" yes "));
+        assert!(!confirm_answer("\n"));
+        assert!(!confirm_answer("no"));
+    }
+
+    #[test]
+    fn rfc_3339_dates_are_accepted() {
+        let stored = parse_date("2024-01-01 10:00:00 +0100").unwrap();
+        assert_eq!(parse_date("2024-01-01T10:00:00+01:00"), Some(stored));
+        assert_eq!(parse_date("2024-01-01T09:00:00Z"), Some(stored));
+        assert_eq!(parse_date("2024-01-01T10:00:00"), None);
+        assert_eq!(parse_date("yesterday"), None);
+
+        let entries = with_env(&[], || {
+            parse_lines(&[
+                "2024-01-01T09:00:00.5Z,from another tool",
+                "2024-01-02 10:00:00 +0000,later",
+            ])
+        });
+        // Dates are displayed as they are stored
+        assert_eq!(entries[0].date, "2024-01-01T09:00:00.5Z");
+        assert!(parse_date(&entries[0].date) < parse_date(&entries[1].date));
+    }
+
+    #[test]
+    fn tag_co_occurrences_count_messages() {
+        let entries = with_env(&[("VENT_TXT_DEDUPE_TAGS", "0")], || {
+            parse_lines(&[
+                "2024-01-01 10:00:00 +0000,#rust #til #rust",
+                "2024-01-02 10:00:00 +0000,#rust #go",
+                "2024-01-03 10:00:00 +0000,#go",
+            ])
+        });
+        let summaries = tag_summaries(&entries, TagSort::Alpha, 1);
+        assert_eq!(
+            serde_json::to_value(&summaries).unwrap(),
+            serde_json::json!([
+                {"tag": "go", "count": 2, "co_occurrences": {"rust": 1}},
+                {"tag": "rust", "count": 3, "co_occurrences": {"go": 1, "til": 1}},
+                {"tag": "til", "count": 1, "co_occurrences": {"rust": 1}},
+            ])
+        );
+    }
+
+    #[test]
+    fn rewrites_replace_the_database() {
+        let path = temp_dir("rewrite").join("vent.csv");
+        let path = path.to_str().unwrap();
+        fs::write(path, "old\n").unwrap();
+        with_env(&[], || {
+            write_raw_entries_to(path, &[String::from("a,new")]).unwrap()
+        });
+        assert_eq!(fs::read_to_string(path).unwrap(), "a,new\n");
+        assert!(!Path::new(&format!("{path}.tmp")).exists());
+
+        // The fallback used when renaming crosses filesystems
+        let temporary_path = format!("{path}.tmp");
+        fs::write(&temporary_path, "a,copied\n").unwrap();
+        copy_over(&temporary_path, path).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "a,copied\n");
+        assert!(!Path::new(&temporary_path).exists());
+    }
+
+    #[test]
+    fn syncs_only_happen_when_enabled() {
+        let missing = temp_dir("fsync").join("missing").join("vent.csv");
+        let missing = missing.to_str().unwrap();
+        with_env(&[], || sync_database_directory(missing).unwrap());
+        with_env(&[("VENT_TXT_FSYNC", "1")], || {
+            sync_database_directory("vent.csv").unwrap();
+            if cfg!(unix) {
+                assert!(sync_database_directory(missing).is_err());
+            }
+        });
+        let syncs = || SYNCS.with(|syncs| syncs.replace(0));
+        /* Rewrites sync the new file, then the directory holding it on Unix. */
+        let per_rewrite = if cfg!(unix) { 2 } else { 1 };
+        with_database(&DATABASE, || {
+            syncs();
+            edit(0, "unsynced", false).unwrap();
+            remove(2).unwrap();
+            assert_eq!(syncs(), 0);
+            env::set_var("VENT_TXT_FSYNC", "1");
+            edit(0, "synced", false).unwrap();
+            assert_eq!(syncs(), per_rewrite);
+            remove(1).unwrap();
+            assert_eq!(syncs(), per_rewrite);
+            assert_eq!(Entry::read_entries().unwrap()[0].message, "synced");
+        });
+    }
+
+    #[test]
+    fn oversized_databases_are_refused() {
+        with_database(&DATABASE, || {
+            let size = fs::metadata(get_csv_path()).unwrap().len();
+            env::set_var("VENT_TXT_MAX_FILE_BYTES", size.to_string());
+            assert_eq!(Entry::read_entries().unwrap().len(), 3);
+            env::set_var("VENT_TXT_MAX_FILE_BYTES", (size - 1).to_string());
+            let error = Entry::read_entries().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::FileTooLarge);
+            assert!(error.to_string().contains(&format!("is {size} bytes")));
+            env::set_var("VENT_TXT_MAX_FILE_BYTES", "0");
+            assert!(Entry::read_entries().is_ok());
+        });
+    }
+
+    #[test]
+    fn scheduled_messages_are_hidden_until_published() {
+        let lines = [
+            "2024-01-01 10:00:00 +0000,first",
+            "2024-01-01 11:00:00 +0000;scheduled=2024-01-02 10:00:00 +0000;prompt=p,due",
+            "2024-01-01 12:00:00 +0000;scheduled=2024-01-09 10:00:00 +0000,later",
+        ];
+        with_database(&lines, || {
+            let entries = Entry::read_entries().unwrap();
+            assert_eq!(count_shown_entries(&entries), 1);
+            let filter = EntryFilter::from_options(&Options::default()).unwrap();
+            assert_eq!(filter.apply(entries).len(), 1);
+
+            env::set_var("VENT_TXT_NOW", "2024-01-05 10:00:00 +0000");
+            publish_due().unwrap();
+            let raw_entries = Entry::read_raw_entries().unwrap();
+            assert_eq!(raw_entries[1], "2024-01-02 10:00:00 +0000;prompt=p,due");
+            assert_eq!(raw_entries[2], lines[2]);
+            assert_eq!(count_shown_entries(&Entry::read_entries().unwrap()), 2);
+        });
+        assert!(parse_publish_date("2024-01-02 10:00").is_ok());
+        assert_eq!(
+            parse_publish_date("tomorrow").unwrap_err().to_string(),
+            "Invalid publish date"
+        );
+    }
+
+    #[test]
+    fn stripped_tags_keep_the_lines() {
+        assert_eq!(
+            EntryTransform::strip_tags("#rust  is fun @friend # @"),
+            "is fun # @"
+        );
+        assert_eq!(
+            EntryTransform::strip_tags("first line #tag\n\nthird @me line"),
+            "first line\n\nthird line"
+        );
+        let entries = with_env(&[], || parse_lines(&["2024-01-01 10:00:00 +0000,#til hi"]));
+        let options = Options::parse(
+            &mut args(&["--strip-tags"]),
+            &EntryTransform::FLAGS,
+            &[],
+            false,
+        )
+        .unwrap();
+        let entries = EntryTransform::from_options(&options).apply(entries);
+        assert_eq!(entries[0].message, "hi");
+        assert_eq!(entries[0].tags, ["til"]);
+    }
+
+    #[test]
+    fn consecutive_duplicates_are_folded() {
+        let entries = with_env(&[], || {
+            parse_lines(&[
+                "2024-01-01 10:00:00 +0000,same",
+                "2024-01-01 10:01:00 +0000,same",
+                "2024-01-01 10:02:00 +0000,same",
+                "2024-01-01 10:03:00 +0000,>>0 same",
+                "2024-01-01 10:04:00 +0000,other",
+                "2024-01-01 10:05:00 +0000,same",
+            ])
+        });
+        let options = Options::parse(
+            &mut args(&["--fold-duplicates"]),
+            &EntryTransform::FLAGS,
+            &[],
+            false,
+        )
+        .unwrap();
+        let entries = EntryTransform::from_options(&options).apply(entries);
+        let folded = entries
+            .iter()
+            .map(|entry| (entry.id, entry.metadata.get("folded").map(String::as_str)))
+            .collect::<Vec<_>>();
+        assert_eq!(folded, [(0, Some("3")), (3, None), (4, None), (5, None)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn clipboards_are_read_with_the_configured_command() {
+        with_env(&[("VENT_TXT_CLIPBOARD", "printf 'copied\\ntext'")], || {
+            assert_eq!(read_clipboard().unwrap(), "copied\ntext");
+        });
+        with_env(&[("VENT_TXT_CLIPBOARD", "exit 3")], || {
+            assert!(read_clipboard().is_err());
+        });
+        with_env(&[("VENT_TXT_CLIPBOARD", "printf '\\377'")], || {
+            let error = read_clipboard().unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        });
+    }
+
+    #[test]
+    fn replies_can_be_migrated_to_the_metadata() {
+        with_database(&DATABASE, || {
+            env::set_var("VENT_TXT_REPLY_STORAGE", "metadata");
+            migrate_replies(DryRun::Off).unwrap();
+            let raw_entries = Entry::read_raw_entries().unwrap();
+            assert_eq!(raw_entries[1], "2024-01-02 10:00:00 +0000;reply=0,second");
+            assert_eq!(raw_entries[2], DATABASE[2]);
+            let entries = Entry::parse_raw_entries(&raw_entries).unwrap();
+            assert_eq!(entries[1].reply, Some(0));
+            assert_eq!(entries[1].message, "second");
+
+            assert_eq!(
+                store_raw_entry_reply("a;reply=0,>>1 edited"),
+                "a;reply=1,edited"
+            );
+            env::remove_var("VENT_TXT_REPLY_STORAGE");
+            assert_eq!(
+                set_raw_entry_reply("a;reply=0,moved", Some(1)),
+                "a,>>1 moved"
+            );
+            assert_eq!(set_raw_entry_reply("a,>>0 dropped", None), "a,dropped");
+        });
+    }
+
+    #[test]
+    fn revisions_replace_the_message_they_revise() {
+        with_database(&DATABASE, || {
+            edit_revision(0, "first revised #fix").unwrap();
+            edit_revision(0, "first again").unwrap();
+            let entries = Entry::read_entries().unwrap();
+            assert_eq!(entries.len(), 5);
+            assert_eq!(entries[0].message, "first again");
+            assert!(entries[0].tags.is_empty());
+            assert!(entries[0].metadata.contains_key("revised_at"));
+            assert_eq!(entries[3].revision_of(), Some(0));
+            let raw_entries = Entry::read_raw_entries().unwrap();
+            assert_eq!(raw_entries[0], DATABASE[0]);
+
+            let error = edit(0, "overwritten", false).unwrap_err().to_string();
+            assert_eq!(error, "Message 0 has revisions, use --revision to edit it");
+            let error = edit_revision(3, "revised revision")
+                .unwrap_err()
+                .to_string();
+            assert_eq!(error, "Message 3 can't be revised");
+        });
+    }
+
+    #[test]
+    fn orphaned_replies_become_top_level_messages() {
+        let lines = [
+            "2024-01-01 10:00:00 +0000,[removed]",
+            "2024-01-02 10:00:00 +0000,>>0 second",
+            "2024-01-03 10:00:00 +0000,>>9 third",
+            "2024-01-04 10:00:00 +0000,>>1 fourth",
+        ];
+        with_database(&lines, || {
+            let raw_entries = Entry::read_raw_entries().unwrap();
+            let entries = Entry::parse_raw_entries(&raw_entries).unwrap();
+            let problems = entries
+                .iter()
+                .map(|e| orphaned_reply(e, &raw_entries))
+                .collect::<Vec<Option<&str>>>();
+            assert_eq!(
+                problems,
+                [
+                    None,
+                    Some("replies to a removed message"),
+                    Some("replies to a non-existent message"),
+                    None,
+                ]
+            );
+
+            orphans(false, DryRun::Off).unwrap();
+            assert_eq!(Entry::read_raw_entries().unwrap(), raw_entries);
+            orphans(true, DryRun::Off).unwrap();
+            assert_eq!(
+                Entry::read_raw_entries().unwrap(),
+                [
+                    lines[0],
+                    "2024-01-02 10:00:00 +0000,second",
+                    "2024-01-03 10:00:00 +0000,third",
+                    lines[3],
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn sensitive_messages_are_spoilers() {
+        let line = "2024-01-01 10:00:00 +0000;sensitive=1,bad day";
+        let entries = with_env(&[], || {
+            parse_lines(&[line, "2024-01-02 10:00:00 +0000;sensitive=0,fine"])
+        });
+        assert!(entries[0].spoiler);
+        assert!(!entries[1].spoiler);
+        assert!(!entries[0].metadata.contains_key("sensitive"));
+        assert_eq!(entries[0].to_raw_entry().unwrap(), line);
+        let json = serde_json::to_value(&entries[0]).unwrap();
+        assert_eq!(json["spoiler"], true);
+    }
+
+    #[test]
+    fn raw_json_has_the_computed_fields() {
+        let entries = with_env(&[], || {
+            parse_lines(&[
+                "2024-01-01 10:00:00 +0000,first",
+                "2024-01-02 10:00:00 +0000,>>0 second",
+                "2024-01-03 10:00:00 +0000,>>1 third",
+                "2024-01-04 10:00:00 +0000,>>1 fourth",
+                "2024-01-05 10:00:00 +0000;revision_of=1,>>0 second revised",
+            ])
+        });
+        let details = |id| {
+            let mut json = Vec::new();
+            show_json(&mut json, &entries, id).unwrap();
+            serde_json::from_slice::<serde_json::Value>(&json).unwrap()
+        };
+        let second = details(1);
+        assert_eq!(second["message"], "second");
+        assert_eq!(second["depth"], 1);
+        assert_eq!(second["backlinks"], serde_json::json!([2, 3]));
+        assert_eq!(second["timestamp"], 1704189600);
+        assert_eq!(details(0)["depth"], 0);
+        assert_eq!(details(0)["backlinks"], serde_json::json!([1]));
+        let mut json = Vec::new();
+        assert!(show_json(&mut json, &entries, 5).is_err());
+    }
+
+    #[test]
+    fn line_formats_place_each_field_once() {
+        assert_eq!(
+            format_line(
+                "{date};source=phone{metadata},{message}",
+                "d",
+                ";p=1",
+                "{date} {x"
+            ),
+            "d;source=phone;p=1,{date} {x"
+        );
+        with_env(
+            &[(
+                "VENT_TXT_LINE_FORMAT",
+                "{date};source=phone{metadata},{message}",
+            )],
+            || {
+                validate_line_format().unwrap();
+                assert_eq!(
+                    format_raw_entry_line("2024-01-01 10:00:00 +0000", ">>0 hi", &[]),
+                    "2024-01-01 10:00:00 +0000;source=phone,>>0 hi"
+                );
+                let mut entry = EntryBuilder::new("a\nb")
+                    .multiline(true)
+                    .date(Some("2024-01-01 10:00:00 +0000"))
+                    .build(&[])
+                    .unwrap();
+                entry.spoiler = true;
+                entry.attachments = vec![String::from("x.png"), String::from("y.png")];
+                let scheduled = String::from("2024-01-02 10:00:00 +0000");
+                entry.metadata.insert(String::from("scheduled"), scheduled);
+                assert_eq!(
+                    entry.to_new_raw_entry(),
+                    "2024-01-01 10:00:00 +0000;source=phone;scheduled=2024-01-02 10:00:00 +0000;\
+                     sensitive=1;attachments=x.png y.png;multiline=1,a\\nb"
+                );
+            },
+        );
+        with_env(&[("VENT_TXT_LINE_FORMAT", "{message},{date}")], || {
+            let error = validate_line_format().unwrap_err().to_string();
+            assert!(error.starts_with("Invalid line format"), "{error}");
+        });
+    }
+
+    #[test]
+    fn starred_messages_can_be_filtered() {
+        let entries = with_database(&DATABASE, || {
+            star(1, true).unwrap();
+            star(1, true).unwrap();
+            star(2, true).unwrap();
+            star(2, false).unwrap();
+            let raw_entries = Entry::read_raw_entries().unwrap();
+            assert_eq!(
+                raw_entries[1],
+                "2024-01-02 10:00:00 +0000;starred=1,>>0 second"
+            );
+            assert_eq!(raw_entries[2], DATABASE[2]);
+            Entry::read_entries().unwrap()
+        });
+        assert!(entries[1].starred);
+        assert_eq!(filtered_ids(entries, &["--starred-only"]), [1]);
+        assert_eq!(
+            set_raw_entry_field("a;x=1;y=2;x=3,b", "x", Some("4")),
+            "a;y=2;x=4,b"
+        );
+    }
+
+    #[test]
+    fn folded_text_drops_case_and_accents() {
+        assert_eq!(fold_text("Crème Brûlée", false), "creme brulee");
+        assert_eq!(fold_text("Crème Brûlée", true), "crème brûlée");
+        assert_eq!(fold_text("ŁÓDŹ", false), "lodz");
+        assert_eq!(fold_text("Cafe\u{301}", false), fold_text("Café", false));
+        assert_eq!(fold_text("日本 ß", false), "日本 ß");
+    }
+
+    #[test]
+    fn dry_runs_plan_the_changes() {
+        let old = DATABASE.map(String::from);
+        let new = [
+            old[0].clone(),
+            String::from("2024-01-03 10:00:00 +0000,edited"),
+        ];
+        let plan = plan_changes(&old, &new, Some(&[Some(0), None, Some(1)]));
+        let mut text = Vec::new();
+        write_plan(&mut text, &plan, DryRun::Text).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "Message 1 would be deleted\n\
+             Message 2 would be rewritten as 2024-01-03 10:00:00 +0000,edited\n"
+        );
+        let mut json = Vec::new();
+        write_plan(&mut json, &plan, DryRun::Json).unwrap();
+        let json = serde_json::from_slice::<serde_json::Value>(&json).unwrap();
+        assert_eq!(json[0]["action"], "delete");
+        assert_eq!(json[0]["new"], serde_json::Value::Null);
+        assert_eq!(json[1]["action"], "edit");
+        assert_eq!(json[1]["old"], DATABASE[2]);
+    }
+
+    #[test]
+    fn replies_are_indented_by_their_depth() {
+        let entries = with_env(&[], || {
+            parse_lines(&[
+                "2024-01-01 10:00:00 +0000,first",
+                "2024-01-02 10:00:00 +0000;prompt=why;multiline=1,>>0 second\\nline",
+                "2024-01-03 10:00:00 +0000,>>1 third",
+            ])
+        });
+        let shown = |vars: &[(&str, &str)]| {
+            let mut text = Vec::new();
+            with_env(vars, || show(&mut text, &entries, 0, true)).unwrap();
+            String::from_utf8(text).unwrap()
+        };
+        assert_eq!(
+            shown(&[("VENT_TXT_INDENT", "| ")]),
+            "0 2024-01-01 10:00:00 +0000 first\n\
+             | 1 2024-01-02 10:00:00 +0000 >>0 second\n\
+             |     line\n\
+             |     prompt: why\n\
+             | | 2 2024-01-03 10:00:00 +0000 >>1 third\n"
+        );
+        assert!(shown(&[]).contains("\n    2 2024-01-03"));
+    }
+
+    #[test]
+    fn id_bounds_are_inclusive_or_exclusive() {
+        let ids = |options: &[&str]| {
+            let lines = (0..6)
+                .map(|day| format!("2024-01-0{} 10:00:00 +0000,message", day + 1))
+                .collect::<Vec<String>>();
+            let lines = lines.iter().map(String::as_str).collect::<Vec<&str>>();
+            filtered_ids(with_env(&[], || parse_lines(&lines)), options)
+        };
+        assert_eq!(ids(&["--since-id", "1", "--until-id", "3"]), [1, 2, 3]);
+        assert_eq!(ids(&["--after-id", "1", "--before-id", "3"]), [2]);
+        assert_eq!(ids(&["--after-id", "3"]), [4, 5]);
+        assert_eq!(ids(&["--since-id", "0", "--after-id", "4"]), [5]);
+        assert!(ids(&["--after-id", "4", "--before-id", "4"]).is_empty());
+        let options = Options::parse(
+            &mut args(&["--since-id", "one"]),
+            &EntryFilter::FLAGS,
+            &EntryFilter::VALUED,
+            false,
+        )
+        .unwrap();
+        assert!(with_env(&[], || EntryFilter::from_options(&options)).is_err());
+    }
+
+    #[test]
+    fn attachments_are_named_by_their_content() {
+        let directory = temp_dir("attach");
+        let photo = directory.join("Photo.JPG");
+        fs::write(&photo, "abc").unwrap();
+        let store = directory.join("attachments");
+        with_database(&DATABASE, || {
+            env::set_var("VENT_TXT_ATTACHMENTS", store.to_str().unwrap());
+            env::set_var("VENT_TXT_NOW", "2024-02-01 10:00:00 +0000");
+            let entry = || {
+                EntryBuilder::new("look")
+                    .build(&Entry::read_entries().unwrap())
+                    .unwrap()
+            };
+            let paths = [photo.to_str().unwrap().to_owned()];
+            add(entry(), &paths, false, false).unwrap();
+            let name = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad.jpg";
+            assert_eq!(
+                Entry::read_raw_entries().unwrap()[3],
+                format!("2024-02-01 10:00:00 +0000;attachments={name},look")
+            );
+            assert_eq!(Entry::read_entries().unwrap()[3].attachments, [name]);
+            assert_eq!(fs::read_to_string(store.join(name)).unwrap(), "abc");
+
+            let missing = [directory.join("missing").to_str().unwrap().to_owned()];
+            assert!(add(entry(), &missing, false, false).is_err());
+            assert_eq!(Entry::read_raw_entries().unwrap().len(), 4);
+        });
+    }
+
+    #[test]
+    fn files_are_stored_as_one_multiline_message() {
+        let path = temp_dir("file").join("note.txt");
+        fs::write(&path, "\r\n>>0 title\r\nC:\\path\\n\r\n\r\n").unwrap();
+        let content = read_message_file(path.to_str().unwrap()).unwrap();
+        let entry = with_env(&[], || {
+            EntryBuilder::new(&content)
+                .multiline(true)
+                .build(&parse_lines(&DATABASE))
+                .unwrap()
+        });
+        let message = entry.message.clone();
+        assert_eq!(entry.reply, Some(0));
+        assert_eq!(message, "title\nC:\\path\\n");
+        let escaped = escape_message(&message);
+        assert_eq!(escaped, "title\\nC:\\\\path\\\\n");
+        assert_eq!(unescape_message(&escaped), message);
+
+        let line = format!("2024-01-01 10:00:00 +0000;multiline=1,>>0 {escaped}");
+        let parsed = with_env(&[], || parse_lines(&[&line]).remove(0));
+        assert_eq!(parsed.reply, Some(0));
+        assert_eq!(parsed.message, message);
+        assert_eq!(with_env(&[], || parsed.to_raw_entry()).unwrap(), line);
+
+        let missing = path.with_file_name("missing.txt");
+        let error = read_message_file(missing.to_str().unwrap()).unwrap_err();
+        assert!(error.to_string().starts_with("Unable to read"));
+    }
+
+    #[test]
+    fn reading_times_are_rounded_up() {
+        let words = |count| vec!["word"; count].join(" ");
+        let seconds = |vars: &[(&str, &str)], message: &str| {
+            with_env(vars, || Entry::reading_time_seconds(message))
+        };
+        assert_eq!(seconds(&[], ""), 0);
+        assert_eq!(seconds(&[], "one"), 1);
+        assert_eq!(seconds(&[], &words(200)), 60);
+        assert_eq!(seconds(&[], &words(201)), 61);
+        assert_eq!(seconds(&[("VENT_TXT_WPM", "60")], &words(3)), 3);
+        assert_eq!(seconds(&[("VENT_TXT_WPM", "0")], &words(200)), 60);
+        let entry = with_env(&[], || {
+            parse_lines(&[&format!("2024-01-01 10:00:00 +0000,{}", words(400))]).remove(0)
+        });
+        assert_eq!(entry.reading_time_seconds, 120);
+    }
+
+    #[test]
+    fn versions_can_be_printed_as_json() {
+        let mut json = Vec::new();
+        version(&mut json, true).unwrap();
+        let json = serde_json::from_slice::<serde_json::Value>(&json).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        for field in ["git_commit", "build_date", "rustc"] {
+            assert!(
+                json[field].as_str().is_some_and(|s| !s.is_empty()),
+                "{field}"
+            );
+        }
+        let mut text = Vec::new();
+        version(&mut text, false).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with(&format!("vent.txt {}\ncommit: ", env!("CARGO_PKG_VERSION"))));
+        assert_eq!(text.lines().count(), 4);
+    }
+
+    #[test]
+    fn batches_are_written_in_whole_lines() {
+        let path = temp_dir("batch").join("vent.csv");
+        let read = || fs::read_to_string(&path).unwrap();
+        with_env(&[], || {
+            let mut writer = BatchWriter::new(open_for_append(path.to_str().unwrap())?, Some(2));
+            writer.push("a,1")?;
+            assert_eq!(read(), "");
+            writer.push("a,2")?;
+            assert_eq!(read(), "a,1\na,2\n");
+            writer.push("a,3")?;
+            assert_eq!(read(), "a,1\na,2\n");
+            writer.flush()?;
+            assert_eq!(read(), "a,1\na,2\na,3\n");
+            Ok::<(), IoError>(())
+        })
+        .unwrap();
+
+        let flush_every = |value: &str| {
+            let options = Options::parse(
+                &mut args(&["--flush-every", value]),
+                &[],
+                &["--flush-every"],
+                false,
+            )
+            .unwrap();
+            parse_flush_every(&options)
+        };
+        assert_eq!(flush_every("100").unwrap(), Some(100));
+        assert!(flush_every("0").is_err());
+        assert!(flush_every("many").is_err());
+    }
+
+    #[test]
+    fn reply_markers_accept_brackets_and_hashes() {
+        assert_eq!(split_reply_marker(">>5 hi"), Some(("5", " hi")));
+        assert_eq!(split_reply_marker(">>[5] hi"), Some(("5", " hi")));
+        assert_eq!(split_reply_marker(">>#5"), Some(("5", "")));
+        for message in [
+            ">>[5 hi",
+            ">>5] hi",
+            ">>[#5] hi",
+            ">> 5 hi",
+            ">>-1",
+            ">>[]",
+            "hi >>5",
+        ] {
+            assert_eq!(split_reply_marker(message), None, "{message}");
+        }
+        let entries = with_env(&[], || {
+            parse_lines(&[
+                "2024-01-01 10:00:00 +0000,first",
+                "2024-01-02 10:00:00 +0000,>>[0] second",
+                "2024-01-03 10:00:00 +0000,>>#1 third",
+            ])
+        });
+        assert_eq!(entries[1].reply, Some(0));
+        assert_eq!(entries[1].message, "second");
+        assert_eq!(entries[2].reply, Some(1));
+    }
+
+    #[test]
+    fn exports_can_be_sorted_by_date() {
+        let lines = [
+            "2024-01-03 10:00:00 +0000,c",
+            "2024-01-01 12:00:00 +0200,a",
+            "2024-01-02 10:00:00 +0000,>>0 b",
+            "yesterday,undated",
+            "2024-01-01 10:00:00 +0000,also a",
+        ];
+        let sorted = |newest_first| {
+            let mut entries = with_env(&[], || parse_lines(&lines));
+            sort_by_date(&mut entries, newest_first);
+            entries.iter().map(|e| e.id).collect::<Vec<usize>>()
+        };
+        assert_eq!(sorted(false), [3, 1, 4, 2, 0]);
+        assert_eq!(sorted(true), [0, 2, 4, 1, 3]);
+    }
+
+    #[test]
+    fn found_ids_are_displayed_one_per_line() {
+        let entries = with_env(&[], || parse_lines(&DATABASE))
+            .into_iter()
+            .skip(1)
+            .collect::<Vec<Entry>>();
+        let ids = |vars: &[(&str, &str)]| {
+            let mut ids = Vec::new();
+            with_env(vars, || find_ids(&mut ids, &entries)).unwrap();
+            String::from_utf8(ids).unwrap()
+        };
+        assert_eq!(ids(&[]), "1\n2\n");
+        assert_eq!(ids(&[("VENT_TXT_ONE_BASED", "1")]), "2\n3\n");
+        for option in [
+            "--tree",
+            "--with-replies",
+            "--highlight",
+            "--porcelain",
+            "--preview",
+        ] {
+            let error = with_env(&[], || run("vent", args(&["find-id", option, "1", "a"])));
+            assert_eq!(
+                error.unwrap_err().to_string(),
+                format!("Unknown option {option}")
+            );
+        }
+    }
+
+    #[test]
+    fn trailing_whitespace_is_trimmed_when_enabled() {
+        let message = "first  \n\tsecond\t\n";
+        assert_eq!(with_env(&[], || trim_stored_message(message)), message);
+        with_env(&[("VENT_TXT_TRIM_STORED", "1")], || {
+            assert_eq!(trim_stored_message(message), "first\n\tsecond\n");
+            let entry = EntryBuilder::new("a  \nb ")
+                .multiline(true)
+                .build(&[])
+                .unwrap();
+            let line = format_raw_entry_line("2024-01-01 10:00:00 +0000", &entry.message, &[]);
+            assert_eq!(line, "2024-01-01 10:00:00 +0000;multiline=1,a\\nb");
+        });
+    }
+
+    #[test]
+    fn filter_expressions_combine_with_other_filters() {
+        let entries = with_env(&[], || parse_lines(&DATABASE));
+        assert_eq!(
+            filtered_ids(entries, &["--filter", "!is_reply", "--since-id", "1"]),
+            [2]
+        );
+        let options = Options::parse(
+            &mut args(&["--filter", "is_reply &&"]),
+            &EntryFilter::FLAGS,
+            &EntryFilter::VALUED,
+            false,
+        )
+        .unwrap();
+        assert!(with_env(&[], || EntryFilter::from_options(&options)).is_err());
     }
 }
@@ -0,0 +1,62 @@
+use std::fs::File;
+use std::io::{Error as IoError, ErrorKind};
+use std::os::unix::io::AsRawFd;
+use std::{ptr, slice};
+
+/* A read-only map of a whole file, unmapped when dropped. vent replaces databases by renaming a
+ * new file over them, which leaves mapped pages alone, but a file truncated by another program
+ * while it is mapped makes reading the missing pages fail with SIGBUS. Maps are only kept while
+ * the database is read.
+ */
+pub struct Mapping {
+    address: *mut libc::c_void,
+    length: usize,
+}
+
+impl Mapping {
+    pub fn new(file: &File) -> Result<Self, IoError> {
+        let length = usize::try_from(file.metadata()?.len())
+            .map_err(|_| IoError::new(ErrorKind::FileTooLarge, "File too large to be mapped"))?;
+        // Empty maps are refused by the kernel
+        if length == 0 {
+            return Ok(Mapping {
+                address: ptr::null_mut(),
+                length,
+            });
+        }
+        // SAFETY: the map is private and read-only, failures are checked before it is used
+        let address = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                length,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if address == libc::MAP_FAILED {
+            return Err(IoError::last_os_error());
+        }
+        // SAFETY: only a hint on the map which was just created, lines are read in order
+        unsafe { libc::madvise(address, length, libc::MADV_SEQUENTIAL) };
+        Ok(Mapping { address, length })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        if self.length == 0 {
+            return &[];
+        }
+        // SAFETY: the map covers `length` readable bytes until it is dropped
+        unsafe { slice::from_raw_parts(self.address as *const u8, self.length) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        if self.length > 0 {
+            // SAFETY: the map was created by `new` and the slices of `bytes` don't outlive it
+            unsafe { libc::munmap(self.address, self.length) };
+        }
+    }
+}
@@ -0,0 +1,1214 @@
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::error::Error;
+use std::io::{self, Error as IoError, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use handlebars::{Handlebars, RenderError};
+use serde::Serialize;
+
+use crate::{
+    create_file, feed, format_local_time, get_removed_text, get_stable_anchors, get_template_path,
+    helpers, parse_date, Entry, Options,
+};
+
+#[derive(Serialize)]
+struct RenderContext<'a> {
+    entries: &'a [Entry],
+    backlinks: BTreeMap<usize, Vec<usize>>,
+    anchors: BTreeMap<usize, String>,
+    chronological: bool,
+    total: usize,
+    shown: usize,
+    #[serde(flatten)]
+    page: Page,
+    groups: Vec<Group<'a>>,
+    generated_at: String,
+    is_preview: bool,
+}
+
+/* Grouped renders list the messages again in a section for each group, a message with several
+ * tags appears under each of them and messages without tags in a last `untagged` section with an
+ * empty name. Messages grouped by month or year are in the section of the period they were
+ * written in, in the local timezone, and messages with unreadable dates in a last `undated` one.
+ */
+#[derive(Serialize)]
+struct Group<'a> {
+    name: String,
+    title: String,
+    slug: String,
+    entries: Vec<GroupEntry<'a>>,
+}
+
+/* A message listed in several sections is `repeated` after its first one, templates only give
+ * its first appearance the anchor of the message so that links to it have a single target.
+ */
+#[derive(Serialize)]
+struct GroupEntry<'a> {
+    #[serde(flatten)]
+    entry: &'a Entry,
+    repeated: bool,
+}
+
+/* Tags are listed alphabetically while periods are listed in display order, the newest first
+ * unless the render is chronological.
+ */
+fn groups<'a>(entries: &'a [Entry], options: &RenderOptions) -> Vec<Group<'a>> {
+    let Some(group_by) = options.group_by else {
+        return Vec::new();
+    };
+    let mut groups = BTreeMap::<String, Vec<&Entry>>::new();
+    let mut ungrouped = Vec::new();
+    for entry in entries.iter() {
+        let names = match group_by {
+            GroupBy::Tag => entry.tags.clone(),
+            GroupBy::Month | GroupBy::Year => parse_date(&entry.date)
+                .map(|date| {
+                    let format = if group_by == GroupBy::Month {
+                        "%Y-%m"
+                    } else {
+                        "%Y"
+                    };
+                    date.with_timezone(&Local).format(format).to_string()
+                })
+                .into_iter()
+                .collect(),
+        };
+        if names.is_empty() {
+            ungrouped.push(entry);
+        }
+        for name in names {
+            groups.entry(name).or_default().push(entry);
+        }
+    }
+    let mut groups = groups
+        .into_iter()
+        .map(|(name, entries)| {
+            let (title, slug) = match group_by {
+                GroupBy::Tag => (format!("#{name}"), format!("tag-{name}")),
+                GroupBy::Month => {
+                    let title = NaiveDate::parse_from_str(&format!("{name}-01"), "%Y-%m-%d")
+                        .map(|month| month.format("%B %Y").to_string())
+                        .unwrap_or_else(|_| name.clone());
+                    (title, format!("month-{name}"))
+                }
+                GroupBy::Year => (name.clone(), format!("year-{name}")),
+            };
+            (name, title, slug, entries)
+        })
+        .collect::<Vec<_>>();
+    if group_by != GroupBy::Tag && !options.chronological {
+        groups.reverse();
+    }
+    if !ungrouped.is_empty() {
+        let title = match group_by {
+            GroupBy::Tag => String::from("untagged"),
+            GroupBy::Month | GroupBy::Year => String::from("undated"),
+        };
+        groups.push((String::new(), title.clone(), title, ungrouped));
+    }
+    let mut anchored = HashSet::new();
+    groups
+        .into_iter()
+        .map(|(name, title, slug, entries)| Group {
+            name,
+            title,
+            slug,
+            entries: entries
+                .into_iter()
+                .map(|entry| GroupEntry {
+                    entry,
+                    repeated: !anchored.insert(entry.id),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/* Every render is paginated, a render without `--per-page` being a single page. The neighbours
+ * are file names relative to the first page, prefixed by the base path in the templates.
+ */
+#[derive(Clone, Serialize)]
+struct Page {
+    page_number: usize,
+    total_pages: usize,
+    prev_page: Option<String>,
+    next_page: Option<String>,
+    base_path: String,
+}
+
+impl Page {
+    fn new(output: &Path, page_number: usize, total_pages: usize, base_path: &str) -> Self {
+        Page {
+            page_number,
+            total_pages,
+            prev_page: (page_number > 1).then(|| page_file_name(output, page_number - 1)),
+            next_page: (page_number < total_pages).then(|| page_file_name(output, page_number + 1)),
+            base_path: base_path.to_owned(),
+        }
+    }
+}
+
+/* The first page is the output itself, the following ones get their number after the stem of its
+ * file name, e.g. `vent.html`, `vent-2.html`, `vent-3.html`.
+ */
+fn page_file_name(output: &Path, page_number: usize) -> String {
+    let file_name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if page_number == 1 {
+        return file_name;
+    }
+    // Only the last extension follows the number, a leading dot doesn't start one
+    match file_name
+        .rsplit_once('.')
+        .filter(|(stem, _)| !stem.is_empty())
+    {
+        Some((stem, extension)) => format!("{stem}-{page_number}.{extension}"),
+        None => format!("{file_name}-{page_number}"),
+    }
+}
+
+/* Backlinks map the ID of each rendered message to the IDs of the rendered messages replying to
+ * it, replies to messages which aren't rendered are left out.
+ */
+fn backlinks(entries: &[Entry]) -> BTreeMap<usize, Vec<usize>> {
+    let ids = entries.iter().map(|e| e.id).collect::<HashSet<usize>>();
+    let mut backlinks = BTreeMap::<usize, Vec<usize>>::new();
+    for entry in entries.iter() {
+        if let Some(reply) = entry.reply.filter(|r| *r != entry.id && ids.contains(r)) {
+            backlinks.entry(reply).or_default().push(entry.id);
+        }
+    }
+    backlinks
+}
+
+impl<'a> RenderContext<'a> {
+    fn new(entries: &'a [Entry], options: &RenderOptions, page: Page) -> Self {
+        RenderContext {
+            entries,
+            backlinks: backlinks(entries),
+            anchors: anchors(entries, options),
+            chronological: options.chronological,
+            total: options.total.unwrap_or(entries.len()),
+            shown: entries.len(),
+            page,
+            groups: groups(entries, options),
+            generated_at: format_local_time(),
+            is_preview: options.preview,
+        }
+    }
+}
+
+fn registry() -> Handlebars<'static> {
+    let mut handlebars = Handlebars::new();
+    helpers::register(&mut handlebars);
+    handlebars
+}
+
+/* Anchors default to the IDs of the messages, which change when a hard prune renumbers them.
+ * Stable anchors, see `stable_anchors`, are computed over the whole database by `with_anchors`,
+ * without it over the rendered messages. Sequential anchors number the rendered messages from 1,
+ * they only make sense for a single render.
+ */
+fn anchors(entries: &[Entry], options: &RenderOptions) -> BTreeMap<usize, String> {
+    if options.numbering == Numbering::Sequential {
+        return entries
+            .iter()
+            .enumerate()
+            .map(|(index, e)| (e.id, (index + 1).to_string()))
+            .collect();
+    }
+    if !get_stable_anchors() {
+        return entries.iter().map(|e| (e.id, e.id.to_string())).collect();
+    }
+    let stable_anchors = match &options.anchors {
+        Some(anchors) => anchors,
+        None => &stable_anchors(entries),
+    };
+    entries
+        .iter()
+        .map(|e| {
+            let anchor = stable_anchors.get(&e.id).cloned();
+            (e.id, anchor.unwrap_or_else(|| e.id.to_string()))
+        })
+        .collect()
+}
+
+/* Anchors derived from dates look like `20240105T100000Z`, in UTC so that they don't depend on
+ * the timezone of the render.
+ */
+fn date_anchor(date: &str) -> Option<String> {
+    parse_date(date).map(|date| date.naive_utc().format("%Y%m%dT%H%M%SZ").to_string())
+}
+
+fn unique_anchor(taken: &HashSet<String>, anchor: String) -> String {
+    let mut count = 1;
+    let mut unique = anchor.clone();
+    while taken.contains(&unique) {
+        count += 1;
+        unique = format!("{anchor}-{count}");
+    }
+    unique
+}
+
+/* Messages added with stable anchors enabled keep theirs in their `anchor` metadata, which edits
+ * and publishing keep even when they change the date. Other messages derive theirs from their
+ * dates, messages sharing a date get a suffix in the order they were added. Removed messages get
+ * theirs last as hard prunes drop them, revisions aren't shown and get none.
+ */
+pub fn stable_anchors(entries: &[Entry]) -> BTreeMap<usize, String> {
+    let mut anchors = BTreeMap::new();
+    for entry in entries.iter() {
+        if let Some(anchor) = entry.metadata.get("anchor") {
+            anchors.insert(entry.id, anchor.clone());
+        }
+    }
+    let mut taken = anchors.values().cloned().collect::<HashSet<String>>();
+    let removed_text = get_removed_text();
+    let (removed, kept): (Vec<&Entry>, Vec<&Entry>) = entries
+        .iter()
+        .filter(|e| !anchors.contains_key(&e.id) && e.revision_of().is_none())
+        .partition(|e| e.message == removed_text);
+    for entry in kept.into_iter().chain(removed) {
+        let anchor = match date_anchor(&entry.date) {
+            Some(anchor) => unique_anchor(&taken, anchor),
+            None => entry.id.to_string(),
+        };
+        taken.insert(anchor.clone());
+        anchors.insert(entry.id, anchor);
+    }
+    anchors
+}
+
+/* The anchor stored for a new message is the one it would derive, made unique among the
+ * anchors of `entries`, the messages of the database.
+ */
+pub fn store_anchor(entry: &mut Entry, entries: &[Entry]) {
+    if !get_stable_anchors() || entry.metadata.contains_key("anchor") {
+        return;
+    }
+    if let Some(anchor) = date_anchor(&entry.date) {
+        let taken = stable_anchors(entries).into_values().collect();
+        let anchor = unique_anchor(&taken, anchor);
+        entry.metadata.insert(String::from("anchor"), anchor);
+    }
+}
+
+pub fn render<W>(writer: W, entries: &[Entry], options: &RenderOptions) -> Result<(), RenderError>
+where
+    W: io::Write,
+{
+    let output = options.output.as_deref().unwrap_or_default();
+    render_page(writer, entries, options, options.single_page(output))
+}
+
+fn render_page<W>(
+    writer: W,
+    entries: &[Entry],
+    options: &RenderOptions,
+    page: Page,
+) -> Result<(), RenderError>
+where
+    W: io::Write,
+{
+    if let Some(Feed::Atom) = options.feed {
+        return Ok(feed::atom(writer, entries, &anchors(entries, options))?);
+    }
+
+    let template_name = "template";
+    let mut handlebars = registry();
+    match &options.template {
+        Some(template) => handlebars.register_template_string(template_name, template)?,
+        None => handlebars.register_template_file(template_name, &options.template_path)?,
+    }
+    let context = RenderContext::new(entries, options, page);
+    handlebars.render_to_write(template_name, &context, writer)
+}
+
+fn read_template<R: io::Read>(mut input: R) -> Result<String, IoError> {
+    let mut template = String::new();
+    input.read_to_string(&mut template)?;
+    Ok(template)
+}
+
+pub fn report_render_error(e: RenderError) -> IoError {
+    eprintln!("{e}");
+    if let Some(es) = e.source() {
+        eprintln!("{:?}", es);
+    }
+    IoError::other("Render error")
+}
+
+#[derive(Clone, Copy)]
+pub enum Feed {
+    Atom,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GroupBy {
+    Tag,
+    Month,
+    Year,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Numbering {
+    Absolute,
+    Sequential,
+}
+
+#[derive(Clone)]
+pub struct RenderOptions {
+    output: Option<String>,
+    timeout: Option<Duration>,
+    template_path: PathBuf,
+    template: Option<String>,
+    chronological: bool,
+    feed: Option<Feed>,
+    numbering: Numbering,
+    verbose: bool,
+    total: Option<usize>,
+    per_page: Option<usize>,
+    base_path: String,
+    group_by: Option<GroupBy>,
+    preview: bool,
+    anchors: Option<BTreeMap<usize, String>>,
+}
+
+impl RenderOptions {
+    pub const FLAGS: [&'static str; 5] = [
+        "--chronological",
+        "--reverse",
+        "--stdin-template",
+        "--verbose",
+        "--preview",
+    ];
+    pub const VALUED: [&'static str; 8] = [
+        "--output",
+        "--timeout",
+        "--template-name",
+        "--feed",
+        "--numbering",
+        "--per-page",
+        "--base-path",
+        "--group-by",
+    ];
+
+    pub fn from_options(options: &Options) -> Result<Self, IoError> {
+        let timeout = options
+            .value("--timeout")
+            .map(|t| {
+                t.parse::<f64>()
+                    .ok()
+                    .and_then(|t| Duration::try_from_secs_f64(t).ok())
+                    .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Invalid render timeout"))
+            })
+            .transpose()?;
+        // Named templates are looked up next to the default one
+        let template_path = PathBuf::from(get_template_path());
+        let template_path = match options.value("--template-name") {
+            Some(name) => template_path.with_file_name(format!("{name}.hbs")),
+            None => template_path,
+        };
+        // Entries are always read from the database so stdin is free for the template
+        let template = if options.flag("--stdin-template") {
+            Some(read_template(io::stdin())?)
+        } else {
+            None
+        };
+        let feed = match options.value("--feed") {
+            Some("atom") => Some(Feed::Atom),
+            Some(_) => return Err(IoError::new(ErrorKind::InvalidInput, "Invalid feed format")),
+            None => None,
+        };
+        let numbering = match options.value("--numbering") {
+            Some("absolute") | None => Numbering::Absolute,
+            Some("sequential") => Numbering::Sequential,
+            Some(_) => return Err(IoError::new(ErrorKind::InvalidInput, "Invalid numbering")),
+        };
+        let per_page = options
+            .value("--per-page")
+            .map(|n| {
+                n.parse::<usize>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Invalid page size"))
+            })
+            .transpose()?;
+        let group_by = match options.value("--group-by") {
+            Some("tag") => Some(GroupBy::Tag),
+            Some("month") => Some(GroupBy::Month),
+            Some("year") => Some(GroupBy::Year),
+            Some(_) => return Err(IoError::new(ErrorKind::InvalidInput, "Invalid grouping")),
+            None => None,
+        };
+        if per_page.is_some() && (options.value("--output").is_none() || feed.is_some()) {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "Pagination requires an output file and can't be used with feeds",
+            ));
+        }
+        Ok(RenderOptions {
+            output: options.value("--output").map(str::to_owned),
+            timeout,
+            template_path,
+            template,
+            chronological: options.flag("--chronological") || options.flag("--reverse"),
+            feed,
+            numbering,
+            verbose: options.flag("--verbose"),
+            total: None,
+            per_page,
+            base_path: options.value("--base-path").unwrap_or_default().to_owned(),
+            group_by,
+            preview: options.flag("--preview"),
+            anchors: None,
+        })
+    }
+
+    pub fn output(&self) -> Option<&str> {
+        self.output.as_deref()
+    }
+
+    pub fn template_path(&self) -> &Path {
+        &self.template_path
+    }
+
+    fn single_page(&self, output: &str) -> Page {
+        Page::new(Path::new(output), 1, 1, &self.base_path)
+    }
+
+    /* The total is the number of messages of the database which can be shown, before any
+     * filter, it defaults to the number of rendered messages.
+     */
+    pub fn with_total(mut self, total: usize) -> Self {
+        self.total = Some(total);
+        self
+    }
+
+    fn summary(&self, shown: usize, bytes: usize) -> String {
+        let filtered = self.total.unwrap_or(shown).saturating_sub(shown);
+        format!("Rendered {shown} messages, {filtered} filtered out, {bytes} bytes")
+    }
+
+    /* Stable anchors are computed over the messages of the database before any filter, so that
+     * the suffixes of messages sharing a date don't depend on which ones are rendered.
+     */
+    pub fn with_anchors(mut self, entries: &[Entry]) -> Self {
+        if get_stable_anchors() {
+            self.anchors = Some(stable_anchors(entries));
+        }
+        self
+    }
+}
+
+/* The document is fully rendered in memory before being written out, a failed or timed out
+ * render leaves the output file untouched. A timed out render can't be cancelled, its thread is
+ * left running until the process exits.
+ */
+pub fn render_with_options(entries: Vec<Entry>, options: &RenderOptions) -> Result<(), IoError> {
+    let shown = entries.len();
+    let rendered = match options.timeout {
+        Some(timeout) => {
+            let (sender, receiver) = mpsc::channel();
+            let options = options.clone();
+            thread::spawn(move || {
+                let _ = sender.send(render_pages(&entries, &options));
+            });
+            receiver
+                .recv_timeout(timeout)
+                .map_err(|_| IoError::new(ErrorKind::TimedOut, "Render timed out"))?
+        }
+        None => render_pages(&entries, options),
+    }
+    .map_err(report_render_error)?;
+
+    match &options.output {
+        Some(path) => {
+            let path = Path::new(path);
+            for (page_number, rendered) in rendered.iter().enumerate() {
+                let page_path = path.with_file_name(page_file_name(path, page_number + 1));
+                io::Write::write_all(&mut create_file(page_path)?, rendered)?;
+            }
+        }
+        None => io::Write::write_all(&mut io::stdout(), &rendered[0])?,
+    }
+    if options.verbose {
+        let bytes = rendered.iter().map(Vec::len).sum::<usize>();
+        eprintln!("{}", options.summary(shown, bytes));
+    }
+    Ok(())
+}
+
+/* Pages are filled in display order, the first page holds the newest messages unless the render
+ * is chronological. Backlinks and reply counts only cover the messages of the same page.
+ */
+fn render_pages(entries: &[Entry], options: &RenderOptions) -> Result<Vec<Vec<u8>>, RenderError> {
+    let Some(per_page) = options.per_page else {
+        let mut buffer = Vec::new();
+        return render(&mut buffer, entries, options).map(|_| vec![buffer]);
+    };
+    let output = Path::new(options.output.as_deref().unwrap_or_default());
+    let mut chunks = entries.chunks(per_page).collect::<Vec<&[Entry]>>();
+    if !options.chronological {
+        chunks.reverse();
+    }
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    let total_pages = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let page = Page::new(output, index + 1, total_pages, &options.base_path);
+            let mut buffer = Vec::new();
+            render_page(&mut buffer, chunk, options, page).map(|_| buffer)
+        })
+        .collect()
+}
+
+fn open_in_browser(path: &Path) -> Result<(), IoError> {
+    let mut command = match env::var("BROWSER") {
+        Ok(browser) if !browser.is_empty() => Command::new(browser),
+        _ if cfg!(target_os = "macos") => Command::new("open"),
+        _ if cfg!(target_os = "windows") => {
+            let mut command = Command::new("cmd");
+            command.args(["/C", "start", ""]);
+            command
+        }
+        _ => Command::new("xdg-open"),
+    };
+    let status = command.arg(path).status().map_err(|e| {
+        IoError::new(
+            e.kind(),
+            format!("Unable to start a browser, is a graphical session running? ({e})"),
+        )
+    })?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(IoError::other(format!("Browser exited with {status}")))
+    }
+}
+
+/* Without an explicit output, the document is rendered in the temporary directory where the
+ * relative links of the template, e.g. to the stylesheet, won't resolve.
+ */
+pub fn open(entries: Vec<Entry>, mut options: RenderOptions) -> Result<(), IoError> {
+    let output = options
+        .output
+        .get_or_insert_with(|| {
+            env::temp_dir()
+                .join("vent.html")
+                .to_string_lossy()
+                .into_owned()
+        })
+        .clone();
+    render_with_options(entries, &options)?;
+    open_in_browser(output.as_ref())
+}
+
+/* Builds render every target from the same parsed entries and template registry, each target in
+ * its own thread. Targets are `[output]:[template name]` or `[output]:atom`, nothing is written
+ * unless every target rendered.
+ */
+pub fn build(
+    entries: &[Entry],
+    options: &RenderOptions,
+    targets: &[String],
+) -> Result<(), IoError> {
+    let targets = targets
+        .iter()
+        .map(|target| {
+            target.rsplit_once(':').ok_or_else(|| {
+                IoError::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid build target {target}"),
+                )
+            })
+        })
+        .collect::<Result<Vec<(&str, &str)>, IoError>>()?;
+
+    let mut handlebars = registry();
+    for &(_, name) in targets.iter().filter(|(_, name)| *name != "atom") {
+        if !handlebars.has_template(name) {
+            let path = options.template_path.with_file_name(format!("{name}.hbs"));
+            handlebars
+                .register_template_file(name, path)
+                .map_err(|e| report_render_error(e.into()))?;
+        }
+    }
+    let context = RenderContext::new(entries, options, options.single_page(""));
+
+    let rendered = thread::scope(|scope| {
+        let handles = targets
+            .iter()
+            .map(|&(_, name)| {
+                let handlebars = &handlebars;
+                let context = &context;
+                scope.spawn(move || {
+                    let mut buffer = Vec::new();
+                    if name == "atom" {
+                        feed::atom(&mut buffer, entries, &context.anchors)?;
+                    } else {
+                        handlebars.render_to_write(name, context, &mut buffer)?;
+                    }
+                    Ok(buffer)
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(RenderError::new("Render panicked")))
+            })
+            .collect::<Result<Vec<Vec<u8>>, RenderError>>()
+    })
+    .map_err(report_render_error)?;
+
+    for (&(output, _), rendered) in targets.iter().zip(rendered) {
+        io::Write::write_all(&mut create_file(output)?, &rendered)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{parse_lines, temp_dir, with_env};
+    use std::fs;
+
+    #[cfg(unix)]
+    #[test]
+    fn build_outputs_take_the_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let output = temp_dir("build").join("feed.atom");
+        with_env(&[("VENT_TXT_FILE_MODE", "600")], || {
+            let entries = parse_lines(&["2024-01-01 10:00:00 +0000,first"]);
+            let options = RenderOptions::from_options(&Options::default()).unwrap();
+            build(&entries, &options, &[format!("{}:atom", output.display())]).unwrap();
+        });
+        let metadata = fs::metadata(&output).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    }
+
+    const SAME_DATE: [&str; 4] = [
+        "2024-01-05 10:00:00 +0000,first",
+        "2024-01-05 10:00:00 +0000,second",
+        "2024-01-05 10:00:00 +0000;revision_of=0,first revised",
+        "2024-01-05 10:00:00 +0000,third",
+    ];
+
+    fn stable_options() -> RenderOptions {
+        RenderOptions::from_options(&Options::default()).unwrap()
+    }
+
+    #[test]
+    fn stable_anchors_are_suffixed_in_order() {
+        with_env(&[("VENT_TXT_STABLE_ANCHORS", "1")], || {
+            let entries = parse_lines(&SAME_DATE);
+            let anchors = stable_anchors(&entries);
+            assert_eq!(anchors[&0], "20240105T100000Z");
+            assert_eq!(anchors[&1], "20240105T100000Z-2");
+            assert!(!anchors.contains_key(&2));
+            assert_eq!(anchors[&3], "20240105T100000Z-3");
+        });
+    }
+
+    #[test]
+    fn stable_anchors_dont_depend_on_filters() {
+        with_env(&[("VENT_TXT_STABLE_ANCHORS", "1")], || {
+            let entries = parse_lines(&SAME_DATE);
+            let options = stable_options().with_anchors(&entries);
+            let shown = parse_lines(&SAME_DATE)
+                .into_iter()
+                .skip(3)
+                .collect::<Vec<Entry>>();
+            assert_eq!(anchors(&shown, &options)[&3], "20240105T100000Z-3");
+        });
+    }
+
+    #[test]
+    fn stored_anchors_are_kept_and_never_reused() {
+        with_env(&[("VENT_TXT_STABLE_ANCHORS", "1")], || {
+            let entries = parse_lines(&[
+                "2024-02-01 10:00:00 +0000;anchor=20240105T100000Z,edited later",
+                "2024-01-05 10:00:00 +0000,same date",
+                "2024-01-05 10:00:00 +0000,[removed]",
+            ]);
+            let anchors = stable_anchors(&entries);
+            assert_eq!(anchors[&0], "20240105T100000Z");
+            assert_eq!(anchors[&1], "20240105T100000Z-2");
+            assert_eq!(anchors[&2], "20240105T100000Z-3");
+
+            let mut entry = parse_lines(&["2024-01-05 10:00:00 +0000,new"]).remove(0);
+            store_anchor(&mut entry, &entries);
+            assert_eq!(entry.metadata["anchor"], "20240105T100000Z-4");
+        });
+    }
+
+    #[test]
+    fn anchors_are_ids_by_default() {
+        with_env(&[], || {
+            let entries = parse_lines(&SAME_DATE);
+            let options = stable_options().with_anchors(&entries);
+            assert_eq!(anchors(&entries, &options)[&1], "1");
+            let mut entry = parse_lines(&["2024-01-05 10:00:00 +0000,new"]).remove(0);
+            store_anchor(&mut entry, &entries);
+            assert!(entry.metadata.is_empty());
+        });
+    }
+
+    fn grouped_options(group_by: GroupBy) -> RenderOptions {
+        let mut options = RenderOptions::from_options(&Options::default()).unwrap();
+        options.group_by = Some(group_by);
+        options
+    }
+
+    fn summary(groups: &[Group]) -> Vec<(String, Vec<(usize, bool)>)> {
+        groups
+            .iter()
+            .map(|group| {
+                let entries = group.entries.iter();
+                let entries = entries.map(|e| (e.entry.id, e.repeated)).collect();
+                (group.slug.clone(), entries)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tag_groups_end_with_untagged_messages() {
+        with_env(&[], || {
+            let entries = parse_lines(&[
+                "2024-01-01 10:00:00 +0000,a #y #x",
+                "2024-01-02 10:00:00 +0000,b",
+                "2024-01-03 10:00:00 +0000,>>0 c #y",
+            ]);
+            let groups = groups(&entries, &grouped_options(GroupBy::Tag));
+            assert_eq!(
+                summary(&groups),
+                [
+                    (String::from("tag-x"), vec![(0, false)]),
+                    (String::from("tag-y"), vec![(0, true), (2, false)]),
+                    (String::from("untagged"), vec![(1, false)]),
+                ]
+            );
+            assert_eq!(groups[2].name, "");
+        });
+    }
+
+    #[test]
+    fn grouped_renders_anchor_each_message_once() {
+        let rendered = with_env(&[], || {
+            let entries = parse_lines(&[
+                "2024-01-01 10:00:00 +0000,a #x #y",
+                "2024-01-02 10:00:00 +0000,b",
+                "2024-01-03 10:00:00 +0000,>>0 c #y",
+            ]);
+            let mut rendered = Vec::new();
+            render(&mut rendered, &entries, &grouped_options(GroupBy::Tag)).unwrap();
+            String::from_utf8(rendered).unwrap()
+        });
+        for id in ["0", "1", "2"] {
+            assert_eq!(
+                rendered
+                    .matches(&format!("class=\"entry\" id=\"{id}\""))
+                    .count(),
+                1
+            );
+        }
+        assert!(rendered.contains("<h2 id=\"untagged\">untagged</h2>"));
+        assert!(rendered.contains("href=\"#0\""));
+    }
+
+    /* The options are read with the given settings, as `render` is. */
+    fn parse_with(vars: &[(&str, &str)], args: &[&str]) -> Result<RenderOptions, IoError> {
+        let mut args = args.iter().map(|a| a.to_string()).collect::<Vec<String>>();
+        let options = Options::parse(
+            &mut args,
+            &RenderOptions::FLAGS,
+            &RenderOptions::VALUED,
+            false,
+        )
+        .unwrap();
+        with_env(vars, || RenderOptions::from_options(&options))
+    }
+
+    #[test]
+    fn scripts_are_stripped_from_messages() {
+        let render_message = |vars: &[(&str, &str)]| {
+            with_env(vars, || {
+                let entries = parse_lines(&[
+                    "2024-01-01 10:00:00 +0000,<b>hi</b> <script>alert(1)</script>there",
+                ]);
+                let options = RenderOptions::from_options(&Options::default()).unwrap();
+                let mut rendered = Vec::new();
+                render(&mut rendered, &entries, &options).unwrap();
+                String::from_utf8(rendered).unwrap()
+            })
+        };
+        let rendered = render_message(&[]);
+        assert!(!rendered.contains("alert(1)"));
+        assert!(rendered.contains("&lt;b&gt;hi&lt;/b&gt; there"));
+        let rendered = render_message(&[("VENT_TXT_HTML_TAGS", "b script")]);
+        assert!(!rendered.contains("alert(1)"));
+        assert!(rendered.contains("<b>hi</b> there"));
+    }
+
+    #[test]
+    fn period_groups_end_with_undated_messages() {
+        with_env(&[], || {
+            let entries = parse_lines(&[
+                "2024-01-15 10:00:00 +0000,a",
+                "yesterday,b",
+                "2024-03-15 10:00:00 +0000,c",
+                "2024-03-16 10:00:00 +0000,d",
+            ]);
+            let mut options = grouped_options(GroupBy::Month);
+            let slugs = |groups: Vec<Group>| {
+                let slugs = groups.iter().map(|group| group.slug.clone());
+                slugs.collect::<Vec<String>>()
+            };
+            assert_eq!(
+                slugs(groups(&entries, &options)),
+                ["month-2024-03", "month-2024-01", "undated"]
+            );
+            options.chronological = true;
+            assert_eq!(
+                slugs(groups(&entries, &options)),
+                ["month-2024-01", "month-2024-03", "undated"]
+            );
+            let groups = groups(&entries, &grouped_options(GroupBy::Year));
+            assert_eq!(
+                summary(&groups)[1],
+                (String::from("undated"), vec![(1, false)])
+            );
+        });
+    }
+
+    #[test]
+    fn failed_renders_leave_the_output_untouched() {
+        let directory = temp_dir("render");
+        let good = directory.join("good.hbs");
+        let bad = directory.join("bad.hbs");
+        let output = directory.join("out.html");
+        fs::write(&good, "{{#each entries}}{{message}}{{/each}}").unwrap();
+        fs::write(&bad, "{{#if}}").unwrap();
+        let render_to = |template: &Path| {
+            let vars = [("VENT_TXT_HBS", template.to_str().unwrap())];
+            let args = ["--output", output.to_str().unwrap(), "--timeout", "5"];
+            let options = parse_with(&vars, &args)?;
+            with_env(&vars, || {
+                render_with_options(parse_lines(&["2024-01-01 10:00:00 +0000,first"]), &options)
+            })
+        };
+        render_to(&good).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "first");
+        assert!(render_to(&bad).is_err());
+        assert_eq!(fs::read_to_string(&output).unwrap(), "first");
+
+        for timeout in ["x", "-1", "inf"] {
+            assert_eq!(
+                parse_with(&[], &["--timeout", timeout])
+                    .err()
+                    .unwrap()
+                    .to_string(),
+                "Invalid render timeout"
+            );
+        }
+        assert_eq!(
+            parse_with(&[], &["--timeout", "0.5"]).unwrap().timeout,
+            Some(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn named_templates_are_next_to_the_default_one() {
+        let directory = temp_dir("templates");
+        let default = directory.join("vent.hbs");
+        fs::write(&default, "default").unwrap();
+        fs::write(
+            directory.join("short.hbs"),
+            "{{#each entries}}{{id}}{{/each}}",
+        )
+        .unwrap();
+        let vars = [("VENT_TXT_HBS", default.to_str().unwrap())];
+        let rendered = |args: &[&str]| {
+            let options = parse_with(&vars, args).unwrap();
+            let entries = parse_lines(&[
+                "2024-01-01 10:00:00 +0000,first",
+                "2024-01-02 10:00:00 +0000,second",
+            ]);
+            let mut rendered = Vec::new();
+            with_env(&vars, || render(&mut rendered, &entries, &options)).map(|_| rendered)
+        };
+        assert_eq!(rendered(&[]).unwrap(), b"default");
+        assert_eq!(rendered(&["--template-name", "short"]).unwrap(), b"01");
+        assert_eq!(
+            parse_with(&vars, &["--template-name", "short"])
+                .unwrap()
+                .template_path(),
+            directory.join("short.hbs")
+        );
+        assert!(rendered(&["--template-name", "missing"]).is_err());
+    }
+
+    fn render_template(template: &str, entries: &[Entry]) -> String {
+        with_env(&[], || {
+            let mut options = RenderOptions::from_options(&Options::default()).unwrap();
+            options.template = Some(template.to_owned());
+            let mut rendered = Vec::new();
+            render(&mut rendered, entries, &options).unwrap();
+            String::from_utf8(rendered).unwrap()
+        })
+    }
+
+    #[test]
+    fn replies_are_counted_among_rendered_messages() {
+        let entries = parse_lines(&[
+            "2024-01-01 10:00:00 +0000,first",
+            "2024-01-02 10:00:00 +0000,>>0 second",
+            "2024-01-03 10:00:00 +0000,>>0 third",
+            "2024-01-04 10:00:00 +0000,>>1 fourth",
+        ]);
+        let template =
+            "{{#each entries}}{{id}}:{{reply_count id}}{{#if (reply_count id)}}!{{/if}} {{/each}}";
+        assert_eq!(render_template(template, &entries), "0:2! 1:1! 2:0 3:0 ");
+        let shown = entries.into_iter().skip(1).collect::<Vec<Entry>>();
+        assert_eq!(render_template(template, &shown), "1:1! 2:0 3:0 ");
+    }
+
+    /* `BROWSER` isn't a setting of vent, it is removed again before the environment is unlocked. */
+    #[cfg(unix)]
+    #[test]
+    fn documents_are_opened_with_the_browser() {
+        let output = temp_dir("open").join("vent.html");
+        let open_with = |browser: &str| {
+            let template = output.with_file_name("vent.hbs");
+            fs::write(&template, "{{#each entries}}{{message}}{{/each}}").unwrap();
+            let vars = [("VENT_TXT_HBS", template.to_str().unwrap())];
+            let options = parse_with(&vars, &["--output", output.to_str().unwrap()]).unwrap();
+            with_env(&vars, || {
+                env::set_var("BROWSER", browser);
+                let result = open(parse_lines(&["2024-01-01 10:00:00 +0000,first"]), options);
+                env::remove_var("BROWSER");
+                result
+            })
+        };
+        open_with("true").unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "first");
+        assert_eq!(
+            open_with("false").unwrap_err().to_string(),
+            "Browser exited with exit status: 1"
+        );
+        assert!(open_with("/nonexistent/browser")
+            .unwrap_err()
+            .to_string()
+            .starts_with("Unable to start a browser"));
+    }
+
+    #[test]
+    fn builds_write_every_target_or_none() {
+        let directory = temp_dir("builds");
+        let default = directory.join("vent.hbs");
+        fs::write(&default, "default").unwrap();
+        fs::write(
+            directory.join("ids.hbs"),
+            "{{#each entries}}{{id}}{{/each}}",
+        )
+        .unwrap();
+        fs::write(directory.join("broken.hbs"), "{{missing_helper 1}}").unwrap();
+        let vars = [("VENT_TXT_HBS", default.to_str().unwrap())];
+        let target =
+            |name: &str, template: &str| format!("{}:{template}", directory.join(name).display());
+        let build_targets = |targets: &[String]| {
+            let options = parse_with(&vars, &[]).unwrap();
+            with_env(&vars, || {
+                let entries = parse_lines(&[
+                    "2024-01-01 10:00:00 +0000,first",
+                    "2024-01-02 10:00:00 +0000,second",
+                ]);
+                build(&entries, &options, targets)
+            })
+        };
+        build_targets(&[target("ids.html", "ids"), target("feed.atom", "atom")]).unwrap();
+        assert_eq!(
+            fs::read_to_string(directory.join("ids.html")).unwrap(),
+            "01"
+        );
+        let feed = fs::read_to_string(directory.join("feed.atom")).unwrap();
+        assert_eq!(feed.matches("<entry>").count(), 2);
+
+        // Templates fail at render time, once the other targets have been rendered
+        let targets = [target("other.html", "ids"), target("x.html", "broken")];
+        assert!(build_targets(&targets).is_err());
+        assert!(!directory.join("other.html").exists());
+        assert!(build_targets(&[target("other.html", "missing")]).is_err());
+        assert_eq!(
+            build_targets(&[String::from("no-target")])
+                .unwrap_err()
+                .to_string(),
+            "Invalid build target no-target"
+        );
+    }
+
+    #[test]
+    fn piped_templates_replace_the_template_file() {
+        let template = read_template(&b"{{#each entries}}<{{message}}>{{/each}}"[..]).unwrap();
+        let missing = temp_dir("piped").join("missing.hbs");
+        let vars = [("VENT_TXT_HBS", missing.to_str().unwrap())];
+        let mut options = parse_with(&vars, &[]).unwrap();
+        let entries = parse_lines(&["2024-01-01 10:00:00 +0000,first"]);
+        let mut rendered = Vec::new();
+        assert!(with_env(&vars, || render(&mut rendered, &entries, &options)).is_err());
+        options.template = Some(template);
+        rendered.clear();
+        with_env(&vars, || render(&mut rendered, &entries, &options)).unwrap();
+        assert_eq!(rendered, b"<first>");
+        let error = read_template(&b"\xff"[..]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn summaries_count_filtered_messages() {
+        let options = parse_with(&[], &["--verbose"]).unwrap();
+        assert!(options.verbose);
+        assert_eq!(
+            options.summary(2, 10),
+            "Rendered 2 messages, 0 filtered out, 10 bytes"
+        );
+        let options = options.with_total(5);
+        assert_eq!(
+            options.summary(2, 10),
+            "Rendered 2 messages, 3 filtered out, 10 bytes"
+        );
+        assert_eq!(
+            options.summary(7, 0),
+            "Rendered 7 messages, 0 filtered out, 0 bytes"
+        );
+    }
+
+    #[test]
+    fn counts_are_given_to_templates() {
+        let entries = parse_lines(&[
+            "2024-01-01 10:00:00 +0000,first",
+            "2024-01-02 10:00:00 +0000,second",
+        ]);
+        let template = "{{shown}}/{{total}}";
+        assert_eq!(render_template(template, &entries), "2/2");
+        let rendered = with_env(&[], || {
+            let mut options = RenderOptions::from_options(&Options::default())
+                .unwrap()
+                .with_total(5);
+            options.template = Some(template.to_owned());
+            let mut rendered = Vec::new();
+            render(&mut rendered, &entries[1..], &options).unwrap();
+            String::from_utf8(rendered).unwrap()
+        });
+        assert_eq!(rendered, "1/5");
+    }
+
+    #[test]
+    fn sequential_numbering_counts_rendered_messages() {
+        let entries = parse_lines(&SAME_DATE)
+            .into_iter()
+            .skip(2)
+            .collect::<Vec<Entry>>();
+        let options = parse_with(&[], &["--numbering", "sequential"]).unwrap();
+        let numbered = with_env(&[], || anchors(&entries, &options));
+        assert_eq!(numbered[&2], "1");
+        assert_eq!(numbered[&3], "2");
+        let options = parse_with(&[], &["--numbering", "absolute"]).unwrap();
+        assert_eq!(with_env(&[], || anchors(&entries, &options))[&3], "3");
+        assert!(parse_with(&[], &["--numbering", "random"]).is_err());
+    }
+
+    #[test]
+    fn pages_link_to_their_neighbours() {
+        let entries = parse_lines(&[
+            "2024-01-01 10:00:00 +0000,a",
+            "2024-01-02 10:00:00 +0000,b",
+            "2024-01-03 10:00:00 +0000,c",
+            "2024-01-04 10:00:00 +0000,d",
+            "2024-01-05 10:00:00 +0000,e",
+        ]);
+        let args = [
+            "--per-page",
+            "2",
+            "--output",
+            "out/vent.html",
+            "--base-path",
+            "/v/",
+        ];
+        let mut options = parse_with(&[], &args).unwrap();
+        options.template = Some(String::from(
+            "{{page_number}}/{{total_pages}} {{base_path}}[{{prev_page}}|{{next_page}}] \
+             {{#each entries}}{{message}}{{/each}}",
+        ));
+        let pages = with_env(&[], || render_pages(&entries, &options)).unwrap();
+        let pages = pages
+            .into_iter()
+            .map(|page| String::from_utf8(page).unwrap())
+            .collect::<Vec<String>>();
+        assert_eq!(
+            pages,
+            [
+                "1/3 /v/[|vent-2.html] e",
+                "2/3 /v/[vent.html|vent-3.html] cd",
+                "3/3 /v/[vent-2.html|] ab",
+            ]
+        );
+        assert_eq!(page_file_name(Path::new("out/vent"), 2), "vent-2");
+        assert_eq!(
+            page_file_name(Path::new("vent.v2.html"), 2),
+            "vent.v2-2.html"
+        );
+        assert_eq!(page_file_name(Path::new("out/.vent"), 3), ".vent-3");
+        assert!(parse_with(&[], &["--per-page", "0", "--output", "a.html"]).is_err());
+        assert!(parse_with(&[], &["--per-page", "2"]).is_err());
+    }
+
+    #[test]
+    fn generation_dates_follow_the_fixed_clock() {
+        let entries = parse_lines(&["2024-01-01 10:00:00 +0000,first"]);
+        let generated_at = |now: &str| {
+            with_env(&[("VENT_TXT_NOW", now)], || {
+                let mut options = RenderOptions::from_options(&Options::default()).unwrap();
+                options.template = Some(String::from("{{generated_at}}"));
+                let mut rendered = Vec::new();
+                render(&mut rendered, &entries, &options).unwrap();
+                String::from_utf8(rendered).unwrap()
+            })
+        };
+        assert_eq!(
+            generated_at("2024-06-01T12:30:00+02:00"),
+            "2024-06-01 12:30:00 +0200"
+        );
+        assert!(parse_date(&generated_at("not a date")).is_some());
+    }
+
+    #[test]
+    fn previews_get_a_watermark() {
+        let entries = parse_lines(&["2024-01-01 10:00:00 +0000,first"]);
+        let rendered = |args: &[&str]| {
+            let mut options = parse_with(&[], args).unwrap();
+            options.template = Some(crate::DEFAULT_TEMPLATE.to_owned());
+            let mut rendered = Vec::new();
+            with_env(&[], || render(&mut rendered, &entries, &options)).unwrap();
+            String::from_utf8(rendered).unwrap()
+        };
+        assert!(rendered(&["--preview"]).contains("<div class=\"preview\">PREVIEW</div>"));
+        assert!(!rendered(&[]).contains("PREVIEW"));
+    }
+}
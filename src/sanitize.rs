@@ -0,0 +1,337 @@
+use std::collections::HashSet;
+
+/* Elements whose content is never shown as text, they are dropped with everything they contain
+ * even when they are allowed.
+ */
+const DROPPED_ELEMENTS: [&str; 7] = [
+    "script", "style", "iframe", "object", "embed", "template", "noscript",
+];
+
+/* Elements without content, they are never closed. */
+const VOID_ELEMENTS: [&str; 5] = ["br", "hr", "img", "wbr", "col"];
+
+/* Attributes holding URLs, which are only kept when they point to a page or a mailbox. */
+const URL_ATTRIBUTES: [&str; 5] = ["href", "src", "cite", "action", "formaction"];
+
+const URL_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+/* Tags and attributes written in messages which are kept in the rendered document, the others
+ * are escaped or dropped. Attributes are allowed on every allowed tag.
+ */
+#[derive(Clone, Default)]
+pub struct Allowlist {
+    tags: HashSet<String>,
+    attributes: HashSet<String>,
+}
+
+impl Allowlist {
+    /* Event handlers run scripts and are never allowed, whatever the list says. */
+    pub fn new(tags: &str, attributes: &str) -> Self {
+        let names = |list: &str| {
+            list.split([' ', ','])
+                .map(str::to_lowercase)
+                .filter(|name| !name.is_empty())
+                .collect::<HashSet<String>>()
+        };
+        let mut tags = names(tags);
+        tags.retain(|tag| !DROPPED_ELEMENTS.contains(&tag.as_str()));
+        let mut attributes = names(attributes);
+        attributes.retain(|attribute| !attribute.starts_with("on"));
+        Allowlist { tags, attributes }
+    }
+}
+
+fn escape_text(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#x27;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/* Character references are decoded before the scheme of a URL is checked, so that
+ * `jav&#x61;script:` is seen as the `javascript:` it is for the browser.
+ */
+fn decode_references(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(';').filter(|&end| end <= 10);
+        let reference = end.and_then(|end| {
+            let name = &rest[1..end];
+            let code = match name.strip_prefix('#') {
+                Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).ok(),
+                Some(decimal) => decimal.parse().ok(),
+                None => match name {
+                    "colon" => Some(':' as u32),
+                    "tab" => Some('\t' as u32),
+                    "newline" => Some('\n' as u32),
+                    _ => None,
+                },
+            };
+            code.and_then(char::from_u32).map(|c| (c, end))
+        });
+        match reference {
+            Some((c, end)) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/* Relative URLs are safe, absolute ones need one of `URL_SCHEMES`. Browsers ignore whitespace and
+ * control characters in schemes, they are ignored here too.
+ */
+fn is_safe_url(url: &str) -> bool {
+    let url = decode_references(url)
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>();
+    match url.find([':', '/', '?', '#']) {
+        Some(end) if url[end..].starts_with(':') => URL_SCHEMES
+            .iter()
+            .any(|scheme| url[..end].eq_ignore_ascii_case(scheme)),
+        _ => true,
+    }
+}
+
+struct Tag<'a> {
+    name: String,
+    closing: bool,
+    attributes: Vec<(String, Option<&'a str>)>,
+    length: usize,
+}
+
+/* Parses the tag at the start of `html`, which starts with `<`. Anything which isn't a well formed
+ * tag gives `None` and is escaped as text.
+ */
+fn parse_tag(html: &str) -> Option<Tag<'_>> {
+    let mut rest = &html[1..];
+    let closing = rest.starts_with('/');
+    if closing {
+        rest = &rest[1..];
+    }
+    let name_end = rest
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+        .unwrap_or(rest.len());
+    if name_end == 0 || !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let name = rest[..name_end].to_lowercase();
+    rest = &rest[name_end..];
+    let mut attributes = Vec::new();
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if let Some(after) = rest.strip_prefix('>') {
+            let length = html.len() - after.len();
+            return Some(Tag {
+                name,
+                closing,
+                attributes,
+                length,
+            });
+        }
+        let attribute_end = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/' | '<' | '"' | '\''))?;
+        if attribute_end == 0 {
+            return None;
+        }
+        let attribute = rest[..attribute_end].to_lowercase();
+        rest = rest[attribute_end..].trim_start();
+        let value = match rest.strip_prefix('=') {
+            Some(after) => {
+                let after = after.trim_start();
+                let (value, after) = match after.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let end = after[1..].find(quote)? + 1;
+                        (&after[1..end], &after[end + 1..])
+                    }
+                    _ => {
+                        let end = after
+                            .find(|c: char| c.is_whitespace() || c == '>')
+                            .unwrap_or(after.len());
+                        (&after[..end], &after[end..])
+                    }
+                };
+                rest = after;
+                Some(value)
+            }
+            None => None,
+        };
+        attributes.push((attribute, value));
+    }
+}
+
+/* Allowed tags are written back normalized with their allowed attributes while other tags are
+ * escaped and shown as typed, except for `DROPPED_ELEMENTS` which are dropped with their content.
+ * Allowed tags left open are closed at the end and closing tags without an opening one are
+ * dropped, so that the message never changes the structure of the document around it.
+ */
+pub fn sanitize(html: &str, allowlist: &Allowlist) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut open = Vec::<String>::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        escape_text(&rest[..start], &mut out);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+            continue;
+        }
+        let Some(tag) = parse_tag(rest) else {
+            out.push_str("&lt;");
+            rest = &rest[1..];
+            continue;
+        };
+        let (source, after) = rest.split_at(tag.length);
+        rest = after;
+        if !tag.closing && DROPPED_ELEMENTS.contains(&tag.name.as_str()) {
+            let closing = format!("</{}", tag.name);
+            let end = rest.to_ascii_lowercase().find(&closing);
+            rest = match end {
+                Some(end) => rest[end..]
+                    .find('>')
+                    .map_or("", |close| &rest[end + close + 1..]),
+                None => "",
+            };
+            continue;
+        }
+        if !allowlist.tags.contains(&tag.name) {
+            escape_text(source, &mut out);
+            continue;
+        }
+        if tag.closing {
+            if let Some(position) = open.iter().rposition(|name| *name == tag.name) {
+                for name in open.drain(position..).rev() {
+                    out.push_str(&format!("</{name}>"));
+                }
+            }
+            continue;
+        }
+        out.push('<');
+        out.push_str(&tag.name);
+        for (attribute, value) in tag.attributes.iter() {
+            if !allowlist.attributes.contains(attribute) {
+                continue;
+            }
+            let value = value.unwrap_or_default();
+            if URL_ATTRIBUTES.contains(&attribute.as_str()) && !is_safe_url(value) {
+                continue;
+            }
+            out.push_str(&format!(" {attribute}=\""));
+            escape_text(&decode_references(value), &mut out);
+            out.push('"');
+        }
+        out.push('>');
+        if !VOID_ELEMENTS.contains(&tag.name.as_str()) {
+            open.push(tag.name);
+        }
+    }
+    escape_text(rest, &mut out);
+    for name in open.iter().rev() {
+        out.push_str(&format!("</{name}>"));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowlist() -> Allowlist {
+        Allowlist::new("b i a em br script", "href title onclick")
+    }
+
+    #[test]
+    fn scripts_are_stripped() {
+        let html = "hi <script>alert(1)</script><SCRIPT src=x.js></SCRIPT>there";
+        assert_eq!(sanitize(html, &allowlist()), "hi there");
+        assert_eq!(sanitize(html, &Allowlist::default()), "hi there");
+        assert_eq!(sanitize("<script>alert(1)", &allowlist()), "");
+    }
+
+    #[test]
+    fn allowed_tags_are_kept() {
+        assert_eq!(
+            sanitize("<b>bold</b> <I>it</I><br/>", &allowlist()),
+            "<b>bold</b> <i>it</i><br>"
+        );
+        assert_eq!(sanitize("<b>x", &allowlist()), "<b>x</b>");
+        assert_eq!(sanitize("</b>x", &allowlist()), "x");
+        assert_eq!(sanitize("<b><i>x</b>", &allowlist()), "<b><i>x</i></b>");
+    }
+
+    #[test]
+    fn other_tags_are_shown_as_typed() {
+        assert_eq!(
+            sanitize("<div class=x><u>no</u></div>", &allowlist()),
+            "&lt;div class=x&gt;&lt;u&gt;no&lt;/u&gt;&lt;/div&gt;"
+        );
+        assert_eq!(
+            sanitize("<b>bold</b>", &Allowlist::default()),
+            "&lt;b&gt;bold&lt;/b&gt;"
+        );
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(
+            sanitize("1 < 2 & \"3\" > 0 <3", &allowlist()),
+            "1 &lt; 2 &amp; &quot;3&quot; &gt; 0 &lt;3"
+        );
+        assert_eq!(sanitize("<b", &allowlist()), "&lt;b");
+        assert_eq!(sanitize("a<!-- hidden -->b", &allowlist()), "ab");
+    }
+
+    #[test]
+    fn attributes_are_filtered() {
+        assert_eq!(
+            sanitize(
+                "<a href='/x' title=t onclick=\"alert(1)\" style=x>y</a>",
+                &allowlist()
+            ),
+            "<a href=\"/x\" title=\"t\">y</a>"
+        );
+        assert_eq!(
+            sanitize("<a title='\"><script>'>y</a>", &allowlist()),
+            "<a title=\"&quot;&gt;&lt;script&gt;\">y</a>"
+        );
+    }
+
+    #[test]
+    fn unsafe_urls_are_dropped() {
+        for url in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            " java\tscript:alert(1)",
+            "jav&#x61;script:alert(1)",
+            "javascript&colon;alert(1)",
+            "data:text/html,x",
+        ] {
+            let html = format!("<a href=\"{url}\">x</a>");
+            assert_eq!(sanitize(&html, &allowlist()), "<a>x</a>", "{url}");
+        }
+        for url in [
+            "https://example.com/",
+            "mailto:a@example.com",
+            "page.html?a=b:c",
+            "#top",
+        ] {
+            assert!(is_safe_url(url), "{url}");
+        }
+    }
+}
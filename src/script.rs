@@ -0,0 +1,725 @@
+use std::collections::HashMap;
+use std::io::{Error as IoError, ErrorKind};
+
+use serde_json::Value as Json;
+
+use crate::tokens::{tokenize_line, Cursor, Location, Syntax, Token};
+
+/* Scripts are the expressions computed by custom helpers, e.g.
+ * `if(len(value) > 20, slice(value, 0, 20) + "…", value)`. They only compute a value from the
+ * parameters of the call: there is no way to read files, run commands or loop, and the work
+ * they do is bounded, so that a script can't do more to the machine than rendering does.
+ */
+pub struct Script {
+    expression: Expression,
+}
+
+/* The parameters of a helper call, `arg(n)` is the nth one and `hash("key")` the `key=`. */
+pub struct Call<'a> {
+    pub args: Vec<&'a Json>,
+    pub hash: HashMap<&'a str, &'a Json>,
+}
+
+/* Strings can't grow past this, and a script can't produce more than `MAX_WORK` bytes in all. */
+const MAX_STRING_LENGTH: usize = 1 << 20;
+const MAX_WORK: usize = 1 << 24;
+
+/* Parentheses and chains of operators nest expressions, which are parsed and evaluated
+ * recursively, bounding them keeps both off the end of the stack.
+ */
+const MAX_DEPTH: usize = 128;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "a condition",
+            Value::Int(_) => "a number",
+            Value::Str(_) => "a string",
+        }
+    }
+
+    /* Like in templates, null, false, 0 and the empty string are false. */
+    fn is_true(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Int(i) => *i != 0,
+            Value::Str(s) => !s.is_empty(),
+        }
+    }
+
+    fn to_text(&self) -> String {
+        match self {
+            Value::Null => String::new(),
+            Value::Bool(b) => b.to_string(),
+            Value::Int(i) => i.to_string(),
+            Value::Str(s) => s.clone(),
+        }
+    }
+
+    /* Numbers which aren't integers are given as strings, arrays and objects can't be used. */
+    fn from_json(json: &Json) -> Result<Self, String> {
+        match json {
+            Json::Null => Ok(Value::Null),
+            Json::Bool(b) => Ok(Value::Bool(*b)),
+            Json::Number(n) => Ok(n
+                .as_i64()
+                .map_or_else(|| Value::Str(n.to_string()), Value::Int)),
+            Json::String(s) => Ok(Value::Str(s.clone())),
+            Json::Array(_) | Json::Object(_) => {
+                Err(String::from("arrays and objects can't be used in scripts"))
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Json {
+        match self {
+            Value::Null => Json::Null,
+            Value::Bool(b) => Json::from(*b),
+            Value::Int(i) => Json::from(*i),
+            Value::Str(s) => Json::from(s.as_str()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Function {
+    Arg,
+    Hash,
+    If,
+    Default,
+    Str,
+    Int,
+    Len,
+    Lower,
+    Upper,
+    Trim,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Replace,
+    Slice,
+    Repeat,
+}
+
+/* Names with their number of arguments. */
+const FUNCTIONS: [(&str, Function, usize); 16] = [
+    ("arg", Function::Arg, 1),
+    ("hash", Function::Hash, 1),
+    ("if", Function::If, 3),
+    ("default", Function::Default, 2),
+    ("str", Function::Str, 1),
+    ("int", Function::Int, 1),
+    ("len", Function::Len, 1),
+    ("lower", Function::Lower, 1),
+    ("upper", Function::Upper, 1),
+    ("trim", Function::Trim, 1),
+    ("contains", Function::Contains, 2),
+    ("starts_with", Function::StartsWith, 2),
+    ("ends_with", Function::EndsWith, 2),
+    ("replace", Function::Replace, 3),
+    ("slice", Function::Slice, 3),
+    ("repeat", Function::Repeat, 2),
+];
+
+#[derive(Clone, Copy)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+const COMPARISONS: [(&str, Operator); 6] = [
+    ("==", Operator::Equal),
+    ("!=", Operator::NotEqual),
+    ("<=", Operator::LessOrEqual),
+    (">=", Operator::GreaterOrEqual),
+    ("<", Operator::Less),
+    (">", Operator::Greater),
+];
+
+const SUMS: [(&str, Operator); 2] = [("+", Operator::Add), ("-", Operator::Subtract)];
+
+const PRODUCTS: [(&str, Operator); 3] = [
+    ("*", Operator::Multiply),
+    ("/", Operator::Divide),
+    ("%", Operator::Remainder),
+];
+
+enum Expression {
+    Value(Value),
+    Not(Box<Expression>),
+    Negate(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+    Call(Function, Vec<Expression>),
+}
+
+fn too_long() -> String {
+    format!("strings can't be longer than {MAX_STRING_LENGTH} bytes")
+}
+
+fn text(value: &Value, function: &str) -> Result<String, String> {
+    match value {
+        Value::Str(s) => Ok(s.clone()),
+        other => Err(format!(
+            "{function} needs a string, found {}",
+            other.type_name()
+        )),
+    }
+}
+
+fn int(value: &Value, function: &str) -> Result<i64, String> {
+    match value {
+        Value::Int(i) => Ok(*i),
+        other => Err(format!(
+            "{function} needs a number, found {}",
+            other.type_name()
+        )),
+    }
+}
+
+struct Evaluator<'a> {
+    call: &'a Call<'a>,
+    work: usize,
+}
+
+impl Evaluator<'_> {
+    /* Every step costs one, strings cost their length, keeping large strings from being copied
+     * over and over.
+     */
+    fn spend(&mut self, cost: usize) -> Result<(), String> {
+        self.work += cost + 1;
+        match self.work > MAX_WORK {
+            true => Err(String::from("script does too much work")),
+            false => Ok(()),
+        }
+    }
+
+    fn string(&mut self, s: String) -> Result<Value, String> {
+        if s.len() > MAX_STRING_LENGTH {
+            return Err(too_long());
+        }
+        self.spend(s.len())?;
+        Ok(Value::Str(s))
+    }
+
+    fn binary(&mut self, operator: Operator, left: Value, right: Value) -> Result<Value, String> {
+        let ordering = |left: &Value, right: &Value| match (left, right) {
+            (Value::Int(a), Value::Int(b)) => Ok(a.cmp(b)),
+            (Value::Str(a), Value::Str(b)) => Ok(a.cmp(b)),
+            _ => Err(format!(
+                "can't compare {} with {}",
+                left.type_name(),
+                right.type_name()
+            )),
+        };
+        let overflow = || String::from("number too large");
+        match operator {
+            Operator::Equal => Ok(Value::Bool(left == right)),
+            Operator::NotEqual => Ok(Value::Bool(left != right)),
+            Operator::Less => Ok(Value::Bool(ordering(&left, &right)?.is_lt())),
+            Operator::LessOrEqual => Ok(Value::Bool(ordering(&left, &right)?.is_le())),
+            Operator::Greater => Ok(Value::Bool(ordering(&left, &right)?.is_gt())),
+            Operator::GreaterOrEqual => Ok(Value::Bool(ordering(&left, &right)?.is_ge())),
+            // Adding anything to a string concatenates it
+            Operator::Add => match (&left, &right) {
+                (Value::Int(a), Value::Int(b)) => {
+                    a.checked_add(*b).map(Value::Int).ok_or_else(overflow)
+                }
+                (Value::Str(_), _) | (_, Value::Str(_)) => {
+                    self.string(left.to_text() + &right.to_text())
+                }
+                _ => Err(format!(
+                    "can't add {} to {}",
+                    right.type_name(),
+                    left.type_name()
+                )),
+            },
+            _ => {
+                let a = int(&left, "arithmetic")?;
+                let b = int(&right, "arithmetic")?;
+                let result = match operator {
+                    Operator::Subtract => a.checked_sub(b),
+                    Operator::Multiply => a.checked_mul(b),
+                    _ if b == 0 => return Err(String::from("division by zero")),
+                    Operator::Divide => a.checked_div(b),
+                    _ => a.checked_rem(b),
+                };
+                result.map(Value::Int).ok_or_else(overflow)
+            }
+        }
+    }
+
+    fn function(&mut self, function: Function, arguments: &[Expression]) -> Result<Value, String> {
+        // Only the branch taken by `if` is evaluated
+        if let Function::If = function {
+            let condition = self.evaluate(&arguments[0])?;
+            return self.evaluate(&arguments[if condition.is_true() { 1 } else { 2 }]);
+        }
+        let values = arguments
+            .iter()
+            .map(|argument| self.evaluate(argument))
+            .collect::<Result<Vec<Value>, String>>()?;
+        let name = FUNCTIONS
+            .iter()
+            .find(|(_, f, _)| *f == function)
+            .map_or("", |(name, ..)| name);
+        match function {
+            Function::Arg => {
+                let index = usize::try_from(int(&values[0], name)?).ok();
+                match index.and_then(|index| self.call.args.get(index)) {
+                    Some(json) => Value::from_json(json),
+                    None => Ok(Value::Null),
+                }
+            }
+            Function::Hash => match self.call.hash.get(text(&values[0], name)?.as_str()) {
+                Some(json) => Value::from_json(json),
+                None => Ok(Value::Null),
+            },
+            Function::If => unreachable!(),
+            Function::Default => match values[0] {
+                Value::Null => Ok(values[1].clone()),
+                Value::Str(ref s) if s.is_empty() => Ok(values[1].clone()),
+                _ => Ok(values[0].clone()),
+            },
+            Function::Str => self.string(values[0].to_text()),
+            Function::Int => Ok(match &values[0] {
+                Value::Int(i) => Value::Int(*i),
+                Value::Bool(b) => Value::Int(*b as i64),
+                Value::Str(s) => s.trim().parse().map_or(Value::Null, Value::Int),
+                Value::Null => Value::Null,
+            }),
+            Function::Len => Ok(Value::Int(text(&values[0], name)?.chars().count() as i64)),
+            Function::Lower => self.string(text(&values[0], name)?.to_lowercase()),
+            Function::Upper => self.string(text(&values[0], name)?.to_uppercase()),
+            Function::Trim => self.string(text(&values[0], name)?.trim().to_owned()),
+            Function::Contains => {
+                let (s, t) = (text(&values[0], name)?, text(&values[1], name)?);
+                Ok(Value::Bool(s.contains(&t)))
+            }
+            Function::StartsWith => {
+                let (s, t) = (text(&values[0], name)?, text(&values[1], name)?);
+                Ok(Value::Bool(s.starts_with(&t)))
+            }
+            Function::EndsWith => {
+                let (s, t) = (text(&values[0], name)?, text(&values[1], name)?);
+                Ok(Value::Bool(s.ends_with(&t)))
+            }
+            Function::Replace => {
+                let s = text(&values[0], name)?;
+                let (from, to) = (text(&values[1], name)?, text(&values[2], name)?);
+                if from.is_empty() {
+                    return Err(String::from("replace needs something to replace"));
+                }
+                // Bound the result before building it
+                let count = s.matches(&from).count();
+                if s.len() - count * from.len() + count * to.len() > MAX_STRING_LENGTH {
+                    return Err(too_long());
+                }
+                self.string(s.replace(&from, &to))
+            }
+            Function::Slice => {
+                // Positions count characters and are clamped to the string
+                let s = text(&values[0], name)?;
+                let position = |value: &Value| -> Result<usize, String> {
+                    Ok(usize::try_from(int(value, name)?.max(0)).unwrap_or(usize::MAX))
+                };
+                let (start, end) = (position(&values[1])?, position(&values[2])?);
+                let slice = s
+                    .chars()
+                    .skip(start)
+                    .take(end.saturating_sub(start))
+                    .collect();
+                self.string(slice)
+            }
+            Function::Repeat => {
+                let s = text(&values[0], name)?;
+                let count = usize::try_from(int(&values[1], name)?.max(0)).unwrap_or(usize::MAX);
+                if s.len().saturating_mul(count) > MAX_STRING_LENGTH {
+                    return Err(too_long());
+                }
+                self.string(s.repeat(count))
+            }
+        }
+    }
+
+    fn evaluate(&mut self, expression: &Expression) -> Result<Value, String> {
+        self.spend(0)?;
+        match expression {
+            Expression::Value(value) => Ok(value.clone()),
+            Expression::Not(operand) => Ok(Value::Bool(!self.evaluate(operand)?.is_true())),
+            Expression::Negate(operand) => {
+                let i = int(&self.evaluate(operand)?, "'-'")?;
+                i.checked_neg()
+                    .map(Value::Int)
+                    .ok_or_else(|| String::from("number too large"))
+            }
+            Expression::And(left, right) => Ok(Value::Bool(
+                self.evaluate(left)?.is_true() && self.evaluate(right)?.is_true(),
+            )),
+            Expression::Or(left, right) => Ok(Value::Bool(
+                self.evaluate(left)?.is_true() || self.evaluate(right)?.is_true(),
+            )),
+            Expression::Binary(operator, left, right) => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                self.binary(*operator, left, right)
+            }
+            Expression::Call(function, arguments) => self.function(*function, arguments),
+        }
+    }
+}
+
+const OPERATORS: [&str; 17] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "(", ")", ",", "+", "-", "*", "/", "%",
+];
+
+/* Scripts are files, so errors give both the line and the column. */
+fn error((line, column): Location, message: &str) -> IoError {
+    IoError::new(
+        ErrorKind::InvalidInput,
+        format!("Invalid script at line {line}, column {column}: {message}"),
+    )
+}
+
+const SYNTAX: Syntax = Syntax {
+    operators: &OPERATORS,
+    comments: true,
+    newlines: true,
+    error,
+};
+
+/* The grammar, from the loosest operator to the tightest, is:
+ *   or         = and ("||" and)*
+ *   and        = not ("&&" not)*
+ *   not        = "!" not | comparison
+ *   comparison = sum (("==" | "!=" | "<" | "<=" | ">" | ">=") sum)?
+ *   sum        = product (("+" | "-") product)*
+ *   product    = negation (("*" | "/" | "%") negation)*
+ *   negation   = "-" negation | primary
+ *   primary    = number | string | "true" | "false" | "null" | "value"
+ *              | function "(" arguments ")" | "(" or ")"
+ * Types are only known when the helper is called, so they are checked then.
+ */
+struct Parser {
+    cursor: Cursor,
+    depth: usize,
+}
+
+impl Parser {
+    fn deepen(&mut self) -> Result<(), IoError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(error(self.cursor.location(), "nested too deeply"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Expression, IoError>,
+    ) -> Result<Expression, IoError> {
+        self.deepen()?;
+        let expression = parse(self)?;
+        self.depth -= 1;
+        Ok(expression)
+    }
+
+    fn binary(
+        &mut self,
+        operators: &[(&str, Operator)],
+        operand: fn(&mut Self) -> Result<Expression, IoError>,
+    ) -> Result<Expression, IoError> {
+        let (mut expression, depth) = (operand(self)?, self.depth);
+        while let Some(&(_, operator)) = operators.iter().find(|(o, _)| self.cursor.accept(o)) {
+            self.deepen()?;
+            let right = operand(self)?;
+            expression = Expression::Binary(operator, Box::new(expression), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(expression)
+    }
+
+    fn or(&mut self) -> Result<Expression, IoError> {
+        let (mut expression, depth) = (self.and()?, self.depth);
+        while self.cursor.accept("||") {
+            self.deepen()?;
+            let right = self.and()?;
+            expression = Expression::Or(Box::new(expression), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(expression)
+    }
+
+    fn and(&mut self) -> Result<Expression, IoError> {
+        let (mut expression, depth) = (self.not()?, self.depth);
+        while self.cursor.accept("&&") {
+            self.deepen()?;
+            let right = self.not()?;
+            expression = Expression::And(Box::new(expression), Box::new(right));
+        }
+        self.depth = depth;
+        Ok(expression)
+    }
+
+    fn not(&mut self) -> Result<Expression, IoError> {
+        if self.cursor.accept("!") {
+            let operand = self.nested(Self::not)?;
+            return Ok(Expression::Not(Box::new(operand)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expression, IoError> {
+        let left = self.sum()?;
+        let comparison = COMPARISONS.iter().find(|(o, _)| self.cursor.accept(o));
+        let Some(&(_, operator)) = comparison else {
+            return Ok(left);
+        };
+        let right = self.sum()?;
+        Ok(Expression::Binary(
+            operator,
+            Box::new(left),
+            Box::new(right),
+        ))
+    }
+
+    fn sum(&mut self) -> Result<Expression, IoError> {
+        self.binary(&SUMS, Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expression, IoError> {
+        self.binary(&PRODUCTS, Self::negation)
+    }
+
+    fn negation(&mut self) -> Result<Expression, IoError> {
+        if self.cursor.accept("-") {
+            let operand = self.nested(Self::negation)?;
+            return Ok(Expression::Negate(Box::new(operand)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, IoError> {
+        let location = self.cursor.location();
+        match self.cursor.next() {
+            Some(Token::Int(number)) => Ok(Expression::Value(Value::Int(number))),
+            Some(Token::Str(string)) => Ok(Expression::Value(Value::Str(string))),
+            Some(Token::Operator("(")) => {
+                let inner = self.nested(Self::or)?;
+                self.cursor.expect(")")?;
+                Ok(inner)
+            }
+            Some(Token::Identifier(name)) if self.cursor.accept("(") => {
+                let &(_, function, count) = FUNCTIONS
+                    .iter()
+                    .find(|(function, ..)| *function == name)
+                    .ok_or_else(|| error(location, &format!("unknown function {name}")))?;
+                let mut arguments = Vec::new();
+                for i in 0..count {
+                    if i > 0 {
+                        self.cursor.expect(",")?;
+                    }
+                    arguments.push(self.nested(Self::or)?);
+                }
+                self.cursor.expect(")")?;
+                Ok(Expression::Call(function, arguments))
+            }
+            Some(Token::Identifier(name)) => match name.as_str() {
+                "true" | "false" => Ok(Expression::Value(Value::Bool(name == "true"))),
+                "null" => Ok(Expression::Value(Value::Null)),
+                "value" => Ok(Expression::Call(
+                    Function::Arg,
+                    vec![Expression::Value(Value::Int(0))],
+                )),
+                _ => Err(error(location, &format!("unknown name {name}"))),
+            },
+            Some(Token::Operator(operator)) => {
+                Err(error(location, &format!("unexpected '{operator}'")))
+            }
+            None => Err(error(location, "unexpected end of script")),
+        }
+    }
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Self, IoError> {
+        let lines = text.lines().count().max(1);
+        let last_line = text.lines().last().unwrap_or_default();
+        let mut tokens = Vec::new();
+        for (line_index, line) in text.lines().enumerate() {
+            tokenize_line(&SYNTAX, line_index + 1, line, &mut tokens)?;
+        }
+        let end = (lines, last_line.chars().count() + 1);
+        let mut parser = Parser {
+            cursor: Cursor::new(&SYNTAX, tokens, end),
+            depth: 0,
+        };
+        let expression = parser.or()?;
+        if !parser.cursor.at_end() {
+            return Err(error(
+                parser.cursor.location(),
+                "expected the end of the script",
+            ));
+        }
+        Ok(Script { expression })
+    }
+
+    pub fn evaluate(&self, call: &Call) -> Result<Value, String> {
+        Evaluator { call, work: 0 }.evaluate(&self.expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn run(script: &str, args: &[Json]) -> Result<Value, String> {
+        let hash = json!({"length": 3, "mark": "…"});
+        let call = Call {
+            args: args.iter().collect(),
+            hash: hash
+                .as_object()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| (k.as_str(), v))
+                .collect(),
+        };
+        Script::parse(script).unwrap().evaluate(&call)
+    }
+
+    fn string(s: &str) -> Result<Value, String> {
+        Ok(Value::Str(String::from(s)))
+    }
+
+    #[test]
+    fn arithmetic_follows_precedence() {
+        assert_eq!(run("1 + 2 * 3 - -4", &[]), Ok(Value::Int(11)));
+        assert_eq!(run("(1 + 2) * 3 % 4", &[]), Ok(Value::Int(1)));
+        assert_eq!(run("7 / 2 == 3 && !(1 > 2)", &[]), Ok(Value::Bool(true)));
+        assert_eq!(run("1 / 0", &[]), Err(String::from("division by zero")));
+        assert_eq!(
+            run("9223372036854775807 + 1", &[]),
+            Err(String::from("number too large"))
+        );
+    }
+
+    #[test]
+    fn helpers_compute_from_their_parameters() {
+        let truncate = "if(len(value) > hash(\"length\"), slice(value, 0, hash(\"length\")) + hash(\"mark\"), value)";
+        assert_eq!(run(truncate, &[json!("hello")]), string("hel…"));
+        assert_eq!(run(truncate, &[json!("hi")]), string("hi"));
+        assert_eq!(
+            run("arg(1) + arg(0)", &[json!(1), json!("a")]),
+            string("a1")
+        );
+        assert_eq!(run("arg(5)", &[]), Ok(Value::Null));
+        assert_eq!(run("default(hash(\"none\"), \"x\")", &[]), string("x"));
+        assert_eq!(run("int(\" 42 \") + 1", &[]), Ok(Value::Int(43)));
+        assert_eq!(run("arg(0)", &[json!(1.5)]), string("1.5"));
+        assert_eq!(
+            run(
+                "upper(replace(trim(value), \"a\", \"o\"))",
+                &[json!(" banana ")]
+            ),
+            string("BONONO")
+        );
+        assert_eq!(
+            run(
+                "starts_with(value, \"#\") ||\n // tags\n ends_with(value, \"!\")",
+                &[json!("#a")]
+            ),
+            Ok(Value::Bool(true))
+        );
+    }
+
+    #[test]
+    fn types_are_checked_when_called() {
+        assert_eq!(
+            run("len(value)", &[json!(3)]),
+            Err(String::from("len needs a string, found a number"))
+        );
+        assert_eq!(
+            run("value < 2", &[json!("1")]),
+            Err(String::from("can't compare a string with a number"))
+        );
+        assert_eq!(
+            run("value", &[json!([1])]),
+            Err(String::from("arrays and objects can't be used in scripts"))
+        );
+        assert_eq!(run("if(true, 1, len(2))", &[]), Ok(Value::Int(1)));
+    }
+
+    #[test]
+    fn errors_give_their_position() {
+        let message = |script: &str| Script::parse(script).err().unwrap().to_string();
+        assert_eq!(
+            message("1 +\n  unknown(2)"),
+            "Invalid script at line 2, column 3: unknown function unknown"
+        );
+        assert_eq!(
+            message("len(\"a\", 2)"),
+            "Invalid script at line 1, column 8: expected ')'"
+        );
+        assert_eq!(
+            message("1 +"),
+            "Invalid script at line 1, column 4: unexpected end of script"
+        );
+        assert_eq!(
+            message("\"open"),
+            "Invalid script at line 1, column 1: unterminated string"
+        );
+        assert_eq!(
+            message("1 2"),
+            "Invalid script at line 1, column 3: expected the end of the script"
+        );
+    }
+
+    #[test]
+    fn work_is_bounded() {
+        let too_deep = |script: &str| {
+            Script::parse(script)
+                .err()
+                .is_some_and(|e| e.to_string().ends_with("nested too deeply"))
+        };
+        assert!(too_deep(&format!(
+            "{}1{}",
+            "(".repeat(200),
+            ")".repeat(200)
+        )));
+        assert!(too_deep(&format!("1{}", " + 1".repeat(200))));
+        assert!(too_deep(&format!("true{}", " && true".repeat(200))));
+        assert!(!too_deep(&format!("1{}", " + 1".repeat(100))));
+        assert!(run("repeat(\"ab\", 1000000)", &[]).is_err());
+        assert_eq!(run("len(repeat(\"ab\", 1000))", &[]), Ok(Value::Int(2000)));
+        let doubling = (0..30).fold(String::from("\"ab\""), |s, _| {
+            format!("replace({s}, \"a\", \"aa\")")
+        });
+        assert!(run(&doubling, &[]).is_err());
+        let copies = vec!["len(repeat(\"a\", 1000000))"; 40].join(" + ");
+        assert_eq!(
+            run(&copies, &[]),
+            Err(String::from("script does too much work"))
+        );
+    }
+}
@@ -0,0 +1,761 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::prelude::*;
+use std::io::{BufReader, Error as IoError, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const CACHE_TTL: Duration = Duration::from_secs(1);
+
+const RELOAD_PATH: &str = "/_vent/reload";
+
+const MAX_SERVED_FILES: usize = 10_000;
+
+/* Reading a request or writing a response taking longer than this ends the connection. */
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/* Reload streams send a comment this often, which ends the stream of a browser gone away. */
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+const MAX_REQUEST_BYTES: u64 = 16 * 1024;
+
+/* Served HTML pages subscribe to the reload events and reload themselves when one is received. */
+const RELOAD_SCRIPT: &str =
+    "<script>new EventSource(\"/_vent/reload\").onmessage = () => location.reload();</script>";
+
+/* Each browser listening for reloads gets its own channel, channels of closed connections are
+ * dropped on the next reload.
+ */
+#[derive(Clone, Default)]
+pub struct Reloads {
+    senders: Arc<Mutex<Vec<Sender<()>>>>,
+    cache: Cache,
+}
+
+impl Reloads {
+    pub fn subscribe(&self) -> Receiver<()> {
+        let (sender, receiver) = mpsc::channel();
+        self.senders.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn notify(&self) {
+        self.cache.clear();
+        self.senders
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.send(()).is_ok());
+    }
+}
+
+/* Served files are kept in memory, a file read less than `CACHE_TTL` ago is reused as is and an
+ * older one is only read again if it was modified since. Renders clear the whole cache, including
+ * the files linked from the output.
+ */
+/* Counts the files the current thread read from disk so tests can tell the cache was used. */
+#[cfg(test)]
+thread_local! {
+    static DISK_READS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+#[derive(Clone, Default)]
+struct Cache {
+    files: Arc<Mutex<HashMap<PathBuf, CachedFile>>>,
+    linked: Arc<Mutex<Option<Arc<HashSet<PathBuf>>>>>,
+}
+
+struct CachedFile {
+    modified: Option<SystemTime>,
+    checked_at: Instant,
+    content: Arc<Vec<u8>>,
+}
+
+impl Cache {
+    fn read(&self, path: &Path) -> Result<Arc<Vec<u8>>, IoError> {
+        let modified = || fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut files = self.files.lock().unwrap();
+        if let Some(cached) = files.get_mut(path) {
+            if cached.checked_at.elapsed() < CACHE_TTL {
+                return Ok(cached.content.clone());
+            }
+            let current = modified();
+            if current.is_some() && current == cached.modified {
+                cached.checked_at = Instant::now();
+                return Ok(cached.content.clone());
+            }
+        }
+        let modified = modified();
+        let content = Arc::new(fs::read(path)?);
+        #[cfg(test)]
+        DISK_READS.with(|reads| reads.set(reads.get() + 1));
+        files.insert(
+            path.to_owned(),
+            CachedFile {
+                modified,
+                checked_at: Instant::now(),
+                content: content.clone(),
+            },
+        );
+        Ok(content)
+    }
+
+    fn linked_files(&self, directory: &Path, index: &str) -> Arc<HashSet<PathBuf>> {
+        let mut linked = self.linked.lock().unwrap();
+        linked
+            .get_or_insert_with(|| Arc::new(linked_files(self, directory, index)))
+            .clone()
+    }
+
+    fn clear(&self) {
+        self.files.lock().unwrap().clear();
+        *self.linked.lock().unwrap() = None;
+    }
+}
+
+/* Links are the values of `href` and `src` attributes and of CSS `url()`, quoted or not. */
+fn links(content: &str) -> Vec<&str> {
+    let mut links = Vec::new();
+    for prefix in ["href=", "src=", "url("] {
+        let mut rest = content;
+        while let Some(start) = rest.find(prefix) {
+            rest = &rest[start + prefix.len()..];
+            let end = match rest.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    rest = &rest[1..];
+                    rest.find(quote)
+                }
+                _ => rest.find(|c: char| c.is_whitespace() || c == '>' || c == ')'),
+            };
+            let end = end.unwrap_or(rest.len());
+            links.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+    links
+}
+
+/* Links are resolved against the directory of the file they are in, `None` when they go above
+ * the served directory.
+ */
+fn resolve_link(parent: &str, link: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    let base = match link.starts_with('/') {
+        true => "",
+        false => parent,
+    };
+    for segment in base.split('/').chain(link.split('/')) {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Some(format!("/{}", segments.join("/")))
+}
+
+/* Only the output and the files it links to, followed through HTML and CSS files, are served so
+ * that the database, drafts and attachments next to the output stay private. Links to other
+ * sites, fragments and data URLs are skipped, as are links leaving the served directory.
+ */
+fn linked_files(cache: &Cache, directory: &Path, index: &str) -> HashSet<PathBuf> {
+    let mut files = HashSet::new();
+    let mut pending = vec![directory.join(index)];
+    while let Some(path) = pending.pop() {
+        if files.len() >= MAX_SERVED_FILES || !files.insert(path.clone()) {
+            continue;
+        }
+        if !matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("html" | "css")
+        ) {
+            continue;
+        }
+        let Ok(content) = cache.read(&path) else {
+            continue;
+        };
+        let parent = path
+            .parent()
+            .and_then(|parent| parent.strip_prefix(directory).ok())
+            .map(|parent| parent.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_default();
+        for link in links(&String::from_utf8_lossy(&content)) {
+            if link.is_empty() || link.starts_with(['#', '?']) || link.contains(':') {
+                continue;
+            }
+            let link = link.split(['?', '#']).next().unwrap_or_default();
+            let linked = resolve_link(&parent, link)
+                .and_then(|request_path| served_path(directory, index, &request_path));
+            if let Some(linked) = linked {
+                pending.push(linked);
+            }
+        }
+    }
+    files
+}
+
+fn modification_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/* Files are polled rather than watched through the platform APIs, a change is rendered on the
+ * next poll. Failed renders are reported and the previous output is kept until the next change.
+ */
+pub fn watch<F>(paths: &[PathBuf], mut render: F, reloads: &Reloads) -> Result<(), IoError>
+where
+    F: FnMut() -> Result<(), IoError>,
+{
+    render()?;
+    let mut times = modification_times(paths);
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        poll(paths, &mut times, &mut render, reloads);
+    }
+}
+
+/* `times` are the modification times seen by the previous poll, a change to any of them is
+ * rendered and, if that succeeded, reloaded. Returns whether there was a change.
+ */
+fn poll<F>(
+    paths: &[PathBuf],
+    times: &mut Vec<Option<SystemTime>>,
+    render: F,
+    reloads: &Reloads,
+) -> bool
+where
+    F: FnOnce() -> Result<(), IoError>,
+{
+    let new_times = modification_times(paths);
+    if new_times == *times {
+        return false;
+    }
+    *times = new_times;
+    match render() {
+        Ok(()) => {
+            eprintln!("Rendered after a change");
+            reloads.notify();
+        }
+        Err(e) => eprintln!("Render failed: {e}"),
+    }
+    true
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("xml") => "application/atom+xml; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+fn write_response(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) {
+    let header = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    let _ = stream
+        .write_all(header.as_bytes())
+        .and_then(|_| stream.write_all(body));
+}
+
+/* Reload events are sent as server-sent events until the browser goes away. */
+fn stream_reloads(mut stream: TcpStream, reloads: &Reloads) {
+    let receiver = reloads.subscribe();
+    let header =
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\n\r\n";
+    if stream.write_all(header.as_bytes()).is_err() {
+        return;
+    }
+    loop {
+        let event: &[u8] = match receiver.recv_timeout(KEEPALIVE_INTERVAL) {
+            Ok(()) => b"data: reload\n\n",
+            Err(RecvTimeoutError::Timeout) => b": keepalive\n\n",
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        if stream.write_all(event).is_err() {
+            return;
+        }
+    }
+}
+
+/* Only files below the served directory are served, paths going up a directory are refused. */
+fn served_path(directory: &Path, index: &str, request_path: &str) -> Option<PathBuf> {
+    let request_path = request_path.split(['?', '#']).next().unwrap_or_default();
+    let relative = Path::new(request_path.trim_start_matches('/'));
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    match relative.as_os_str().is_empty() {
+        true => Some(directory.join(index)),
+        false => Some(directory.join(relative)),
+    }
+}
+
+fn serve_file(stream: &mut TcpStream, cache: &Cache, path: &Path) {
+    match cache.read(path) {
+        Ok(content) => {
+            let mut body = content.to_vec();
+            let content_type = content_type(path);
+            // The script goes at the end of the body, or of the document without one
+            if content_type.starts_with("text/html") {
+                let end = body
+                    .windows(7)
+                    .rposition(|w| w.eq_ignore_ascii_case(b"</body>"))
+                    .unwrap_or(body.len());
+                body.splice(end..end, RELOAD_SCRIPT.bytes());
+            }
+            write_response(stream, "200 OK", content_type, &body);
+        }
+        Err(_) => write_response(stream, "404 Not Found", "text/plain", b"Not found"),
+    }
+}
+
+/* Connections beyond `max_connections` open at once are refused as soon as they are accepted, each
+ * connection being handled by its own thread. Reload streams are counted as well, they stay open
+ * as long as their page.
+ */
+struct Server {
+    directory: PathBuf,
+    index: String,
+    port: u16,
+    reloads: Reloads,
+    max_connections: usize,
+    connections: AtomicUsize,
+}
+
+/* Pages of other sites whose name resolves to the loopback interface, see DNS rebinding, send
+ * their own name as the host and are refused.
+ */
+fn is_loopback_host(host: &str, port: u16) -> bool {
+    [format!("127.0.0.1:{port}"), format!("localhost:{port}")]
+        .iter()
+        .any(|allowed| host.eq_ignore_ascii_case(allowed))
+}
+
+fn handle_connection(mut stream: TcpStream, server: &Server) {
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(match stream.try_clone() {
+        Ok(stream) => stream.take(MAX_REQUEST_BYTES),
+        Err(_) => return,
+    });
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    // Only the host is read from the headers
+    let mut host = None;
+    let mut header = String::new();
+    while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("host") {
+                host = Some(value.trim().to_owned());
+            }
+        }
+        header.clear();
+    }
+    if !host.is_some_and(|host| is_loopback_host(&host, server.port)) {
+        write_response(&mut stream, "403 Forbidden", "text/plain", b"Forbidden");
+        return;
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, request_path) = (parts.next(), parts.next().unwrap_or("/"));
+    if method != Some("GET") {
+        write_response(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+        return;
+    }
+    if request_path == RELOAD_PATH {
+        stream_reloads(stream, &server.reloads);
+        return;
+    }
+    let Some(path) = served_path(&server.directory, &server.index, request_path) else {
+        write_response(&mut stream, "403 Forbidden", "text/plain", b"Forbidden");
+        return;
+    };
+    let cache = &server.reloads.cache;
+    if !cache
+        .linked_files(&server.directory, &server.index)
+        .contains(&path)
+    {
+        write_response(&mut stream, "404 Not Found", "text/plain", b"Not found");
+        return;
+    }
+    serve_file(&mut stream, cache, &path);
+}
+
+/* A connection keeps its place among the `max_connections` until it is dropped, however its
+ * thread ends.
+ */
+struct Connection {
+    server: Arc<Server>,
+}
+
+impl Connection {
+    fn open(server: &Arc<Server>) -> Option<Self> {
+        if server.connections.fetch_add(1, Ordering::SeqCst) >= server.max_connections {
+            server.connections.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(Connection {
+            server: server.clone(),
+        })
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.server.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn accept(listener: TcpListener, server: Arc<Server>) {
+    for mut stream in listener.incoming().flatten() {
+        if stream.set_read_timeout(Some(TIMEOUT)).is_err()
+            || stream.set_write_timeout(Some(TIMEOUT)).is_err()
+        {
+            continue;
+        }
+        match Connection::open(&server) {
+            Some(connection) => {
+                thread::spawn(move || handle_connection(stream, &connection.server));
+            }
+            None => write_response(
+                &mut stream,
+                "503 Service Unavailable",
+                "text/plain",
+                b"Busy",
+            ),
+        }
+    }
+}
+
+/* The output and the files it links to are served on the loopback interface, the output itself
+ * is the index. Every connection is handled in its own thread so that listening for reloads doesn't
+ * block other requests, at most `max_connections` of them at once.
+ */
+pub fn serve(
+    output: &Path,
+    port: u16,
+    max_connections: usize,
+    reloads: &Reloads,
+) -> Result<(), IoError> {
+    let directory = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+        _ => PathBuf::from("."),
+    };
+    let index = output
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "Invalid output file"))?
+        .to_owned();
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("Serving {} on http://127.0.0.1:{port}/", output.display());
+    let server = Arc::new(Server {
+        directory,
+        index,
+        port,
+        reloads: reloads.clone(),
+        max_connections,
+        connections: AtomicUsize::new(0),
+    });
+    thread::spawn(move || accept(listener, server));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_dir;
+    use std::fs::File;
+    use std::io::Read;
+
+    #[test]
+    fn links_are_read_from_attributes_and_css() {
+        let content = r#"<link href="index.css"><img src='a.png'><a href=page-2.html>
+            <style>body { background: url(bg.png) }</style>"#;
+        assert_eq!(
+            links(content),
+            ["index.css", "page-2.html", "a.png", "bg.png"]
+        );
+    }
+
+    #[test]
+    fn served_paths_stay_in_the_directory() {
+        let directory = Path::new("out");
+        assert_eq!(
+            served_path(directory, "vent.html", "/?reload"),
+            Some(PathBuf::from("out/vent.html"))
+        );
+        assert_eq!(
+            served_path(directory, "vent.html", "/css/a.css#top"),
+            Some(PathBuf::from("out/css/a.css"))
+        );
+        assert_eq!(served_path(directory, "vent.html", "/../vent.csv"), None);
+        assert_eq!(served_path(directory, "vent.html", "/css/../../a"), None);
+    }
+
+    fn served_directory() -> PathBuf {
+        let directory = temp_dir("serve");
+        fs::create_dir(directory.join("css")).unwrap();
+        fs::write(
+            directory.join("vent.html"),
+            r##"<link href="./css/index.css"><a href="#top"></a><a href="https://example.com/">
+            <a href="vent-2.html"></a><a href="../outside.html"></a>"##,
+        )
+        .unwrap();
+        fs::write(directory.join("vent-2.html"), r#"<img src="/image.png">"#).unwrap();
+        fs::write(
+            directory.join("css/index.css"),
+            "a { background: url('../bg.png') }",
+        )
+        .unwrap();
+        for file in ["vent.csv", "drafts.csv", "image.png", "bg.png"] {
+            fs::write(directory.join(file), "").unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn only_linked_files_are_served() {
+        let directory = served_directory();
+        let files = linked_files(&Cache::default(), &directory, "vent.html");
+        let mut names = files
+            .iter()
+            .map(|path| path.strip_prefix(&directory).unwrap().to_str().unwrap())
+            .collect::<Vec<&str>>();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "bg.png",
+                "css/index.css",
+                "image.png",
+                "vent-2.html",
+                "vent.html"
+            ]
+        );
+    }
+
+    #[test]
+    fn links_are_resolved_in_the_directory() {
+        assert_eq!(resolve_link("css", "../bg.png").as_deref(), Some("/bg.png"));
+        assert_eq!(
+            resolve_link("css", "./a/b.png").as_deref(),
+            Some("/css/a/b.png")
+        );
+        assert_eq!(resolve_link("css", "/b.png").as_deref(), Some("/b.png"));
+        assert_eq!(resolve_link("", "../vent.csv"), None);
+    }
+
+    #[test]
+    fn loopback_hosts_need_the_port() {
+        assert!(is_loopback_host("127.0.0.1:8000", 8000));
+        assert!(is_loopback_host("LOCALHOST:8000", 8000));
+        assert!(!is_loopback_host("localhost", 8000));
+        assert!(!is_loopback_host("127.0.0.1:8001", 8000));
+        assert!(!is_loopback_host("attacker.example:8000", 8000));
+    }
+
+    fn request(server: Server, request: &str) -> String {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let handle = thread::spawn(move || handle_connection(stream, &server));
+        client.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        response
+    }
+
+    fn server(directory: PathBuf) -> Server {
+        Server {
+            directory,
+            index: String::from("vent.html"),
+            port: 8000,
+            reloads: Reloads::default(),
+            max_connections: 4,
+            connections: AtomicUsize::new(0),
+        }
+    }
+
+    #[test]
+    fn requests_are_checked() {
+        let directory = served_directory();
+        let get = |path: &str, host: &str| {
+            let response = request(
+                server(directory.clone()),
+                &format!("GET {path} HTTP/1.1\r\nHost: {host}\r\n\r\n"),
+            );
+            response.lines().next().unwrap_or_default().to_owned()
+        };
+        assert_eq!(get("/", "127.0.0.1:8000"), "HTTP/1.1 200 OK");
+        assert_eq!(get("/vent-2.html", "localhost:8000"), "HTTP/1.1 200 OK");
+        assert_eq!(get("/vent.csv", "127.0.0.1:8000"), "HTTP/1.1 404 Not Found");
+        assert_eq!(
+            get("/drafts.csv", "127.0.0.1:8000"),
+            "HTTP/1.1 404 Not Found"
+        );
+        assert_eq!(get("/", "attacker.example:8000"), "HTTP/1.1 403 Forbidden");
+        let response = request(server(directory), "GET / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden"));
+    }
+
+    fn status(response: &str) -> &str {
+        response.lines().next().unwrap_or_default()
+    }
+
+    #[test]
+    fn reload_streams_count_against_the_connections() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut server = server(served_directory());
+        server.port = address.port();
+        server.max_connections = 1;
+        let server = Arc::new(server);
+        let accepting = server.clone();
+        thread::spawn(move || accept(listener, accepting));
+        let host = format!("127.0.0.1:{}", address.port());
+
+        let mut reload = TcpStream::connect(address).unwrap();
+        write!(reload, "GET {RELOAD_PATH} HTTP/1.1\r\nHost: {host}\r\n\r\n").unwrap();
+        let mut header = [0; 15];
+        reload.read_exact(&mut header).unwrap();
+        assert_eq!(&header, b"HTTP/1.1 200 OK");
+        assert_eq!(server.connections.load(Ordering::SeqCst), 1);
+
+        // Refused connections are answered without reading their request
+        let mut busy = TcpStream::connect(address).unwrap();
+        let mut response = String::new();
+        busy.read_to_string(&mut response).unwrap();
+        assert_eq!(status(&response), "HTTP/1.1 503 Service Unavailable");
+
+        // The stream ends with its channel, which frees its connection
+        server.reloads.senders.lock().unwrap().clear();
+        let mut rest = Vec::new();
+        reload.read_to_end(&mut rest).unwrap();
+        while server.connections.load(Ordering::SeqCst) > 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mut client = TcpStream::connect(address).unwrap();
+        write!(client, "GET / HTTP/1.1\r\nHost: {host}\r\n\r\n").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert_eq!(status(&response), "HTTP/1.1 200 OK");
+    }
+
+    #[test]
+    fn connections_are_released_when_they_end() {
+        let server = Arc::new(server(served_directory()));
+        let connections = (0..4)
+            .map(|_| Connection::open(&server))
+            .collect::<Vec<Option<Connection>>>();
+        assert!(connections.iter().all(Option::is_some));
+        assert!(Connection::open(&server).is_none());
+        drop(connections);
+        assert_eq!(server.connections.load(Ordering::SeqCst), 0);
+        assert!(Connection::open(&server).is_some());
+    }
+
+    #[test]
+    fn polls_render_and_reload_changes() {
+        let csv = temp_dir("poll").join("vent.csv");
+        fs::write(&csv, "").unwrap();
+        let paths = [csv.clone(), csv.with_file_name("missing.hbs")];
+        let mut times = modification_times(&paths);
+        let reloads = Reloads::default();
+        let receiver = reloads.subscribe();
+        let mut renders = 0;
+        let mut render = || {
+            renders += 1;
+            Ok(())
+        };
+        assert!(!poll(&paths, &mut times, &mut render, &reloads));
+        assert!(receiver.try_recv().is_err());
+
+        let touched = SystemTime::now() + Duration::from_secs(60);
+        File::options()
+            .write(true)
+            .open(&csv)
+            .unwrap()
+            .set_modified(touched)
+            .unwrap();
+        assert!(poll(&paths, &mut times, &mut render, &reloads));
+        assert_eq!(receiver.try_recv(), Ok(()));
+        assert!(!poll(&paths, &mut times, &mut render, &reloads));
+        assert!(receiver.try_recv().is_err());
+
+        // Failed renders keep the pages as they are
+        File::options()
+            .write(true)
+            .open(&csv)
+            .unwrap()
+            .set_modified(touched + Duration::from_secs(60))
+            .unwrap();
+        let failing = || Err(IoError::new(ErrorKind::InvalidData, "Broken template"));
+        assert!(poll(&paths, &mut times, failing, &reloads));
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(renders, 1);
+    }
+
+    #[test]
+    fn cached_files_are_read_again_once_stale_or_rendered() {
+        let path = temp_dir("cache").join("vent.html");
+        fs::write(&path, "first").unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let touch = |time| {
+            let file = File::options().write(true).open(&path).unwrap();
+            file.set_modified(time).unwrap();
+        };
+        let reads = || DISK_READS.with(|reads| reads.replace(0));
+        let reloads = Reloads::default();
+        let read = || String::from_utf8(reloads.cache.read(&path).unwrap().to_vec()).unwrap();
+        reads();
+        assert_eq!(read(), "first");
+        fs::write(&path, "second").unwrap();
+        touch(modified);
+        assert_eq!(read(), "first");
+        assert_eq!(reads(), 1);
+
+        // Past `CACHE_TTL` the file is only read again if its modification time changed
+        let expire = || {
+            let mut files = reloads.cache.files.lock().unwrap();
+            let cached = files.get_mut(&path).unwrap();
+            cached.checked_at -= CACHE_TTL;
+        };
+        expire();
+        assert_eq!(read(), "first");
+        assert_eq!(reads(), 0);
+        touch(modified + Duration::from_secs(60));
+        expire();
+        assert_eq!(read(), "second");
+        assert_eq!(reads(), 1);
+
+        fs::write(&path, "third").unwrap();
+        assert_eq!(read(), "second");
+        reloads.notify();
+        assert_eq!(read(), "third");
+        assert_eq!(reads(), 1);
+    }
+}
@@ -0,0 +1,547 @@
+use std::io::{self, Error as IoError, ErrorKind};
+
+/* Fresh SQLite databases are written directly in the file format described at
+ * https://www.sqlite.org/fileformat.html, so that exporting doesn't need SQLite itself. Only
+ * what exports need is written: tables are b-trees filled once, in rowid order, there are no
+ * indexes and no free pages.
+ */
+const PAGE_SIZE: usize = 4096;
+const FILE_HEADER_SIZE: usize = 100;
+
+const TABLE_LEAF: u8 = 0x0d;
+const TABLE_INTERIOR: u8 = 0x05;
+const LEAF_HEADER_SIZE: usize = 8;
+const INTERIOR_HEADER_SIZE: usize = 12;
+
+/* Interior cells are a page number and a key, which takes at most 9 bytes. */
+const INTERIOR_CHILDREN: usize = (PAGE_SIZE - INTERIOR_HEADER_SIZE) / (4 + 9 + 2) + 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Text(String),
+}
+
+/* The first page holds the schema, which is only known once every table is written, so `pages`
+ * starts at the second one.
+ */
+#[derive(Default)]
+pub struct Database {
+    pages: Vec<Vec<u8>>,
+    schema: Vec<Vec<Value>>,
+}
+
+/* Integers are stored big-endian, with 7 bits per byte and the high bit set on every byte but
+ * the last. The ninth byte, only needed for the highest bits, keeps all of its 8 bits.
+ */
+fn varint(value: u64) -> Vec<u8> {
+    if value > 0x00ff_ffff_ffff_ffff {
+        let mut bytes = vec![0; 9];
+        bytes[8] = value as u8;
+        let mut rest = value >> 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (rest & 0x7f) as u8 | 0x80;
+            rest >>= 7;
+        }
+        return bytes;
+    }
+    let mut bytes = vec![(value & 0x7f) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        bytes.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+/* A record is a header giving the serial type of every column, then their contents. 0 and 1
+ * have their own serial types, other integers take the fewest bytes they fit in.
+ */
+fn record(values: &[Value]) -> Vec<u8> {
+    let mut types = Vec::new();
+    let mut body = Vec::<u8>::new();
+    for value in values.iter() {
+        match value {
+            Value::Null => types.extend(varint(0)),
+            Value::Integer(0) => types.extend(varint(8)),
+            Value::Integer(1) => types.extend(varint(9)),
+            &Value::Integer(i) => {
+                let (serial_type, size) = [(1, 1), (2, 2), (3, 3), (4, 4), (5, 6)]
+                    .into_iter()
+                    .find(|&(_, size)| {
+                        let bits = 8 * size - 1;
+                        (-(1 << bits)..(1 << bits)).contains(&i)
+                    })
+                    .unwrap_or((6, 8));
+                types.extend(varint(serial_type));
+                body.extend(&i.to_be_bytes()[8 - size..]);
+            }
+            Value::Text(text) => {
+                types.extend(varint(13 + 2 * text.len() as u64));
+                body.extend(text.as_bytes());
+            }
+        }
+    }
+    // The size of the header includes its own varint
+    let mut header_size = types.len() + 1;
+    while varint(header_size as u64).len() + types.len() != header_size {
+        header_size = varint(header_size as u64).len() + types.len();
+    }
+    let mut record = varint(header_size as u64);
+    record.extend(types);
+    record.extend(body);
+    record
+}
+
+/* Part of a payload too large for a leaf page stays in the page and the rest goes to overflow
+ * pages, the split is the one SQLite computes so that it reads the cell back.
+ */
+fn local_payload(size: usize) -> usize {
+    let max_local = PAGE_SIZE - 35;
+    if size <= max_local {
+        return size;
+    }
+    let min_local = (PAGE_SIZE - 12) * 32 / 255 - 23;
+    let local = min_local + (size - min_local) % (PAGE_SIZE - 4);
+    match local <= max_local {
+        true => local,
+        false => min_local,
+    }
+}
+
+/* Cells are written from the end of the page towards its header, in key order. The b-tree
+ * header of the first page comes after the file header.
+ */
+fn page(kind: u8, offset: usize, cells: &[Vec<u8>], right_child: Option<u32>) -> Vec<u8> {
+    let mut page = vec![0; PAGE_SIZE];
+    let header_size = match right_child {
+        Some(_) => INTERIOR_HEADER_SIZE,
+        None => LEAF_HEADER_SIZE,
+    };
+    let mut content = PAGE_SIZE;
+    for (i, cell) in cells.iter().enumerate() {
+        content -= cell.len();
+        page[content..content + cell.len()].copy_from_slice(cell);
+        let pointer = offset + header_size + 2 * i;
+        page[pointer..pointer + 2].copy_from_slice(&(content as u16).to_be_bytes());
+    }
+    page[offset] = kind;
+    page[offset + 3..offset + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    page[offset + 5..offset + 7].copy_from_slice(&(content as u16).to_be_bytes());
+    if let Some(right_child) = right_child {
+        page[offset + 8..offset + 12].copy_from_slice(&right_child.to_be_bytes());
+    }
+    page
+}
+
+impl Database {
+    fn add_page(&mut self, page: Vec<u8>) -> u32 {
+        self.pages.push(page);
+        self.pages.len() as u32 + 1
+    }
+
+    fn overflow(&mut self, payload: &[u8]) -> u32 {
+        let first = self.pages.len() as u32 + 2;
+        let chunks = payload.chunks(PAGE_SIZE - 4).collect::<Vec<&[u8]>>();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let next = match i + 1 < chunks.len() {
+                true => first + i as u32 + 1,
+                false => 0,
+            };
+            let mut page = next.to_be_bytes().to_vec();
+            page.extend(*chunk);
+            page.resize(PAGE_SIZE, 0);
+            self.add_page(page);
+        }
+        first
+    }
+
+    fn leaf_cell(&mut self, rowid: i64, values: &[Value]) -> Vec<u8> {
+        let payload = record(values);
+        let local = local_payload(payload.len());
+        let mut cell = varint(payload.len() as u64);
+        cell.extend(varint(rowid as u64));
+        cell.extend(&payload[..local]);
+        if local < payload.len() {
+            let overflow = self.overflow(&payload[local..]);
+            cell.extend(overflow.to_be_bytes());
+        }
+        cell
+    }
+
+    /* Leaves are filled one after the other, then interior pages are added level by level over
+     * them, each child of an interior page being keyed by the largest rowid it holds. Returns the
+     * root page.
+     */
+    fn write_tree(&mut self, rows: &[(i64, Vec<Value>)]) -> u32 {
+        let mut children = Vec::<(u32, i64)>::new();
+        let mut cells = Vec::new();
+        let (mut used, mut last_rowid) = (0, 0);
+        for (rowid, values) in rows.iter() {
+            let cell = self.leaf_cell(*rowid, values);
+            if !cells.is_empty() && used + cell.len() + 2 > PAGE_SIZE - LEAF_HEADER_SIZE {
+                let number = self.add_page(page(TABLE_LEAF, 0, &cells, None));
+                children.push((number, last_rowid));
+                cells.clear();
+                used = 0;
+            }
+            used += cell.len() + 2;
+            cells.push(cell);
+            last_rowid = *rowid;
+        }
+        children.push((self.add_page(page(TABLE_LEAF, 0, &cells, None)), last_rowid));
+
+        // Children are spread evenly so that no interior page is left with a single one
+        while children.len() > 1 {
+            let count = children.len().div_ceil(INTERIOR_CHILDREN);
+            let (size, larger) = (children.len() / count, children.len() % count);
+            let mut parents = Vec::new();
+            let mut rest = &children[..];
+            for i in 0..count {
+                let (group, after) = rest.split_at(size + usize::from(i < larger));
+                rest = after;
+                let (&(right_child, key), others) = group.split_last().unwrap();
+                let cells = others
+                    .iter()
+                    .map(|&(child, key)| {
+                        let mut cell = child.to_be_bytes().to_vec();
+                        cell.extend(varint(key as u64));
+                        cell
+                    })
+                    .collect::<Vec<Vec<u8>>>();
+                let number = self.add_page(page(TABLE_INTERIOR, 0, &cells, Some(right_child)));
+                parents.push((number, key));
+            }
+            children = parents;
+        }
+        children[0].0
+    }
+
+    /* Rows are given with their rowid, in increasing order, with `Null` for the `INTEGER PRIMARY
+     * KEY` column which SQLite reads from the rowid.
+     */
+    pub fn create_table(
+        &mut self,
+        name: &str,
+        sql: &str,
+        rows: &[(i64, Vec<Value>)],
+    ) -> Result<(), IoError> {
+        if rows.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                format!("Rows of table {name} aren't in increasing rowid order"),
+            ));
+        }
+        let root = self.write_tree(rows);
+        self.schema.push(vec![
+            Value::Text(String::from("table")),
+            Value::Text(name.to_owned()),
+            Value::Text(name.to_owned()),
+            Value::Integer(root as i64),
+            Value::Text(sql.to_owned()),
+        ]);
+        Ok(())
+    }
+
+    pub fn write<W>(mut self, mut writer: W) -> Result<(), IoError>
+    where
+        W: io::Write,
+    {
+        let schema = std::mem::take(&mut self.schema);
+        let cells = schema
+            .iter()
+            .enumerate()
+            .map(|(i, values)| self.leaf_cell(i as i64 + 1, values))
+            .collect::<Vec<Vec<u8>>>();
+        let used = cells.iter().map(|cell| cell.len() + 2).sum::<usize>();
+        if used > PAGE_SIZE - FILE_HEADER_SIZE - LEAF_HEADER_SIZE {
+            return Err(IoError::new(ErrorKind::InvalidInput, "Schema too large"));
+        }
+        let mut first_page = page(TABLE_LEAF, FILE_HEADER_SIZE, &cells, None);
+
+        let header = &mut first_page[..FILE_HEADER_SIZE];
+        header[..16].copy_from_slice(b"SQLite format 3\0");
+        header[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+        // Legacy journal, no reserved bytes and the fixed payload fractions
+        header[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+        header[24..28].copy_from_slice(&1u32.to_be_bytes());
+        header[28..32].copy_from_slice(&(self.pages.len() as u32 + 1).to_be_bytes());
+        // Schema cookie and format, UTF-8 text
+        header[40..44].copy_from_slice(&1u32.to_be_bytes());
+        header[44..48].copy_from_slice(&4u32.to_be_bytes());
+        header[56..60].copy_from_slice(&1u32.to_be_bytes());
+        header[92..96].copy_from_slice(&1u32.to_be_bytes());
+        header[96..100].copy_from_slice(&3_045_000u32.to_be_bytes());
+
+        writer.write_all(&first_page)?;
+        for page in self.pages.iter() {
+            writer.write_all(page)?;
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /* A reader for what `Database` writes, walking the b-trees like SQLite does. */
+    pub(crate) struct Reader<'a> {
+        pub(crate) file: &'a [u8],
+    }
+
+    impl Reader<'_> {
+        fn page(&self, number: u32) -> &[u8] {
+            let start = (number as usize - 1) * PAGE_SIZE;
+            &self.file[start..start + PAGE_SIZE]
+        }
+
+        fn read_varint(bytes: &[u8]) -> (u64, usize) {
+            let mut value = 0;
+            for (i, &byte) in bytes.iter().enumerate().take(9) {
+                if i == 8 {
+                    return ((value << 8) | byte as u64, 9);
+                }
+                value = (value << 7) | (byte & 0x7f) as u64;
+                if byte & 0x80 == 0 {
+                    return (value, i + 1);
+                }
+            }
+            unreachable!()
+        }
+
+        /* SQLite's own split of a table leaf payload of `size` bytes, written from its file
+         * format documentation rather than shared with the writer so that a wrong split is caught.
+         */
+        fn local_size(size: usize) -> usize {
+            let usable = PAGE_SIZE;
+            let max_local = usable - 35;
+            let min_local = ((usable - 12) * 32 / 255) - 23;
+            if size <= max_local {
+                return size;
+            }
+            let local = min_local + (size - min_local) % (usable - 4);
+            if local <= max_local {
+                local
+            } else {
+                min_local
+            }
+        }
+
+        fn payload(&self, cell: &[u8], size: usize) -> Vec<u8> {
+            let local = Self::local_size(size);
+            let mut payload = cell[..local].to_vec();
+            let mut next = match local < size {
+                true => u32::from_be_bytes(cell[local..local + 4].try_into().unwrap()),
+                false => 0,
+            };
+            while next != 0 {
+                let page = self.page(next);
+                let take = (size - payload.len()).min(PAGE_SIZE - 4);
+                payload.extend(&page[4..4 + take]);
+                next = u32::from_be_bytes(page[..4].try_into().unwrap());
+            }
+            payload
+        }
+
+        fn values(record: &[u8]) -> Vec<Value> {
+            let (header_size, mut position) = Self::read_varint(record);
+            let mut body = header_size as usize;
+            let mut values = Vec::new();
+            while position < header_size as usize {
+                let (serial_type, length) = Self::read_varint(&record[position..]);
+                position += length;
+                let (value, size) = match serial_type {
+                    0 => (Value::Null, 0),
+                    8 => (Value::Integer(0), 0),
+                    9 => (Value::Integer(1), 0),
+                    1..=6 => {
+                        let size = [1, 2, 3, 4, 6, 8][serial_type as usize - 1];
+                        let bytes = &record[body..body + size];
+                        let mut value = (bytes[0] as i8) as i64;
+                        for &byte in bytes[1..].iter() {
+                            value = (value << 8) | byte as i64;
+                        }
+                        (Value::Integer(value), size)
+                    }
+                    t if t >= 13 && t % 2 == 1 => {
+                        let size = (t as usize - 13) / 2;
+                        let text = String::from_utf8(record[body..body + size].to_vec());
+                        (Value::Text(text.unwrap()), size)
+                    }
+                    t => panic!("Unexpected serial type {t}"),
+                };
+                values.push(value);
+                body += size;
+            }
+            assert_eq!(body, record.len());
+            values
+        }
+
+        fn rows(&self, number: u32, rows: &mut Vec<(i64, Vec<Value>)>, max: Option<i64>) {
+            let page = self.page(number);
+            let offset = if number == 1 { FILE_HEADER_SIZE } else { 0 };
+            let header = &page[offset..];
+            let count = u16::from_be_bytes([header[3], header[4]]) as usize;
+            let header_size = match header[0] {
+                TABLE_LEAF => LEAF_HEADER_SIZE,
+                TABLE_INTERIOR => INTERIOR_HEADER_SIZE,
+                kind => panic!("Unexpected page type {kind}"),
+            };
+            for i in 0..count {
+                let pointer = offset + header_size + 2 * i;
+                let cell = &page[u16::from_be_bytes([page[pointer], page[pointer + 1]]) as usize..];
+                if header[0] == TABLE_INTERIOR {
+                    let child = u32::from_be_bytes(cell[..4].try_into().unwrap());
+                    let (key, _) = Self::read_varint(&cell[4..]);
+                    self.rows(child, rows, Some(key as i64));
+                    continue;
+                }
+                let (size, first) = Self::read_varint(cell);
+                let (rowid, second) = Self::read_varint(&cell[first..]);
+                let payload = self.payload(&cell[first + second..], size as usize);
+                if let Some(max) = max {
+                    assert!(rowid as i64 <= max);
+                }
+                rows.push((rowid as i64, Self::values(&payload)));
+            }
+            if header[0] == TABLE_INTERIOR {
+                let right_child = u32::from_be_bytes(header[8..12].try_into().unwrap());
+                self.rows(right_child, rows, max);
+            }
+        }
+
+        /* Like `SELECT COUNT(*)`, the cells of the leaves are counted without reading them. */
+        fn cells(&self, number: u32) -> usize {
+            let page = self.page(number);
+            let offset = if number == 1 { FILE_HEADER_SIZE } else { 0 };
+            let header = &page[offset..];
+            let count = u16::from_be_bytes([header[3], header[4]]) as usize;
+            if header[0] == TABLE_LEAF {
+                return count;
+            }
+            let right_child = u32::from_be_bytes(header[8..12].try_into().unwrap());
+            let children = (0..count).map(|i| {
+                let pointer = offset + INTERIOR_HEADER_SIZE + 2 * i;
+                let cell = u16::from_be_bytes([page[pointer], page[pointer + 1]]) as usize;
+                u32::from_be_bytes(page[cell..cell + 4].try_into().unwrap())
+            });
+            children
+                .chain([right_child])
+                .map(|child| self.cells(child))
+                .sum()
+        }
+
+        pub(crate) fn count(&self, name: &str) -> usize {
+            self.cells(self.root(name))
+        }
+
+        fn root(&self, name: &str) -> u32 {
+            let mut schema = Vec::new();
+            self.rows(1, &mut schema, None);
+            schema
+                .iter()
+                .find(|(_, values)| values[1] == Value::Text(name.to_owned()))
+                .map(|(_, values)| match values[3] {
+                    Value::Integer(root) => root as u32,
+                    _ => panic!("Invalid root page"),
+                })
+                .unwrap()
+        }
+
+        pub(crate) fn table(&self, name: &str) -> Vec<(i64, Vec<Value>)> {
+            let mut rows = Vec::new();
+            self.rows(self.root(name), &mut rows, None);
+            rows
+        }
+    }
+
+    fn text(text: &str) -> Value {
+        Value::Text(text.to_owned())
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 127, 128, 16383, 16384, 1 << 56, u64::MAX] {
+            let bytes = varint(value);
+            assert_eq!(Reader::read_varint(&bytes), (value, bytes.len()));
+        }
+        assert_eq!(varint(300), [0x82, 0x2c]);
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let values = vec![
+            Value::Null,
+            Value::Integer(0),
+            Value::Integer(1),
+            Value::Integer(-2),
+            Value::Integer(300),
+            Value::Integer(-70000),
+            Value::Integer(1 << 40),
+            Value::Integer(i64::MIN),
+            text(""),
+            text("héllo"),
+        ];
+        assert_eq!(Reader::values(&record(&values)), values);
+    }
+
+    #[test]
+    fn tables_round_trip() {
+        let rows = (0..3000)
+            .map(|i| {
+                let message = match i {
+                    10 => "long ".repeat(2000),
+                    11 => "x".repeat(PAGE_SIZE - 40),
+                    _ => format!("message {i}"),
+                };
+                (i * 2, vec![Value::Null, text(&message), Value::Integer(i)])
+            })
+            .collect::<Vec<_>>();
+        let mut database = Database::default();
+        database
+            .create_table("empty", "CREATE TABLE empty (a)", &[])
+            .unwrap();
+        database
+            .create_table("t", "CREATE TABLE t (id INTEGER PRIMARY KEY, m, n)", &rows)
+            .unwrap();
+        let mut file = Vec::new();
+        database.write(&mut file).unwrap();
+
+        assert_eq!(&file[..16], b"SQLite format 3\0");
+        assert_eq!(file.len() % PAGE_SIZE, 0);
+        let pages = u32::from_be_bytes(file[28..32].try_into().unwrap()) as usize;
+        assert_eq!(pages * PAGE_SIZE, file.len());
+        let reader = Reader { file: &file };
+        assert_eq!(reader.table("t"), rows);
+        assert_eq!(reader.count("t"), 3000);
+        assert!(reader.table("empty").is_empty());
+        assert_eq!(reader.count("empty"), 0);
+    }
+
+    /* The two overflowing rows of `tables_round_trip`, one keeping what fits past the minimum
+     * and one only keeping the minimum.
+     */
+    #[test]
+    fn overflow_splits_follow_sqlite() {
+        let size =
+            |message: &str, n| record(&[Value::Null, text(message), Value::Integer(n)]).len();
+        let long = size(&"long ".repeat(2000), 10);
+        let page = size(&"x".repeat(PAGE_SIZE - 40), 11);
+        assert_eq!((long, page), (10007, 4062));
+        assert_eq!(Reader::local_size(long), 1823);
+        assert_eq!(Reader::local_size(page), 489);
+        assert_eq!(Reader::local_size(4061), 4061);
+    }
+
+    #[test]
+    fn rows_must_be_ordered() {
+        let rows = [(2, vec![]), (1, vec![])];
+        let mut database = Database::default();
+        assert!(database
+            .create_table("t", "CREATE TABLE t (a)", &rows)
+            .is_err());
+    }
+}
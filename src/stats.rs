@@ -0,0 +1,357 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{self, Error as IoError};
+
+use chrono::prelude::*;
+use chrono::Duration;
+
+use crate::{display_message_id, parse_date, Entry};
+
+const HISTOGRAM_BUCKET_WIDTH: usize = 20;
+const HISTOGRAM_BAR_WIDTH: usize = 40;
+
+/* Every pair of messages is compared, so only the latest ones are compared in large databases. */
+const MAX_DUPLICATE_CANDIDATES: usize = 2000;
+
+pub const DEFAULT_STOPWORDS: [&str; 48] = [
+    "a", "about", "after", "all", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from",
+    "had", "has", "have", "i", "i'm", "if", "in", "is", "it", "it's", "me", "my", "no", "not",
+    "of", "on", "or", "so", "than", "that", "the", "then", "there", "this", "to", "too", "was",
+    "we", "what", "when", "with", "you", "your", "just",
+];
+
+/* Removed messages are left out, they would all end up in the bucket of the removed text. Bars are
+ * scaled so the largest bucket is `HISTOGRAM_BAR_WIDTH` wide.
+ */
+pub fn stats<W>(mut writer: W, entries: &[Entry], removed_text: &str) -> Result<(), IoError>
+where
+    W: io::Write,
+{
+    let lengths = entries
+        .iter()
+        .filter(|e| e.message != removed_text)
+        .map(|e| e.message.chars().count())
+        .collect::<Vec<usize>>();
+    writeln!(writer, "messages: {}", lengths.len())?;
+    if lengths.is_empty() {
+        return Ok(());
+    }
+    let average = lengths.iter().sum::<usize>() as f64 / lengths.len() as f64;
+    writeln!(writer, "average length: {average:.1}")?;
+
+    let longest = lengths.iter().max().copied().unwrap_or_default();
+    let mut buckets = vec![0; longest / HISTOGRAM_BUCKET_WIDTH + 1];
+    for length in lengths.iter() {
+        buckets[length / HISTOGRAM_BUCKET_WIDTH] += 1;
+    }
+    let largest = buckets.iter().max().copied().unwrap_or_default();
+    writeln!(writer, "length histogram:")?;
+    for (i, &count) in buckets.iter().enumerate() {
+        let range = format!(
+            "{}-{}",
+            i * HISTOGRAM_BUCKET_WIDTH,
+            (i + 1) * HISTOGRAM_BUCKET_WIDTH - 1
+        );
+        let bar = "#".repeat((count * HISTOGRAM_BAR_WIDTH).div_ceil(largest));
+        writeln!(writer, "  {range:>9} {bar} {count}")?;
+    }
+    Ok(())
+}
+
+/* Words are split on anything but letters, digits and inner apostrophes, tags are left out as
+ * `vent tags` already counts them. Words without any letter, e.g. numbers, are ignored.
+ */
+pub fn words<W>(
+    mut writer: W,
+    entries: &[Entry],
+    removed_text: &str,
+    stopwords: &HashSet<String>,
+    top: usize,
+) -> Result<(), IoError>
+where
+    W: io::Write,
+{
+    let mut counts = HashMap::<String, usize>::new();
+    for entry in entries.iter().filter(|e| e.message != removed_text) {
+        let words = entry
+            .message
+            .split_whitespace()
+            .filter(|word| !word.starts_with('#'))
+            .flat_map(|word| word.split(|c: char| !c.is_alphanumeric() && c != '\''))
+            .map(|word| word.trim_matches('\'').to_lowercase());
+        for word in words {
+            if word.chars().any(char::is_alphabetic) && !stopwords.contains(&word) {
+                *counts.entry(word).or_default() += 1;
+            }
+        }
+    }
+    let mut counts = counts.into_iter().collect::<Vec<(String, usize)>>();
+    counts.sort_by(|(a_word, a_count), (b_word, b_count)| {
+        b_count.cmp(a_count).then(a_word.cmp(b_word))
+    });
+    for (word, count) in counts.into_iter().take(top) {
+        writeln!(writer, "{count} {word}")?;
+    }
+    Ok(())
+}
+
+/* Messages count for the day they were written in the local timezone, shifted by `day_start`
+ * hours. The current streak is still running when the last message is from the day before, a
+ * new message today keeps it going.
+ */
+pub fn streaks<W>(
+    mut writer: W,
+    entries: &[Entry],
+    removed_text: &str,
+    day_start: u32,
+    now: DateTime<FixedOffset>,
+) -> Result<(), IoError>
+where
+    W: io::Write,
+{
+    let day = |date: DateTime<FixedOffset>| {
+        (date.with_timezone(&Local) - Duration::hours(day_start.into())).date_naive()
+    };
+    let days = entries
+        .iter()
+        .filter(|e| e.message != removed_text)
+        .filter_map(|e| parse_date(&e.date))
+        .map(day)
+        .collect::<BTreeSet<NaiveDate>>();
+
+    let mut longest = 0;
+    let mut run = 0;
+    let mut previous: Option<NaiveDate> = None;
+    for &date in days.iter() {
+        run = match previous {
+            Some(previous) if previous.succ_opt() == Some(date) => run + 1,
+            _ => 1,
+        };
+        longest = longest.max(run);
+        previous = Some(date);
+    }
+    let today = day(now);
+    let current = match previous {
+        Some(last) if last == today || last.succ_opt() == Some(today) => run,
+        _ => 0,
+    };
+    writeln!(writer, "days with messages: {}", days.len())?;
+    writeln!(writer, "longest streak in days: {longest}")?;
+    writeln!(writer, "current streak in days: {current}")
+}
+
+/* The Levenshtein distance counts the characters to insert, delete or substitute to turn one text
+ * into the other. Only distances up to `max` are computed, cells further than `max` from the
+ * diagonal can't lead to them and the computation stops once a whole row is beyond it.
+ */
+fn levenshtein(a: &[char], b: &[char], max: usize) -> Option<usize> {
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    let beyond = max + 1;
+    let mut previous = (0..=b.len()).map(|j| j.min(beyond)).collect::<Vec<usize>>();
+    let mut current = vec![beyond; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        let start = (i + 1).saturating_sub(max);
+        let end = (i + 1 + max).min(b.len());
+        current.fill(beyond);
+        if start == 0 {
+            current[0] = (i + 1).min(beyond);
+        }
+        for j in start.max(1)..=end {
+            let substitution = previous[j - 1] + usize::from(*ca != b[j - 1]);
+            current[j] = substitution
+                .min(previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(beyond);
+        }
+        if current[start..=end].iter().all(|&d| d > max) {
+            return None;
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    Some(previous[b.len()]).filter(|&d| d <= max)
+}
+
+/* Messages are compared case-insensitively with runs of whitespace collapsed, their similarity is
+ * one minus their Levenshtein distance over the length of the longest one. Pairs are printed from
+ * the most similar.
+ */
+pub fn duplicates<W>(
+    mut writer: W,
+    entries: &[Entry],
+    removed_text: &str,
+    threshold: f64,
+) -> Result<(), IoError>
+where
+    W: io::Write,
+{
+    let mut candidates = entries
+        .iter()
+        .filter(|e| e.message != removed_text)
+        .map(|e| {
+            let text = e
+                .message
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ");
+            (e.id, text.to_lowercase().chars().collect::<Vec<char>>())
+        })
+        .collect::<Vec<(usize, Vec<char>)>>();
+    if candidates.len() > MAX_DUPLICATE_CANDIDATES {
+        eprintln!(
+            "Only the latest {MAX_DUPLICATE_CANDIDATES} of {} messages are compared, use filters to compare others",
+            candidates.len()
+        );
+        candidates.drain(..candidates.len() - MAX_DUPLICATE_CANDIDATES);
+    }
+
+    let mut pairs = Vec::new();
+    for (i, (a_id, a)) in candidates.iter().enumerate() {
+        for (b_id, b) in candidates[i + 1..].iter() {
+            let longest = a.len().max(b.len());
+            if longest == 0 {
+                continue;
+            }
+            let max = ((1.0 - threshold) * longest as f64 + 1e-9).floor() as usize;
+            if let Some(distance) = levenshtein(a, b, max) {
+                pairs.push((*a_id, *b_id, 1.0 - distance as f64 / longest as f64));
+            }
+        }
+    }
+    pairs.sort_by(|(a_first, a_second, a), (b_first, b_second, b)| {
+        b.total_cmp(a)
+            .then(a_first.cmp(b_first))
+            .then(a_second.cmp(b_second))
+    });
+    for (first, second, similarity) in pairs {
+        writeln!(
+            writer,
+            "{} {} {similarity:.2}",
+            display_message_id(first),
+            display_message_id(second)
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{parse_lines, with_env};
+
+    fn entries(lines: &[&str]) -> Vec<Entry> {
+        with_env(&[], || parse_lines(lines))
+    }
+
+    #[test]
+    fn histograms_bucket_message_lengths() {
+        let long = format!("2024-01-03 10:00:00 +0000,{}", "x".repeat(45));
+        let entries = entries(&[
+            "2024-01-01 10:00:00 +0000,short",
+            "2024-01-02 10:00:00 +0000,also short",
+            &long,
+            "2024-01-04 10:00:00 +0000,[removed]",
+        ]);
+        let mut output = Vec::new();
+        stats(&mut output, &entries, "[removed]").unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            [
+                "messages: 3",
+                "average length: 20.0",
+                "length histogram:",
+                &format!("       0-19 {} 2", "#".repeat(40)),
+                "      20-39  0",
+                &format!("      40-59 {} 1", "#".repeat(20)),
+                "",
+            ]
+            .join("\n")
+        );
+
+        let mut output = Vec::new();
+        stats(&mut output, &[], "[removed]").unwrap();
+        assert_eq!(output, b"messages: 0\n");
+    }
+
+    #[test]
+    fn words_are_counted_without_stopwords_and_tags() {
+        let entries = entries(&[
+            "2024-01-01 10:00:00 +0000,The cat's toy, the CAT! #cat 42",
+            "2024-01-02 10:00:00 +0000,'cat' and dog-toy",
+            "2024-01-03 10:00:00 +0000,[removed]",
+        ]);
+        let stopwords = DEFAULT_STOPWORDS
+            .iter()
+            .map(|word| word.to_string())
+            .collect::<HashSet<String>>();
+        let mut output = Vec::new();
+        words(&mut output, &entries, "[removed]", &stopwords, 3).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "2 cat\n2 toy\n1 cat's\n"
+        );
+    }
+
+    /* Messages are written at noon so that their local day is the same in every timezone. */
+    #[test]
+    fn streaks_count_consecutive_days() {
+        let entries = entries(&[
+            "2024-01-01 12:00:00 +0000,a",
+            "2024-01-02 12:00:00 +0000,b",
+            "2024-01-02 13:00:00 +0000,same day",
+            "2024-01-03 12:00:00 +0000,c",
+            "2024-01-05 12:00:00 +0000,d",
+            "2024-01-06 12:00:00 +0000,e",
+            "2024-01-07 12:00:00 +0000,[removed]",
+        ]);
+        let report = |now: &str| {
+            let mut output = Vec::new();
+            let now = parse_date(now).unwrap();
+            streaks(&mut output, &entries, "[removed]", 0, now).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        let summary = |current| {
+            format!(
+                "days with messages: 5\nlongest streak in days: 3\ncurrent streak in days: {current}\n"
+            )
+        };
+        assert_eq!(report("2024-01-07 12:00:00 +0000"), summary(2));
+        assert_eq!(report("2024-01-06 12:00:00 +0000"), summary(2));
+        assert_eq!(report("2024-01-08 12:00:00 +0000"), summary(0));
+    }
+
+    #[test]
+    fn levenshtein_distances_are_bounded() {
+        let chars = |text: &str| text.chars().collect::<Vec<char>>();
+        let distance = |a: &str, b: &str, max| levenshtein(&chars(a), &chars(b), max);
+        assert_eq!(distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(distance("kitten", "sitting", 2), None);
+        assert_eq!(distance("same", "same", 0), Some(0));
+        assert_eq!(distance("", "abc", 3), Some(3));
+        assert_eq!(distance("a", "abcd", 2), None);
+        assert_eq!(distance("café", "cafe", 1), Some(1));
+        assert_eq!(distance("flaw", "lawn", 5), Some(2));
+    }
+
+    #[test]
+    fn near_duplicates_are_listed_from_the_most_similar() {
+        let entries = entries(&[
+            "2024-01-01 10:00:00 +0000,Hello   world",
+            "2024-01-02 10:00:00 +0000,hello world",
+            "2024-01-03 10:00:00 +0000,hello worlds",
+            "2024-01-04 10:00:00 +0000,something else",
+            "2024-01-05 10:00:00 +0000,[removed]",
+            "2024-01-06 10:00:00 +0000,[removed]",
+        ]);
+        let report = |threshold| {
+            let mut output = Vec::new();
+            with_env(&[], || {
+                duplicates(&mut output, &entries, "[removed]", threshold)
+            })
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(report(0.9), "0 1 1.00\n0 2 0.92\n1 2 0.92\n");
+        assert_eq!(report(1.0), "0 1 1.00\n");
+    }
+}
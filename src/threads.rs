@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use crate::Entry;
+
+/* Replies may point to any message ID, including later or non-existent ones, so following reply
+ * links has to guard against cycles. Children are indices in `entries` which may not hold every
+ * message.
+ */
+pub fn reply_children(entries: &[Entry]) -> Vec<Vec<usize>> {
+    let indices = entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (entry.id, index))
+        .collect::<HashMap<usize, usize>>();
+    let mut children = vec![Vec::new(); entries.len()];
+    for (index, entry) in entries.iter().enumerate() {
+        let parent = entry
+            .reply
+            .filter(|&r| r != entry.id)
+            .and_then(|r| indices.get(&r));
+        if let Some(&parent) = parent {
+            children[parent].push(index);
+        }
+    }
+    children
+}
+
+pub fn reply_ancestors(entries: &[Entry], message_id: usize) -> Vec<usize> {
+    let mut ancestors = Vec::new();
+    let mut visited = vec![false; entries.len()];
+    visited[message_id] = true;
+    let mut current = message_id;
+    while let Some(parent) = entries[current]
+        .reply
+        .filter(|&r| r < entries.len() && !visited[r])
+    {
+        visited[parent] = true;
+        ancestors.push(parent);
+        current = parent;
+    }
+    ancestors.reverse();
+    ancestors
+}
+
+fn has_parent(children: &[Vec<usize>]) -> Vec<bool> {
+    let mut has_parent = vec![false; children.len()];
+    children
+        .iter()
+        .flatten()
+        .for_each(|&child| has_parent[child] = true);
+    has_parent
+}
+
+/* `last` tells, for each level of depth down to the message, if the message or its ancestor at
+ * this level is the last reply to its parent. Roots have an empty `last`.
+ */
+pub struct TreeNode {
+    pub index: usize,
+    pub last: Vec<bool>,
+}
+
+/* Messages are walked depth-first starting from the top-level ones, messages which are only
+ * reachable through a cycle start their own tree.
+ */
+pub fn reply_forest(entries: &[Entry]) -> Vec<TreeNode> {
+    let children = reply_children(entries);
+    let has_parent = has_parent(&children);
+    let roots = (0..entries.len())
+        .filter(|&index| !has_parent[index])
+        .chain(0..entries.len());
+
+    let mut visited = vec![false; entries.len()];
+    let mut nodes = Vec::new();
+    for root in roots {
+        let mut stack = vec![(root, Vec::new())];
+        while let Some((index, last)) = stack.pop() {
+            if visited[index] {
+                continue;
+            }
+            visited[index] = true;
+            let unvisited = children[index]
+                .iter()
+                .filter(|&&child| !visited[child])
+                .collect::<Vec<&usize>>();
+            for (position, &&child) in unvisited.iter().enumerate().rev() {
+                let mut child_last = last.clone();
+                child_last.push(position + 1 == unvisited.len());
+                stack.push((child, child_last));
+            }
+            nodes.push(TreeNode { index, last });
+        }
+    }
+    nodes
+}
+
+/* Threads are the trees of `reply_forest`, the depth of a thread is the number of replies between
+ * its root and its deepest message. A root which has a parent is only reachable through a cycle.
+ */
+pub struct Thread {
+    pub root: usize,
+    pub size: usize,
+    pub depth: usize,
+    pub cycle: bool,
+}
+
+pub fn reply_threads(entries: &[Entry]) -> Vec<Thread> {
+    let has_parent = has_parent(&reply_children(entries));
+    let mut threads = Vec::<Thread>::new();
+    for node in reply_forest(entries) {
+        match threads.last_mut() {
+            Some(thread) if !node.last.is_empty() => {
+                thread.size += 1;
+                thread.depth = thread.depth.max(node.last.len());
+            }
+            _ => threads.push(Thread {
+                root: node.index,
+                size: 1,
+                depth: 0,
+                cycle: has_parent[node.index],
+            }),
+        }
+    }
+    threads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::parse_lines;
+
+    fn forest(lines: &[&str]) -> Vec<(usize, Vec<bool>)> {
+        reply_forest(&parse_lines(lines))
+            .into_iter()
+            .map(|node| (node.index, node.last))
+            .collect()
+    }
+
+    #[test]
+    fn forests_are_walked_depth_first() {
+        let nodes = forest(&[
+            "2024-01-01 10:00:00 +0000,root",
+            "2024-01-02 10:00:00 +0000,>>0 first reply",
+            "2024-01-03 10:00:00 +0000,other root",
+            "2024-01-04 10:00:00 +0000,>>1 nested",
+            "2024-01-05 10:00:00 +0000,>>0 last reply",
+        ]);
+        assert_eq!(
+            nodes,
+            [
+                (0, vec![]),
+                (1, vec![false]),
+                (3, vec![false, true]),
+                (4, vec![true]),
+                (2, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn cycles_start_their_own_tree() {
+        let lines = [
+            "2024-01-01 10:00:00 +0000,>>1 first",
+            "2024-01-02 10:00:00 +0000,>>0 second",
+            "2024-01-03 10:00:00 +0000,>>2 itself",
+            "2024-01-04 10:00:00 +0000,>>9 missing",
+        ];
+        assert_eq!(
+            forest(&lines),
+            [(2, vec![]), (3, vec![]), (0, vec![]), (1, vec![true])]
+        );
+        assert_eq!(reply_ancestors(&parse_lines(&lines), 1), [0]);
+    }
+
+    #[test]
+    fn threads_report_their_size_and_depth() {
+        let threads = reply_threads(&parse_lines(&[
+            "2024-01-01 10:00:00 +0000,root",
+            "2024-01-02 10:00:00 +0000,>>0 reply",
+            "2024-01-03 10:00:00 +0000,>>1 nested",
+            "2024-01-04 10:00:00 +0000,>>0 other reply",
+            "2024-01-05 10:00:00 +0000,alone",
+            "2024-01-06 10:00:00 +0000,>>6 cycle",
+            "2024-01-07 10:00:00 +0000,>>5 cycle",
+        ]))
+        .into_iter()
+        .map(|t| (t.root, t.size, t.depth, t.cycle))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            threads,
+            [(0, 4, 2, false), (4, 1, 0, false), (5, 2, 1, true)]
+        );
+    }
+}
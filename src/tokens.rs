@@ -0,0 +1,239 @@
+use std::io::Error as IoError;
+
+/* Filters and scripts share their tokens and the cursor their parsers read them with, only the
+ * operators, the escapes of strings and the way errors are located differ.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Identifier(String),
+    Int(i64),
+    Str(String),
+    Operator(&'static str),
+}
+
+/* Tokens are located by their 1-based line and column. */
+pub type Location = (usize, usize);
+
+pub struct Syntax {
+    /* Longer operators come first so that `<=` isn't read as `<` followed by `=`. */
+    pub operators: &'static [&'static str],
+    /* `//` starts a comment running to the end of the line. */
+    pub comments: bool,
+    /* `\n` in a string is a new line, otherwise it is kept as is. */
+    pub newlines: bool,
+    pub error: fn(Location, &str) -> IoError,
+}
+
+/* The tokens of `line` are appended to `tokens`, a new line in it is only whitespace. */
+pub fn tokenize_line(
+    syntax: &Syntax,
+    line_number: usize,
+    line: &str,
+    tokens: &mut Vec<(Location, Token)>,
+) -> Result<(), IoError> {
+    let error = syntax.error;
+    let chars = line.chars().collect::<Vec<char>>();
+    let mut i = 0;
+    while i < chars.len() {
+        let location = (line_number, i + 1);
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if syntax.comments && c == '/' && chars.get(i + 1) == Some(&'/') {
+            break;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let number = chars[start..i].iter().collect::<String>();
+            let number = number
+                .parse()
+                .map_err(|_| error(location, "number too large"))?;
+            tokens.push((location, Token::Int(number)));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let name = chars[start..i].iter().collect();
+            tokens.push((location, Token::Identifier(name)));
+        } else if c == '"' {
+            // Quotes and backslashes are escaped with a backslash
+            let mut string = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some('"') => break,
+                    Some('\\') if matches!(chars.get(i + 1), Some('"') | Some('\\')) => {
+                        string.push(chars[i + 1]);
+                        i += 2;
+                    }
+                    Some('\\') if syntax.newlines && chars.get(i + 1) == Some(&'n') => {
+                        string.push('\n');
+                        i += 2;
+                    }
+                    Some(&c) => {
+                        string.push(c);
+                        i += 1;
+                    }
+                    None => return Err(error(location, "unterminated string")),
+                }
+            }
+            i += 1;
+            tokens.push((location, Token::Str(string)));
+        } else {
+            let rest = chars[i..].iter().take(2).collect::<String>();
+            let operator = syntax
+                .operators
+                .iter()
+                .find(|operator| rest.starts_with(*operator))
+                .ok_or_else(|| error(location, &format!("unexpected '{c}'")))?;
+            i += operator.len();
+            tokens.push((location, Token::Operator(operator)));
+        }
+    }
+    Ok(())
+}
+
+/* `end` is where the end of the text is reported, just after its last character. */
+pub struct Cursor {
+    tokens: Vec<(Location, Token)>,
+    position: usize,
+    end: Location,
+    error: fn(Location, &str) -> IoError,
+}
+
+impl Cursor {
+    pub fn new(syntax: &Syntax, tokens: Vec<(Location, Token)>, end: Location) -> Self {
+        Cursor {
+            tokens,
+            position: 0,
+            end,
+            error: syntax.error,
+        }
+    }
+
+    pub fn location(&self) -> Location {
+        self.tokens
+            .get(self.position)
+            .map(|(location, _)| *location)
+            .unwrap_or(self.end)
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    pub fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).map(|(_, t)| t.clone());
+        self.position += 1;
+        token
+    }
+
+    pub fn peek(&self, operator: &str) -> bool {
+        matches!(
+            self.tokens.get(self.position),
+            Some((_, Token::Operator(o))) if *o == operator
+        )
+    }
+
+    pub fn accept(&mut self, operator: &str) -> bool {
+        let found = self.peek(operator);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    pub fn expect(&mut self, operator: &str) -> Result<(), IoError> {
+        match self.accept(operator) {
+            true => Ok(()),
+            false => Err((self.error)(
+                self.location(),
+                &format!("expected '{operator}'"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    fn error((line, column): Location, message: &str) -> IoError {
+        IoError::new(
+            ErrorKind::InvalidInput,
+            format!("{line}:{column}: {message}"),
+        )
+    }
+
+    fn tokens(syntax: &Syntax, line: &str) -> Result<Vec<(Location, Token)>, String> {
+        let mut tokens = Vec::new();
+        tokenize_line(syntax, 2, line, &mut tokens).map_err(|e| e.to_string())?;
+        Ok(tokens)
+    }
+
+    const SYNTAX: Syntax = Syntax {
+        operators: &["<=", "<", "/"],
+        comments: false,
+        newlines: false,
+        error,
+    };
+
+    #[test]
+    fn escapes_and_comments_follow_the_syntax() {
+        let scripts = Syntax {
+            comments: true,
+            newlines: true,
+            ..SYNTAX
+        };
+        assert_eq!(
+            tokens(&SYNTAX, "a <= \"\\\"\\n\" // b").unwrap(),
+            [
+                ((2, 1), Token::Identifier(String::from("a"))),
+                ((2, 3), Token::Operator("<=")),
+                ((2, 6), Token::Str(String::from("\"\\n"))),
+                ((2, 13), Token::Operator("/")),
+                ((2, 14), Token::Operator("/")),
+                ((2, 16), Token::Identifier(String::from("b"))),
+            ]
+        );
+        assert_eq!(
+            tokens(&scripts, "1 <\"\\n\" // b").unwrap(),
+            [
+                ((2, 1), Token::Int(1)),
+                ((2, 3), Token::Operator("<")),
+                ((2, 4), Token::Str(String::from("\n"))),
+            ]
+        );
+        assert_eq!(tokens(&SYNTAX, "a = 1").unwrap_err(), "2:3: unexpected '='");
+        assert_eq!(
+            tokens(&SYNTAX, "99999999999999999999").unwrap_err(),
+            "2:1: number too large"
+        );
+        assert_eq!(
+            tokens(&SYNTAX, " \"a").unwrap_err(),
+            "2:2: unterminated string"
+        );
+    }
+
+    #[test]
+    fn cursors_report_where_they_stopped() {
+        let mut cursor = Cursor::new(&SYNTAX, tokens(&SYNTAX, "a < 1").unwrap(), (2, 6));
+        assert!(!cursor.accept("<"));
+        assert_eq!(cursor.next(), Some(Token::Identifier(String::from("a"))));
+        assert!(cursor.peek("<"));
+        assert_eq!(
+            cursor.expect("<=").unwrap_err().to_string(),
+            "2:3: expected '<='"
+        );
+        cursor.expect("<").unwrap();
+        assert_eq!(cursor.location(), (2, 5));
+        assert_eq!(cursor.next(), Some(Token::Int(1)));
+        assert!(cursor.at_end());
+        assert_eq!(cursor.location(), (2, 6));
+        assert_eq!(cursor.next(), None);
+    }
+}